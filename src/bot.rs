@@ -1,40 +1,476 @@
+use anyhow::anyhow;
 use futures::FutureExt;
-use std::panic::AssertUnwindSafe;
-use teloxide::{dispatching::UpdateHandler, prelude::*};
+use std::{net::SocketAddr, panic::AssertUnwindSafe, sync::Arc};
+use teloxide::{
+    dispatching::UpdateHandler,
+    prelude::*,
+    stop::StopToken,
+    update_listeners::{UpdateListener, webhooks},
+};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, instrument};
+use url::Url;
 
-use crate::utils::downcast_panic;
+use crate::{
+    config::BotConfig, health::Health, metrics::UptimeMetrics, tasks::TaskAccounting,
+    utils::downcast_panic,
+};
+use chat_membership::ActiveChats;
+use chat_settings::ChatSettingsStore;
+use maintenance::Maintenance;
+use me::SharedMe;
+use redirects::RedirectResolver;
+use replies::ReplyTracker;
+use reply_limiter::ReplyLimiter;
+use stats::StatsStore;
+use thank_react::ThankCooldown;
+use update_limiter::UpdateLimiter;
 
 type BotRequester = Bot;
 
+mod anchor;
+mod bulk_clean;
+mod chat_membership;
+mod chat_settings;
+mod commands;
+#[cfg(test)]
+mod fake_telegram;
+mod i18n;
+mod inline;
+mod maintenance;
+mod me;
+mod persistence;
+mod quiet_hours;
+mod redirects;
 mod remove_si;
+mod replies;
+mod reply_limiter;
+mod request_id;
+mod ruleset_refresh;
+pub(crate) mod stats;
 mod thank_react;
+mod update_limiter;
 
-#[instrument(skip_all)]
-pub async fn run_bot(token: String) {
+/// How the bot receives updates
+#[derive(Debug, Clone)]
+enum UpdateSource {
+    /// Requesting updates from Telegram
+    Polling,
+    /// Telegram sends the updates to `url`, which leads to the webhook served at `addr`
+    Webhook { addr: SocketAddr, url: Url },
+}
+
+/// Run the bot, receiving updates with long polling
+///
+/// Once `shutdown` is cancelled, no new updates are accepted
+/// and the bot returns after the ones in flight are handled
+///
+/// `health` is set while the dispatcher is running and unset while it restarts after a panic,
+/// `metrics` are counted as the updates are handled
+pub async fn run_bot(
+    token: String,
+    config: BotConfig,
+    tasks: TaskAccounting,
+    shutdown: CancellationToken,
+    health: Health,
+    metrics: UptimeMetrics,
+) -> anyhow::Result<()> {
+    run(
+        Bot::new(token),
+        config,
+        tasks,
+        shutdown,
+        health,
+        metrics,
+        UpdateSource::Polling,
+    )
+    .await
+}
+
+/// Run the bot, receiving updates through a webhook served at `addr`
+///
+/// `url` is the public url of the webhook Telegram sends the updates to,
+/// e.g. the address of a reverse proxy forwarding to `addr`,
+/// `shutdown`, `health` and `metrics` work the same as in [`run_bot`]
+#[allow(clippy::too_many_arguments)] // the same handles as run_bot and the webhook address and url
+pub async fn run_bot_webhook(
+    token: String,
+    config: BotConfig,
+    tasks: TaskAccounting,
+    shutdown: CancellationToken,
+    health: Health,
+    metrics: UptimeMetrics,
+    addr: SocketAddr,
+    url: Url,
+) -> anyhow::Result<()> {
+    run(
+        Bot::new(token),
+        config,
+        tasks,
+        shutdown,
+        health,
+        metrics,
+        UpdateSource::Webhook { addr, url },
+    )
+    .await
+}
+
+#[instrument(skip_all, fields(source = ?source))]
+async fn run(
+    bot: BotRequester,
+    config: BotConfig,
+    tasks: TaskAccounting,
+    shutdown: CancellationToken,
+    health: Health,
+    metrics: UptimeMetrics,
+    source: UpdateSource,
+) -> anyhow::Result<()> {
     info!("starting bot");
-    let bot = Bot::new(token);
+    let settings = ChatSettingsStore::load(config.chat_settings_path.clone())?;
+    let stats = StatsStore::load(config.stats_path.clone())?;
+    let active_chats = ActiveChats::load(config.active_chats_path.clone())?;
+    let stats_flusher = tasks.spawn_background(
+        stats
+            .clone()
+            .flush_periodically(config.stats_flush_interval),
+    );
+    let replies = ReplyTracker::default();
+    let reply_limiter = ReplyLimiter::new(config.replies_per_minute);
+    let thank_cooldown = ThankCooldown::default();
+    let resolver = RedirectResolver::new(config.redirect_timeout)?;
+    let maintenance = Maintenance::new(config.maintenance, config.maintenance_notice_interval);
+    let limiter = UpdateLimiter::new(config.update_limit);
+    let me = SharedMe::new(bot.get_me().await?);
+    let me_refresher = tasks.spawn_background(
+        me.clone()
+            .refresh_periodically(bot.clone(), config.me_refresh_interval),
+    );
+    let ruleset_refresher = config.ruleset_url.clone().map(|url| {
+        tasks.spawn_background(ruleset_refresh::refresh_periodically(
+            config.rulesets.clone(),
+            bot.clone(),
+            url,
+            config.ruleset_refresh_interval,
+        ))
+    });
+    let config = Arc::new(config);
 
-    loop {
+    #[cfg(feature = "systemd")]
+    let watchdog = start_watchdog(&tasks, health.clone());
+
+    let supervised = supervise(config.max_restarts, || {
         let mut dispatcher = Dispatcher::builder(bot.clone(), schema())
-            .enable_ctrlc_handler()
+            .dependencies(dptree::deps![
+                config.clone(),
+                settings.clone(),
+                stats.clone(),
+                metrics.clone(),
+                replies.clone(),
+                reply_limiter.clone(),
+                thank_cooldown.clone(),
+                active_chats.clone(),
+                maintenance.clone(),
+                me.clone(),
+                limiter.clone(),
+                tasks.clone(),
+                resolver.clone()
+            ])
             .default_handler(async |_| {}) // no-op update not to pollute the logs
             .build();
 
-        // catching panics from the dispatcher
-        let Err(e) = AssertUnwindSafe(dispatcher.dispatch()).catch_unwind().await else {
-            break;
-        };
+        // stopping the dispatcher once it has processed enough updates or on shutdown,
+        // it stops accepting updates and waits for the handlers in flight
+        let shutdown_token = dispatcher.shutdown_token();
+        let stop_watcher = tasks.spawn_background({
+            let limiter = limiter.clone();
+            let shutdown = shutdown.clone();
+            let tasks = tasks.clone();
+            async move {
+                tokio::select! {
+                    _ = limiter.exhausted() => info!("update limit reached, shutting down"),
+                    _ = shutdown.cancelled() => info!(
+                        in_flight = tasks.summary(true).in_flight_handlers,
+                        "shutdown requested, finishing the updates in flight"
+                    ),
+                }
+
+                if let Ok(shutdown) = shutdown_token.shutdown() {
+                    shutdown.await;
+                }
+            }
+        });
+
+        let health = health.clone();
+        let bot = bot.clone();
+        let source = source.clone();
+
+        let shutdown = shutdown.clone();
+
+        async move {
+            // aborting the watcher even if the dispatcher panics
+            let _abort_watcher = AbortOnDrop(stop_watcher);
+
+            // not restarting after a panic during the shutdown
+            if shutdown.is_cancelled() {
+                info!("shutdown requested, not starting the dispatcher");
+                return Ok(());
+            }
+
+            // the bot is known to Telegram by now, `get_me` succeeded above
+            health.set(true);
+
+            // marking the dispatcher unhealthy even if it panics, until it's restarted
+            let _unhealthy = UnhealthyOnDrop(health);
+
+            match source {
+                UpdateSource::Polling => dispatcher.dispatch().await,
+                UpdateSource::Webhook { addr, url } => {
+                    // the options are built for every start, the listener takes them
+                    let options = webhooks::Options::new(addr, url);
+                    info!(address = %options.address, url = %options.url, "setting the webhook");
+                    let mut listener = webhooks::axum(bot, options).await?;
+                    // stopping the server even if the dispatcher panics, so a restart can bind again
+                    let _stop_server = StopOnDrop(listener.stop_token());
+
+                    dispatcher
+                        .dispatch_with_listener(
+                            listener,
+                            LoggingErrorHandler::with_custom_text("webhook listener error"),
+                        )
+                        .await;
+                }
+            }
+
+            anyhow::Ok(())
+        }
+    })
+    .await;
+
+    info!(summary = %tasks.summary(true), "dispatcher stopped, cancelling background tasks");
+    stats_flusher.abort();
+    me_refresher.abort();
+    if let Some(ruleset_refresher) = ruleset_refresher {
+        ruleset_refresher.abort();
+    }
+    #[cfg(feature = "systemd")]
+    if let Some(watchdog) = watchdog {
+        watchdog.abort();
+    }
+    stats.flush().await?;
+
+    supervised.map(|_restarts| ())
+}
+
+/// Run the dispatcher returned by `dispatch` until it exits cleanly or with an error,
+/// catching its panics and restarting it at most `max_restarts` times
+///
+/// Returns how many times it was restarted, or an error once it panicked more times than allowed
+async fn supervise<F, Fut>(max_restarts: Option<usize>, mut dispatch: F) -> anyhow::Result<usize>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut restarts = 0;
+
+    loop {
+        match AssertUnwindSafe(dispatch()).catch_unwind().await {
+            Ok(Ok(())) => {
+                info!(restarts, "dispatcher exited cleanly");
+                return Ok(restarts);
+            }
+            Ok(Err(e)) => {
+                error!(restarts, error = %e, "dispatcher failed to start");
+                return Err(e);
+            }
+            Err(e) => {
+                let message = downcast_panic(&*e).unwrap_or_default();
+                error!(panic = message, "dispatcher panicked");
+
+                if max_restarts.is_some_and(|max_restarts| restarts >= max_restarts) {
+                    error!(restarts, "restart limit reached, giving up");
+                    return Err(anyhow!(
+                        "dispatcher panicked after {restarts} restart(s): {message}"
+                    ));
+                }
+
+                info!("restarting dispatcher");
+                restarts += 1;
+            }
+        }
+    }
+}
+
+/// Aborts the task when dropped
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Stops the update listener when dropped
+struct StopOnDrop(StopToken);
 
-        let message = downcast_panic(&*e).unwrap_or_default();
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+/// Marks the dispatcher unhealthy when dropped
+struct UnhealthyOnDrop(Health);
 
-        error!(panic = message, "dispatcher panicked");
-        info!("restaring dispatcher");
+impl Drop for UnhealthyOnDrop {
+    fn drop(&mut self) {
+        self.0.set(false);
     }
 }
 
+/// Notify systemd that the bot is ready and start pinging the watchdog if it's enabled
+#[cfg(feature = "systemd")]
+fn start_watchdog(tasks: &TaskAccounting, health: Health) -> Option<tokio::task::JoinHandle<()>> {
+    use crate::watchdog::{SystemdNotifier, keepalive_interval, notify_ready, run_keepalive};
+
+    let notifier = SystemdNotifier::from_env()?;
+    notify_ready(&notifier);
+
+    let interval = keepalive_interval()?;
+    info!(?interval, "pinging the systemd watchdog");
+    Some(tasks.spawn_background(run_keepalive(notifier, interval, health)))
+}
+
 fn schema() -> UpdateHandler<anyhow::Error> {
-    Update::filter_message()
+    let message_handler = Update::filter_message()
+        .branch(dptree::filter_map(commands::parse_command).endpoint(commands::handle_command))
+        .branch(
+            dptree::filter_map(commands::parse_command_error)
+                .endpoint(commands::handle_command_error),
+        )
+        .branch(dptree::filter(bulk_clean::bulk_clean_filter).endpoint(bulk_clean::bulk_clean))
         .branch(dptree::filter(thank_react::thank_react_filter).endpoint(thank_react::thank_react))
-        .endpoint(remove_si::remove_si)
+        .endpoint(remove_si::remove_si);
+
+    dptree::entry()
+        .inspect(|limiter: UpdateLimiter| limiter.register())
+        .map(|tasks: TaskAccounting| tasks.track_handler())
+        .branch(message_handler)
+        .branch(Update::filter_edited_message().endpoint(remove_si::remove_si_edited))
+        .branch(Update::filter_my_chat_member().endpoint(chat_membership::track_membership))
+        .branch(Update::filter_inline_query().endpoint(inline::answer_inline_query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake_telegram::{FakeTelegram, message};
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn always_panics() -> anyhow::Result<()> {
+        panic!("dispatcher failed")
+    }
+
+    #[tokio::test]
+    async fn single_update_mode_exits_after_one_update() -> anyhow::Result<()> {
+        let telegram = FakeTelegram::start().await?;
+        telegram.push_update(
+            "message",
+            message(
+                1,
+                json!({ "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce" }),
+            ),
+        );
+        let config = BotConfig {
+            update_limit: Some(1),
+            ..BotConfig::default()
+        };
+        let metrics = UptimeMetrics::default();
+
+        let bot = run(
+            telegram.bot(),
+            config,
+            TaskAccounting::default(),
+            CancellationToken::new(),
+            Health::default(),
+            metrics.clone(),
+            UpdateSource::Polling,
+        );
+        // without the limit the bot keeps polling for updates until it's shut down
+        tokio::time::timeout(Duration::from_secs(10), bot).await??;
+
+        assert_eq!(metrics.messages_processed(), 1);
+        let replies = telegram.requests_to("sendMessage");
+        assert_eq!(replies.len(), 1);
+        let text = replies[0]["text"].as_str().unwrap_or_default();
+        assert!(text.contains("https://youtu.be/0FwBHrVuMJc"), "{text}");
+        assert!(!text.contains("si="), "{text}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clean_exit_is_not_restarted() {
+        let mut runs = 0;
+
+        let restarts = supervise(None, || {
+            runs += 1;
+            async { anyhow::Ok(()) }
+        })
+        .await;
+
+        assert_eq!(restarts.ok(), Some(0));
+        assert_eq!(runs, 1);
+    }
+
+    #[tokio::test]
+    async fn panics_are_restarted_until_a_clean_exit() {
+        let mut runs = 0;
+
+        let restarts = supervise(Some(2), || {
+            runs += 1;
+            let should_panic = runs < 3;
+
+            async move {
+                if should_panic {
+                    panic!("dispatcher failed");
+                }
+
+                anyhow::Ok(())
+            }
+        })
+        .await;
+
+        assert_eq!(restarts.ok(), Some(2));
+        assert_eq!(runs, 3);
+    }
+
+    #[tokio::test]
+    async fn restart_limit_is_respected() {
+        let mut runs = 0;
+
+        let result = supervise(Some(3), || {
+            runs += 1;
+            always_panics()
+        })
+        .await;
+
+        assert!(result.is_err());
+        // the first run and 3 restarts
+        assert_eq!(runs, 4);
+    }
+
+    #[tokio::test]
+    async fn failing_to_start_is_not_restarted() {
+        let mut runs = 0;
+
+        let result = supervise(None, || {
+            runs += 1;
+            async { Err::<(), _>(anyhow!("address in use")) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(runs, 1);
+    }
 }
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use teloxide::{prelude::*, types::MessageId};
+use tracing::{info, warn};
+
+use super::{BotRequester, chat_settings::ChatSettingsStore};
+use crate::utils::FullErrorDisplay;
+
+const ANCHOR_TEXT: &str = "Tracking is stripped from YouTube links here, cleaned links are posted as replies to this message";
+
+/// The chats whose anchor is being created, so concurrent replies don't create one each
+///
+/// The entries are removed once nobody waits for them
+static CREATING_ANCHORS: LazyLock<Mutex<HashMap<ChatId, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Mutex::default);
+
+/// Which message a new reply of the bot should reply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyTarget {
+    /// The user's message with the tracked links
+    Message(MessageId),
+    /// The bot's anchor message in the chat
+    Anchor(MessageId),
+    /// The anchor message has to be created first
+    NewAnchor,
+}
+
+/// Decide where to reply, given whether reply threading is enabled and the chat's current anchor
+pub fn reply_target(
+    thread_replies: bool,
+    anchor: Option<MessageId>,
+    message: MessageId,
+) -> ReplyTarget {
+    match (thread_replies, anchor) {
+        (false, _) => ReplyTarget::Message(message),
+        (true, Some(anchor)) => ReplyTarget::Anchor(anchor),
+        (true, None) => ReplyTarget::NewAnchor,
+    }
+}
+
+/// The message a reply goes to, see [`resolve_reply_to`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyTo {
+    pub id: MessageId,
+    /// The anchor may have been deleted since, the reply should then go to the message
+    pub is_anchor: bool,
+}
+
+/// The message to reply to, creating and pinning the anchor message if needed
+pub async fn resolve_reply_to(
+    bot: &BotRequester,
+    settings: &ChatSettingsStore,
+    thread_replies: bool,
+    chat_id: ChatId,
+    message: MessageId,
+) -> anyhow::Result<ReplyTo> {
+    let anchor = current_anchor(settings, chat_id).await;
+
+    let (id, is_anchor) = match reply_target(thread_replies, anchor, message) {
+        ReplyTarget::Message(id) => (id, false),
+        ReplyTarget::Anchor(id) => (id, true),
+        ReplyTarget::NewAnchor => (create_anchor_once(bot, settings, chat_id).await?, true),
+    };
+
+    Ok(ReplyTo { id, is_anchor })
+}
+
+/// Forget the chat's anchor, e.g. because it was deleted, a new one is created for the next reply
+pub async fn forget_anchor(settings: &ChatSettingsStore, chat_id: ChatId) -> anyhow::Result<()> {
+    settings
+        .update(chat_id, |s| s.anchor_message_id = None)
+        .await
+}
+
+async fn current_anchor(settings: &ChatSettingsStore, chat_id: ChatId) -> Option<MessageId> {
+    settings.get(chat_id).await.anchor_message_id.map(MessageId)
+}
+
+/// Create the anchor unless a concurrent reply in the chat already did
+async fn create_anchor_once(
+    bot: &BotRequester,
+    settings: &ChatSettingsStore,
+    chat_id: ChatId,
+) -> anyhow::Result<MessageId> {
+    let lock = CREATING_ANCHORS
+        .lock()
+        .unwrap()
+        .entry(chat_id)
+        .or_default()
+        .clone();
+    let creating = lock.lock().await;
+
+    // created by the reply holding the lock before
+    let anchor = match current_anchor(settings, chat_id).await {
+        Some(anchor) => Ok(anchor),
+        None => create_anchor(bot, settings, chat_id).await,
+    };
+
+    {
+        let mut anchors = CREATING_ANCHORS.lock().unwrap();
+        // the one in the map and this one, the waiting replies hold the others
+        if Arc::strong_count(&lock) <= 2 {
+            anchors.remove(&chat_id);
+        }
+    }
+    drop(creating);
+
+    anchor
+}
+
+async fn create_anchor(
+    bot: &BotRequester,
+    settings: &ChatSettingsStore,
+    chat_id: ChatId,
+) -> anyhow::Result<MessageId> {
+    info!("creating the anchor message");
+    let anchor = bot.send_message(chat_id, ANCHOR_TEXT).await?.id;
+
+    // the bot may lack the rights to pin, the anchor is still usable then
+    if let Err(e) = bot
+        .pin_chat_message(chat_id, anchor)
+        .disable_notification(true)
+        .await
+    {
+        warn!(error = %FullErrorDisplay(e), "failed to pin the anchor message");
+    }
+
+    remember_anchor(settings, chat_id, anchor).await?;
+
+    Ok(anchor)
+}
+
+async fn remember_anchor(
+    settings: &ChatSettingsStore,
+    chat_id: ChatId,
+    anchor: MessageId,
+) -> anyhow::Result<()> {
+    settings
+        .update(chat_id, |s| s.anchor_message_id = Some(anchor.0))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::fake_telegram::FakeTelegram;
+
+    const MESSAGE: MessageId = MessageId(10);
+    const ANCHOR: MessageId = MessageId(1);
+
+    #[test]
+    fn replies_go_to_the_message_without_threading() {
+        assert_eq!(
+            reply_target(false, None, MESSAGE),
+            ReplyTarget::Message(MESSAGE)
+        );
+        assert_eq!(
+            reply_target(false, Some(ANCHOR), MESSAGE),
+            ReplyTarget::Message(MESSAGE)
+        );
+    }
+
+    #[test]
+    fn replies_go_to_the_anchor_with_threading() {
+        assert_eq!(
+            reply_target(true, Some(ANCHOR), MESSAGE),
+            ReplyTarget::Anchor(ANCHOR)
+        );
+    }
+
+    #[tokio::test]
+    async fn anchor_is_created_once_per_chat() -> anyhow::Result<()> {
+        let settings = ChatSettingsStore::default();
+        let chat = ChatId(1);
+        let other_chat = ChatId(2);
+
+        let anchor = settings.get(chat).await.anchor_message_id.map(MessageId);
+        assert_eq!(reply_target(true, anchor, MESSAGE), ReplyTarget::NewAnchor);
+
+        remember_anchor(&settings, chat, ANCHOR).await?;
+
+        let anchor = settings.get(chat).await.anchor_message_id.map(MessageId);
+        assert_eq!(
+            reply_target(true, anchor, MESSAGE),
+            ReplyTarget::Anchor(ANCHOR)
+        );
+
+        let other_anchor = settings
+            .get(other_chat)
+            .await
+            .anchor_message_id
+            .map(MessageId);
+        assert_eq!(
+            reply_target(true, other_anchor, MESSAGE),
+            ReplyTarget::NewAnchor
+        );
+
+        forget_anchor(&settings, chat).await?;
+        let anchor = settings.get(chat).await.anchor_message_id.map(MessageId);
+        assert_eq!(reply_target(true, anchor, MESSAGE), ReplyTarget::NewAnchor);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_replies_create_one_anchor() -> anyhow::Result<()> {
+        let telegram = FakeTelegram::start().await?;
+        let bot = telegram.bot();
+        let settings = ChatSettingsStore::default();
+        let chat = ChatId(-100);
+
+        let (first, second) = tokio::join!(
+            resolve_reply_to(&bot, &settings, true, chat, MessageId(10)),
+            resolve_reply_to(&bot, &settings, true, chat, MessageId(11)),
+        );
+        let (first, second) = (first?, second?);
+
+        assert!(first.is_anchor);
+        assert_eq!(first, second);
+        assert_eq!(telegram.requests_to("sendMessage").len(), 1);
+        assert_eq!(settings.get(chat).await.anchor_message_id, Some(first.id.0));
+        assert!(!CREATING_ANCHORS.lock().unwrap().contains_key(&chat));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use teloxide::{
+    dispatching::dialogue::GetChatId,
+    net::Download,
+    prelude::*,
+    sugar::request::RequestReplyExt,
+    types::{Document, InputFile},
+};
+use tracing::{info, instrument};
+
+use super::{BotRequester, remove_si::try_parse_url};
+use crate::{config::BotConfig, remove_si::url_without_si};
+
+const CLEANED_FILE_NAME: &str = "cleaned.txt";
+
+/// Documents sent to the bot in private chats are treated as lists of links to clean
+pub fn bulk_clean_filter(message: Message) -> bool {
+    message.chat.is_private() && message.document().is_some()
+}
+
+#[instrument(skip_all, err)]
+pub async fn bulk_clean(
+    bot: BotRequester,
+    message: Message,
+    config: Arc<BotConfig>,
+) -> anyhow::Result<()> {
+    let chat_id = message.chat_id().ok_or(anyhow!("failed to get chat id"))?;
+    let document = message
+        .document()
+        .ok_or(anyhow!("no document in message"))?;
+
+    if !is_text_document(document) {
+        info!(mime = ?document.mime_type, "ignoring a non-text document");
+        bot.send_message(
+            chat_id,
+            "Only plain text files with one link per line are supported",
+        )
+        .reply_to(message.id)
+        .await?;
+
+        return Ok(());
+    }
+
+    if document.file.size > config.max_document_size {
+        info!(
+            size = document.file.size,
+            "ignoring a document that is too large"
+        );
+        bot.send_message(
+            chat_id,
+            format!(
+                "The file is too large, the limit is {} KiB",
+                config.max_document_size / 1024
+            ),
+        )
+        .reply_to(message.id)
+        .await?;
+
+        return Ok(());
+    }
+
+    let file = bot.get_file(document.file.id.clone()).await?;
+    let mut contents = Vec::with_capacity(file.size as usize);
+    bot.download_file(&file.path, &mut contents).await?;
+
+    let Ok(text) = String::from_utf8(contents) else {
+        bot.send_message(chat_id, "The file is not valid UTF-8 text")
+            .reply_to(message.id)
+            .await?;
+
+        return Ok(());
+    };
+
+    info!(lines = text.lines().count(), "cleaning a document");
+    let cleaned = clean_lines(&text);
+
+    bot.send_document(
+        chat_id,
+        InputFile::memory(cleaned.into_bytes()).file_name(CLEANED_FILE_NAME),
+    )
+    .reply_to(message.id)
+    .await?;
+
+    Ok(())
+}
+
+fn is_text_document(document: &Document) -> bool {
+    let has_text_mime = document
+        .mime_type
+        .as_ref()
+        .is_some_and(|mime| mime.type_() == "text");
+    let has_txt_extension = document
+        .file_name
+        .as_ref()
+        .is_some_and(|name| name.to_ascii_lowercase().ends_with(".txt"));
+
+    has_text_mime || has_txt_extension
+}
+
+/// Clean every line of the text that is a tracked YouTube link, keeping other lines as is
+fn clean_lines(text: &str) -> String {
+    let mut cleaned = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        let cleaned_url = (!trimmed.is_empty())
+            .then(|| try_parse_url(trimmed))
+            .flatten()
+            .and_then(url_without_si);
+
+        match cleaned_url {
+            Some(url) => cleaned.push_str(url.as_str()),
+            None => cleaned.push_str(line),
+        }
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleaning_a_file_line_by_line() {
+        let file = "\
+https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce
+  https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up
+
+https://www.youtube.com/watch?v=nFuAJl46w_w
+https://example.org/meow?si=23
+just some notes
+";
+
+        let expected = "\
+https://youtu.be/0FwBHrVuMJc
+https://www.youtube.com/watch?v=3foYyPDp0Ho
+
+https://www.youtube.com/watch?v=nFuAJl46w_w
+https://example.org/meow?si=23
+just some notes
+";
+
+        assert_eq!(clean_lines(file), expected);
+    }
+
+    #[test]
+    fn cleaning_handles_crlf_line_endings() {
+        assert_eq!(
+            clean_lines("https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173\r\n"),
+            "https://youtu.be/FiwMTquj-rQ?t=173\n"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use teloxide::types::ChatMemberUpdated;
+use tokio::sync::RwLock;
+use tracing::{info, instrument};
+
+use super::persistence::{load_json, save_json};
+
+/// The chats the bot is currently a member of, optionally persisted to a JSON file
+#[derive(Debug, Clone, Default)]
+pub struct ActiveChats {
+    chats: Arc<RwLock<HashSet<i64>>>,
+    path: Option<PathBuf>,
+}
+
+impl ActiveChats {
+    /// Load the set from the JSON file at `path`, or start empty if the file doesn't exist yet
+    ///
+    /// If no path is given, the set is only kept in memory
+    #[instrument]
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let chats = match &path {
+            Some(path) => load_json(path)?,
+            None => HashSet::new(),
+        };
+
+        Ok(Self {
+            chats: Arc::new(RwLock::new(chats)),
+            path,
+        })
+    }
+
+    pub async fn count(&self) -> usize {
+        self.chats.read().await.len()
+    }
+
+    /// Add or remove the chat depending on the bot's new status in it, and persist the change
+    pub async fn apply(&self, update: &ChatMemberUpdated) -> anyhow::Result<()> {
+        let chat_id = update.chat.id;
+        let mut chats = self.chats.write().await;
+
+        let changed = if update.new_chat_member.is_present() {
+            chats.insert(chat_id.0)
+        } else {
+            chats.remove(&chat_id.0)
+        };
+
+        if !changed {
+            return Ok(());
+        }
+
+        // holding the lock while saving so concurrent updates don't overwrite each other
+        match &self.path {
+            Some(path) => save_json(path, &*chats).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keeps track of the chats the bot is added to or removed from
+#[instrument(skip_all, err)]
+pub async fn track_membership(
+    update: ChatMemberUpdated,
+    active_chats: ActiveChats,
+) -> anyhow::Result<()> {
+    info!(
+        chat_id = %update.chat.id,
+        old_status = ?update.old_chat_member.status(),
+        new_status = ?update.new_chat_member.status(),
+        "bot membership changed"
+    );
+
+    active_chats.apply(&update).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use teloxide::types::ChatId;
+
+    const CHAT: ChatId = ChatId(-100123);
+
+    /// A `my_chat_member` update moving the bot from `old` to `new` status
+    fn member_update(old: serde_json::Value, new: serde_json::Value) -> ChatMemberUpdated {
+        let bot = json!({ "id": 2, "is_bot": true, "first_name": "Bot", "username": "bot" });
+        let mut old_member = json!({ "user": bot });
+        let mut new_member = json!({ "user": bot });
+        old_member
+            .as_object_mut()
+            .unwrap()
+            .extend(old.as_object().unwrap().clone());
+        new_member
+            .as_object_mut()
+            .unwrap()
+            .extend(new.as_object().unwrap().clone());
+
+        serde_json::from_value(json!({
+            "chat": { "id": CHAT.0, "type": "supergroup", "title": "Test" },
+            "from": { "id": 1, "is_bot": false, "first_name": "Test" },
+            "date": 0,
+            "old_chat_member": old_member,
+            "new_chat_member": new_member,
+        }))
+        .unwrap()
+    }
+
+    async fn contains(chats: &ActiveChats, chat_id: ChatId) -> bool {
+        chats.chats.read().await.contains(&chat_id.0)
+    }
+
+    fn left() -> serde_json::Value {
+        json!({ "status": "left" })
+    }
+
+    fn member() -> serde_json::Value {
+        json!({ "status": "member" })
+    }
+
+    fn kicked() -> serde_json::Value {
+        json!({ "status": "kicked", "until_date": 0 })
+    }
+
+    fn administrator() -> serde_json::Value {
+        json!({
+            "status": "administrator",
+            "can_be_edited": false,
+            "is_anonymous": false,
+            "can_manage_chat": true,
+            "can_delete_messages": true,
+            "can_manage_video_chats": false,
+            "can_restrict_members": false,
+            "can_promote_members": false,
+            "can_change_info": false,
+            "can_invite_users": true,
+            "can_post_stories": false,
+            "can_edit_stories": false,
+            "can_delete_stories": false,
+        })
+    }
+
+    #[tokio::test]
+    async fn joining_and_leaving() -> anyhow::Result<()> {
+        let chats = ActiveChats::default();
+
+        chats.apply(&member_update(left(), member())).await?;
+        assert!(contains(&chats, CHAT).await);
+
+        chats.apply(&member_update(member(), left())).await?;
+        assert!(!contains(&chats, CHAT).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kicked_chats_are_removed() -> anyhow::Result<()> {
+        let chats = ActiveChats::default();
+
+        chats.apply(&member_update(left(), administrator())).await?;
+        assert_eq!(chats.count().await, 1);
+
+        chats
+            .apply(&member_update(administrator(), kicked()))
+            .await?;
+        assert_eq!(chats.count().await, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn promotion_keeps_the_chat() -> anyhow::Result<()> {
+        let chats = ActiveChats::default();
+
+        chats.apply(&member_update(left(), member())).await?;
+        chats
+            .apply(&member_update(member(), administrator()))
+            .await?;
+        assert!(contains(&chats, CHAT).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restricted_member_is_present_only_while_a_member() -> anyhow::Result<()> {
+        let chats = ActiveChats::default();
+        let restricted = |is_member: bool| {
+            json!({
+                "status": "restricted",
+                "until_date": 0,
+                "is_member": is_member,
+                "can_send_messages": true,
+                "can_send_audios": true,
+                "can_send_documents": true,
+                "can_send_photos": true,
+                "can_send_videos": true,
+                "can_send_video_notes": true,
+                "can_send_voice_notes": true,
+                "can_send_polls": true,
+                "can_send_other_messages": true,
+                "can_add_web_page_previews": true,
+                "can_change_info": false,
+                "can_invite_users": false,
+                "can_pin_messages": false,
+                "can_manage_topics": false,
+            })
+        };
+
+        chats
+            .apply(&member_update(left(), restricted(true)))
+            .await?;
+        assert!(contains(&chats, CHAT).await);
+
+        chats
+            .apply(&member_update(restricted(true), restricted(false)))
+            .await?;
+        assert!(!contains(&chats, CHAT).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn active_chats_persistence_round_trip() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "youtube_no_si_{}_active_chats.json",
+            std::process::id()
+        ));
+
+        let chats = ActiveChats::load(Some(path.clone()))?;
+        chats.apply(&member_update(left(), member())).await?;
+
+        let reloaded = ActiveChats::load(Some(path.clone()));
+        std::fs::remove_file(&path)?;
+
+        assert!(contains(&reloaded?, CHAT).await);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use super::{
+    persistence::{load_json, save_json},
+    quiet_hours::QuietHours,
+};
+use crate::{clock::SharedClock, config::ConfirmationMode, remove_si::is_plausible_host};
+
+/// Settings that chat admins can change at runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Overrides the globally configured confirmation mode
+    pub confirmation_mode: Option<ConfirmationMode>,
+    /// Unix timestamp in seconds until which the bot ignores the chat
+    pub paused_until: Option<u64>,
+    /// The bot's message its replies are threaded under, if reply threading is enabled
+    pub anchor_message_id: Option<i32>,
+    /// Text put before the bot's replies, e.g. an emoji
+    pub reply_prefix: Option<String>,
+    /// The daily window in which the bot ignores the chat
+    pub quiet_hours: Option<QuietHours>,
+    /// Overrides the globally configured privacy frontend host
+    pub frontend_host: Option<String>,
+    /// The bot ignores the chat until enabled again
+    pub disabled: bool,
+}
+
+impl ChatSettings {
+    /// Pause the bot in the chat for `duration` starting from `now`
+    pub fn pause(&mut self, now: SystemTime, duration: Duration) {
+        self.paused_until = Some(unix_secs(now).saturating_add(duration.as_secs()));
+    }
+
+    pub fn resume(&mut self) {
+        self.paused_until = None;
+    }
+
+    pub fn is_paused(&self, now: SystemTime) -> bool {
+        self.paused_until
+            .is_some_and(|until| unix_secs(now) < until)
+    }
+
+    /// Whether the bot ignores the chat at `now`, because it's disabled, paused or in the quiet hours
+    pub fn is_muted(&self, now: SystemTime) -> bool {
+        self.disabled
+            || self.is_paused(now)
+            || self.quiet_hours.is_some_and(|quiet| quiet.contains(now))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Per-chat settings shared between handlers, optionally persisted to a JSON file
+#[derive(Debug, Clone, Default)]
+pub struct ChatSettingsStore {
+    settings: Arc<RwLock<HashMap<i64, ChatSettings>>>,
+    path: Option<PathBuf>,
+    clock: SharedClock,
+}
+
+impl ChatSettingsStore {
+    /// Load the store from the JSON file at `path`, or start empty if the file doesn't exist yet
+    ///
+    /// If no path is given, the settings are only kept in memory
+    #[instrument]
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let settings = match &path {
+            Some(path) => load_json(path)?,
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            settings: Arc::new(RwLock::new(settings)),
+            path,
+            clock: SharedClock::default(),
+        })
+    }
+
+    pub async fn get(&self, chat_id: ChatId) -> ChatSettings {
+        self.settings
+            .read()
+            .await
+            .get(&chat_id.0)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Modify the settings of a chat and persist the change
+    pub async fn update(
+        &self,
+        chat_id: ChatId,
+        f: impl FnOnce(&mut ChatSettings),
+    ) -> anyhow::Result<()> {
+        let mut settings = self.settings.write().await;
+        f(settings.entry(chat_id.0).or_default());
+
+        // holding the lock while saving so concurrent updates don't overwrite each other
+        self.save(&settings).await
+    }
+
+    /// The confirmation mode for the chat, falling back to `default` if the chat didn't override it
+    pub async fn confirmation_mode(
+        &self,
+        chat_id: ChatId,
+        default: ConfirmationMode,
+    ) -> ConfirmationMode {
+        self.get(chat_id).await.confirmation_mode.unwrap_or(default)
+    }
+
+    /// The frontend host links are rewritten to in the chat,
+    /// falling back to `default` if the chat didn't override it
+    ///
+    /// Links are not rewritten if the chat's host is invalid, e.g. after editing the settings file
+    pub async fn frontend_host(&self, chat_id: ChatId, default: Option<&str>) -> Option<String> {
+        match self.get(chat_id).await.frontend_host {
+            Some(host) => is_plausible_host(&host).then_some(host),
+            None => default.map(ToOwned::to_owned),
+        }
+    }
+
+    /// Pause the bot in the chat for `duration` starting from now
+    pub async fn pause(&self, chat_id: ChatId, duration: Duration) -> anyhow::Result<()> {
+        let now = self.clock.system_now();
+        self.update(chat_id, |s| s.pause(now, duration)).await
+    }
+
+    pub async fn is_muted(&self, chat_id: ChatId) -> bool {
+        self.get(chat_id).await.is_muted(self.clock.system_now())
+    }
+
+    async fn save(&self, settings: &HashMap<i64, ChatSettings>) -> anyhow::Result<()> {
+        match &self.path {
+            Some(path) => save_json(path, settings).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    #[tokio::test]
+    async fn chat_mode_falls_back_to_default() -> anyhow::Result<()> {
+        let store = ChatSettingsStore::default();
+        let chat = ChatId(42);
+        let other_chat = ChatId(43);
+
+        store
+            .update(chat, |s| {
+                s.confirmation_mode = Some(ConfirmationMode::Silent)
+            })
+            .await?;
+
+        assert_eq!(
+            store.confirmation_mode(chat, ConfirmationMode::Reply).await,
+            ConfirmationMode::Silent
+        );
+        assert_eq!(
+            store
+                .confirmation_mode(other_chat, ConfirmationMode::Reaction)
+                .await,
+            ConfirmationMode::Reaction
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_frontend_overrides_the_default() -> anyhow::Result<()> {
+        let store = ChatSettingsStore::default();
+        let chat = ChatId(42);
+        let other_chat = ChatId(43);
+        let broken_chat = ChatId(44);
+
+        store
+            .update(chat, |s| {
+                s.frontend_host = Some("piped.example.org".to_owned())
+            })
+            .await?;
+        store
+            .update(broken_chat, |s| {
+                s.frontend_host = Some("not a host".to_owned())
+            })
+            .await?;
+
+        assert_eq!(
+            store.frontend_host(chat, Some("yewtu.be")).await.as_deref(),
+            Some("piped.example.org")
+        );
+        assert_eq!(
+            store.frontend_host(chat, None).await.as_deref(),
+            Some("piped.example.org")
+        );
+        assert_eq!(
+            store
+                .frontend_host(other_chat, Some("yewtu.be"))
+                .await
+                .as_deref(),
+            Some("yewtu.be")
+        );
+        assert_eq!(store.frontend_host(other_chat, None).await, None);
+        assert_eq!(
+            store.frontend_host(broken_chat, Some("yewtu.be")).await,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pause_window_expires() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut settings = ChatSettings::default();
+        assert!(!settings.is_paused(now));
+
+        settings.pause(now, Duration::from_secs(10 * 60));
+        assert!(settings.is_paused(now));
+        assert!(settings.is_paused(now + Duration::from_secs(10 * 60 - 1)));
+        assert!(!settings.is_paused(now + Duration::from_secs(10 * 60)));
+        assert!(!settings.is_paused(now + Duration::from_secs(60 * 60)));
+    }
+
+    #[test]
+    fn resuming_clears_the_pause() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut settings = ChatSettings::default();
+
+        settings.pause(now, Duration::from_secs(60));
+        settings.resume();
+        assert!(!settings.is_paused(now));
+    }
+
+    #[test]
+    fn quiet_hours_mute_the_chat() {
+        let midnight = UNIX_EPOCH + Duration::from_secs(24 * 60 * 60);
+        let mut settings = ChatSettings {
+            quiet_hours: Some("22:00-07:00".parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert!(settings.is_muted(midnight));
+        assert!(!settings.is_muted(midnight + Duration::from_secs(12 * 60 * 60)));
+
+        settings.quiet_hours = None;
+        assert!(!settings.is_muted(midnight));
+    }
+
+    #[tokio::test]
+    async fn disabled_chats_stay_muted() -> anyhow::Result<()> {
+        let store = ChatSettingsStore::default();
+        let chat = ChatId(42);
+
+        store.update(chat, |s| s.disabled = true).await?;
+        assert!(store.is_muted(chat).await);
+        // resuming only ends a pause
+        store.update(chat, |s| s.resume()).await?;
+        assert!(store.is_muted(chat).await);
+        assert!(!store.is_muted(ChatId(43)).await);
+
+        store.update(chat, |s| s.disabled = false).await?;
+        assert!(!store.is_muted(chat).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pause_expires_with_the_clock() -> anyhow::Result<()> {
+        let clock = Arc::new(FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+        let store = ChatSettingsStore {
+            clock: SharedClock::new(clock.clone()),
+            ..Default::default()
+        };
+        let chat = ChatId(42);
+
+        store.pause(chat, Duration::from_secs(60)).await?;
+        assert!(store.is_muted(chat).await);
+
+        clock.advance(Duration::from_secs(60));
+        assert!(!store.is_muted(chat).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn settings_persistence_round_trip() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "youtube_no_si_{}_chat_settings.json",
+            std::process::id()
+        ));
+        let chat = ChatId(-100123);
+
+        let store = ChatSettingsStore::load(Some(path.clone()))?;
+        store
+            .update(chat, |s| {
+                s.confirmation_mode = Some(ConfirmationMode::Reaction)
+            })
+            .await?;
+
+        let reloaded = ChatSettingsStore::load(Some(path.clone()));
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            reloaded?.get(chat).await,
+            ChatSettings {
+                confirmation_mode: Some(ConfirmationMode::Reaction),
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,650 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use teloxide::{
+    dispatching::dialogue::GetChatId,
+    prelude::*,
+    sugar::request::RequestReplyExt,
+    types::InputFile,
+    utils::command::{BotCommands, ParseError},
+};
+use tracing::{info, instrument};
+use url::Url;
+
+use super::{
+    BotRequester, UptimeMetrics,
+    chat_membership::ActiveChats,
+    chat_settings::ChatSettingsStore,
+    maintenance::Maintenance,
+    me::SharedMe,
+    quiet_hours::QuietHours,
+    remove_si::cleaned_urls,
+    stats::{ChatStats, StatsStore},
+    thank_react::THANK_EMOJI,
+};
+use crate::{
+    config::{BotConfig, ConfirmationMode},
+    remove_si::{RULESETS, is_plausible_host},
+};
+
+const EXPORT_FILE_NAME: &str = "stats.json";
+/// Longest reply prefix in characters, so the prefix doesn't drown out the links
+const MAX_REPLY_PREFIX_LEN: usize = 32;
+
+#[derive(BotCommands, Debug, Clone, PartialEq, Eq)]
+#[command(rename_rule = "lowercase")]
+pub enum Command {
+    #[command(description = "explain what the bot does")]
+    Start,
+    #[command(description = "show this help")]
+    Help,
+    #[command(description = "switch how the bot responds in this chat: reply, reaction or silent")]
+    Mode(ConfirmationMode),
+    #[command(description = "show how many links were cleaned in this chat")]
+    Stats,
+    #[command(description = "stop cleaning links in this chat for the given number of minutes")]
+    Pause(u64),
+    #[command(description = "resume cleaning links in this chat")]
+    Resume,
+    #[command(description = "stop cleaning links in this chat until enabled again")]
+    Disable,
+    #[command(description = "start cleaning links in this chat again after disabling")]
+    Enable,
+    #[command(description = "export the stats of this chat as a JSON file")]
+    Export,
+    #[command(description = "bot operators only: turn the maintenance mode on or off")]
+    Maintenance(Toggle),
+    #[command(description = "set the text put before replies in this chat, empty to remove it")]
+    SetPrefix(ReplyPrefix),
+    #[command(
+        description = "don't clean links daily in the given hours, e.g. 22:00-07:00 UTC+2, or off"
+    )]
+    Quiet(QuietSetting),
+    #[command(description = "reply with the given links without tracking")]
+    Clean(String),
+    #[command(
+        description = "rewrite YouTube links in this chat to the given frontend host, empty for the default"
+    )]
+    Frontend(FrontendSetting),
+}
+
+/// The argument of commands switching something on or off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+impl FromStr for Toggle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            other => Err(format!("expected `on` or `off`, got `{other}`")),
+        }
+    }
+}
+
+/// The argument of the command setting the reply prefix, None removes the prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyPrefix(pub Option<String>);
+
+impl FromStr for ReplyPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let len = s.chars().count();
+
+        if len > MAX_REPLY_PREFIX_LEN {
+            return Err(format!(
+                "the prefix can be at most {MAX_REPLY_PREFIX_LEN} characters long, got {len}"
+            ));
+        }
+
+        Ok(Self((!s.is_empty()).then(|| s.to_owned())))
+    }
+}
+
+/// The argument of the command setting the quiet hours, None turns them off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietSetting(pub Option<QuietHours>);
+
+impl FromStr for QuietSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("off") {
+            return Ok(Self(None));
+        }
+
+        s.parse().map(|quiet| Self(Some(quiet)))
+    }
+}
+
+/// The argument of the command setting the frontend host, None goes back to the default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontendSetting(pub Option<String>);
+
+impl FromStr for FrontendSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let host = s.trim().to_ascii_lowercase();
+
+        if host.is_empty() {
+            return Ok(Self(None));
+        }
+
+        if !is_plausible_host(&host) {
+            return Err(format!("`{host}` is not a valid host, e.g. yewtu.be"));
+        }
+
+        Ok(Self(Some(host)))
+    }
+}
+
+impl Command {
+    fn requires_admin(&self) -> bool {
+        match self {
+            Self::Mode(_)
+            | Self::Pause(_)
+            | Self::Resume
+            | Self::Disable
+            | Self::Enable
+            | Self::Export
+            | Self::SetPrefix(_)
+            | Self::Quiet(_)
+            | Self::Frontend(_) => true,
+            Self::Start | Self::Help | Self::Clean(_) => false,
+            // checked against the operators from the config instead
+            Self::Stats | Self::Maintenance(_) => false,
+        }
+    }
+}
+
+/// Parse a command from the message, using the current username of the bot for mentions
+///
+/// Commands take precedence over cleaning, links in them are only handled by the command
+pub fn parse_command(message: Message, me: SharedMe) -> Option<Command> {
+    let me = me.get();
+    Command::parse(message.text()?, me.username()).ok()
+}
+
+/// The error of a command for this bot with invalid arguments, e.g. `/pause soon`
+///
+/// Commands of other bots are not ours to answer, so their messages are cleaned as usual
+pub fn parse_command_error(message: Message, me: SharedMe) -> Option<InvalidCommand> {
+    let me = me.get();
+    own_command_error(message.text()?, me.username())
+}
+
+/// Why a command for this bot failed to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCommand(pub String);
+
+fn own_command_error(text: &str, bot_name: &str) -> Option<InvalidCommand> {
+    match Command::parse(text, bot_name) {
+        Ok(_) | Err(ParseError::UnknownCommand(_) | ParseError::WrongBotName(_)) => None,
+        Err(e) => Some(InvalidCommand(e.to_string())),
+    }
+}
+
+/// Tell the user the command was invalid instead of cleaning the links in it
+#[instrument(skip_all, err)]
+pub async fn handle_command_error(
+    bot: BotRequester,
+    message: Message,
+    InvalidCommand(error): InvalidCommand,
+) -> anyhow::Result<()> {
+    info!(error, "invalid command");
+
+    bot.send_message(
+        message.chat.id,
+        format!("{error}\nSee /help for the commands"),
+    )
+    .reply_to(message.id)
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all, err)]
+#[allow(clippy::too_many_arguments)] // the dependencies are injected by dptree
+pub async fn handle_command(
+    bot: BotRequester,
+    message: Message,
+    command: Command,
+    config: Arc<BotConfig>,
+    settings: ChatSettingsStore,
+    stats: StatsStore,
+    metrics: UptimeMetrics,
+    active_chats: ActiveChats,
+    maintenance: Maintenance,
+) -> anyhow::Result<()> {
+    let chat_id = message.chat_id().ok_or(anyhow!("failed to get chat id"))?;
+
+    if command.requires_admin() && !is_admin(&bot, &message).await? {
+        info!(?command, "non-admin tried to use an admin command");
+        bot.send_message(chat_id, "Only chat admins can use this command")
+            .reply_to(message.id)
+            .await?;
+
+        return Ok(());
+    }
+
+    let response = match command {
+        Command::Start | Command::Help => help_text(),
+        Command::Mode(mode) => {
+            settings
+                .update(chat_id, |s| s.confirmation_mode = Some(mode))
+                .await?;
+            info!(?mode, "confirmation mode changed");
+
+            match mode {
+                ConfirmationMode::Reply => "I will reply with the links without tracking",
+                ConfirmationMode::Reaction => "I will react to messages with tracked links",
+                ConfirmationMode::Silent => "I will stay silent",
+            }
+            .to_owned()
+        }
+        Command::Stats => {
+            let mut response = format_stats("In this chat", stats.chat(chat_id));
+
+            if is_operator(&config, &message) {
+                response.push('\n');
+                response.push_str(&format_stats("In all chats", stats.global()));
+                response.push('\n');
+                response.push_str(&format_stats("Since startup", metrics.get()));
+                response.push_str(&format!("\nActive chats: {}", active_chats.count().await));
+            }
+
+            response
+        }
+        Command::Pause(minutes) => {
+            settings
+                .pause(chat_id, Duration::from_secs(minutes.saturating_mul(60)))
+                .await?;
+            info!(minutes, "paused in chat");
+
+            format!("Paused for {minutes} minutes, use /resume to resume earlier")
+        }
+        Command::Resume => {
+            settings.update(chat_id, |s| s.resume()).await?;
+            info!("resumed in chat");
+
+            "Resumed cleaning links".to_owned()
+        }
+        Command::Disable => {
+            settings.update(chat_id, |s| s.disabled = true).await?;
+            info!("disabled in chat");
+
+            "Stopped cleaning links in this chat, use /enable to start again".to_owned()
+        }
+        Command::Enable => {
+            settings.update(chat_id, |s| s.disabled = false).await?;
+            info!("enabled in chat");
+
+            "Cleaning links in this chat again".to_owned()
+        }
+        Command::Export => {
+            // operators get the stats of all chats
+            let scope = (!is_operator(&config, &message)).then_some(chat_id);
+
+            match stats.export(scope) {
+                Some(export) => {
+                    info!(chats = export.chats.len(), "exporting stats");
+                    bot.send_document(
+                        chat_id,
+                        InputFile::memory(serde_json::to_vec_pretty(&export)?)
+                            .file_name(EXPORT_FILE_NAME),
+                    )
+                    .reply_to(message.id)
+                    .await?;
+
+                    return Ok(());
+                }
+                None => "No stats to export yet".to_owned(),
+            }
+        }
+        Command::Maintenance(_) if !is_operator(&config, &message) => {
+            info!("non-operator tried to toggle maintenance");
+            "Only bot operators can use this command".to_owned()
+        }
+        Command::Maintenance(toggle) => {
+            maintenance.set_enabled(toggle == Toggle::On);
+            info!(?toggle, "maintenance toggled");
+
+            match toggle {
+                Toggle::On => "Maintenance mode is on, links are not cleaned",
+                Toggle::Off => "Maintenance mode is off, cleaning links again",
+            }
+            .to_owned()
+        }
+        Command::SetPrefix(ReplyPrefix(prefix)) => {
+            let response = match &prefix {
+                Some(prefix) => format!("Replies will start with: {prefix}"),
+                None => "Removed the reply prefix".to_owned(),
+            };
+
+            settings
+                .update(chat_id, |s| s.reply_prefix = prefix)
+                .await?;
+            info!("reply prefix changed");
+
+            response
+        }
+        Command::Frontend(FrontendSetting(host)) => {
+            let response = match &host {
+                Some(host) => format!("YouTube links will be rewritten to {host}"),
+                None => "Using the default frontend again".to_owned(),
+            };
+
+            settings.update(chat_id, |s| s.frontend_host = host).await?;
+            info!("frontend host changed");
+
+            response
+        }
+        Command::Clean(_) => {
+            let frontend_host = settings
+                .frontend_host(chat_id, config.frontend_host.as_deref())
+                .await;
+            let urls = cleaned_urls(&message, &config, frontend_host.as_deref());
+            stats.record(chat_id, urls.len());
+            metrics.record(urls.len());
+            info!(urls = urls.len(), "cleaning links from the command");
+
+            clean_command_response(&urls)
+        }
+        Command::Quiet(QuietSetting(quiet_hours)) => {
+            settings
+                .update(chat_id, |s| s.quiet_hours = quiet_hours)
+                .await?;
+            info!(?quiet_hours, "quiet hours changed");
+
+            match quiet_hours {
+                Some(quiet_hours) => format!("I won't clean links daily during {quiet_hours}"),
+                None => "Quiet hours are off".to_owned(),
+            }
+        }
+    };
+
+    bot.send_message(chat_id, response)
+        .reply_to(message.id)
+        .await?;
+
+    Ok(())
+}
+
+/// The cleaned links, one per line
+fn clean_command_response(urls: &[Url]) -> String {
+    if urls.is_empty() {
+        return "No links with tracking found".to_owned();
+    }
+
+    urls.iter().map(Url::as_str).collect::<Vec<_>>().join("\n")
+}
+
+/// What the bot strips from which links, and the list of commands
+fn help_text() -> String {
+    let mut text = "I remove tracking parameters from the links in messages \
+        and reply with the links without them.\n\nParameters I strip:\n"
+        .to_owned();
+
+    for ruleset in RULESETS {
+        text.push_str(&format!(
+            "{}: {}\n",
+            ruleset.name,
+            ruleset.params.join(", ")
+        ));
+    }
+
+    text.push_str(&format!(
+        "\nReply to one of my messages and I'll react with {THANK_EMOJI}\n\n{}",
+        Command::descriptions()
+    ));
+
+    text
+}
+
+fn format_stats(title: &str, stats: ChatStats) -> String {
+    format!(
+        "{title}:\nMessages processed: {}\nLinks cleaned: {}\n",
+        stats.messages_processed, stats.urls_cleaned
+    )
+}
+
+/// Whether the sender of the message is one of the bot operators from the config
+fn is_operator(config: &BotConfig, message: &Message) -> bool {
+    message
+        .from
+        .as_ref()
+        .is_some_and(|user| config.admin_user_ids.contains(&user.id.0))
+}
+
+/// Whether the sender of the message is an admin of the chat
+///
+/// Everyone is an admin of their private chat with the bot
+async fn is_admin(bot: &BotRequester, message: &Message) -> anyhow::Result<bool> {
+    if message.chat.is_private() {
+        return Ok(true);
+    }
+
+    let Some(user) = &message.from else {
+        return Ok(false);
+    };
+
+    let member = bot.get_chat_member(message.chat.id, user.id).await?;
+
+    Ok(member.is_privileged())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_mode_command() {
+        assert_eq!(
+            Command::parse("/mode reaction", "test_bot").ok(),
+            Some(Command::Mode(ConfirmationMode::Reaction))
+        );
+        assert_eq!(
+            Command::parse("/mode@test_bot silent", "test_bot").ok(),
+            Some(Command::Mode(ConfirmationMode::Silent))
+        );
+        assert_eq!(
+            Command::parse("/mode Reply", "test_bot").ok(),
+            Some(Command::Mode(ConfirmationMode::Reply))
+        );
+        assert!(Command::parse("/mode loud", "test_bot").is_err());
+        assert!(Command::parse("/mode", "test_bot").is_err());
+    }
+
+    #[test]
+    fn parsing_pause_commands() {
+        assert_eq!(
+            Command::parse("/pause 30", "test_bot").ok(),
+            Some(Command::Pause(30))
+        );
+        assert_eq!(
+            Command::parse("/resume", "test_bot").ok(),
+            Some(Command::Resume)
+        );
+        assert!(Command::parse("/pause soon", "test_bot").is_err());
+    }
+
+    #[test]
+    fn parsing_enable_commands() {
+        assert_eq!(
+            Command::parse("/disable", "test_bot").ok(),
+            Some(Command::Disable)
+        );
+        assert_eq!(
+            Command::parse("/enable@test_bot", "test_bot").ok(),
+            Some(Command::Enable)
+        );
+        assert!(Command::Disable.requires_admin());
+        assert!(Command::Enable.requires_admin());
+    }
+
+    #[test]
+    fn parsing_maintenance_command() {
+        assert_eq!(
+            Command::parse("/maintenance on", "test_bot").ok(),
+            Some(Command::Maintenance(Toggle::On))
+        );
+        assert_eq!(
+            Command::parse("/maintenance OFF", "test_bot").ok(),
+            Some(Command::Maintenance(Toggle::Off))
+        );
+        assert!(Command::parse("/maintenance maybe", "test_bot").is_err());
+    }
+
+    #[test]
+    fn parsing_setprefix_command() {
+        assert_eq!(
+            Command::parse("/setprefix 🧹 Cleaned:", "test_bot").ok(),
+            Some(Command::SetPrefix(ReplyPrefix(Some(
+                "🧹 Cleaned:".to_owned()
+            ))))
+        );
+        assert_eq!(
+            Command::parse("/setprefix", "test_bot").ok(),
+            Some(Command::SetPrefix(ReplyPrefix(None)))
+        );
+
+        let too_long = "🧹".repeat(MAX_REPLY_PREFIX_LEN + 1);
+        assert!(Command::parse(&format!("/setprefix {too_long}"), "test_bot").is_err());
+
+        let longest = "🧹".repeat(MAX_REPLY_PREFIX_LEN);
+        assert!(Command::parse(&format!("/setprefix {longest}"), "test_bot").is_ok());
+    }
+
+    #[test]
+    fn parsing_quiet_command() {
+        assert_eq!(
+            Command::parse("/quiet 22:00-07:00 UTC+2", "test_bot").ok(),
+            Some(Command::Quiet(QuietSetting(Some(QuietHours {
+                start: 22 * 60,
+                end: 7 * 60,
+                utc_offset: 2 * 60,
+            }))))
+        );
+        assert_eq!(
+            Command::parse("/quiet off", "test_bot").ok(),
+            Some(Command::Quiet(QuietSetting(None)))
+        );
+        assert!(Command::parse("/quiet", "test_bot").is_err());
+        assert!(Command::parse("/quiet at night", "test_bot").is_err());
+    }
+
+    #[test]
+    fn parsing_frontend_command() {
+        assert_eq!(
+            Command::parse("/frontend Yewtu.be", "test_bot").ok(),
+            Some(Command::Frontend(FrontendSetting(Some(
+                "yewtu.be".to_owned()
+            ))))
+        );
+        assert_eq!(
+            Command::parse("/frontend", "test_bot").ok(),
+            Some(Command::Frontend(FrontendSetting(None)))
+        );
+        assert!(Command::parse("/frontend https://yewtu.be", "test_bot").is_err());
+        assert!(Command::parse("/frontend not a host", "test_bot").is_err());
+    }
+
+    #[test]
+    fn parsing_help_commands() {
+        assert_eq!(
+            Command::parse("/start", "test_bot").ok(),
+            Some(Command::Start)
+        );
+        assert_eq!(
+            Command::parse("/help@test_bot", "test_bot").ok(),
+            Some(Command::Help)
+        );
+    }
+
+    #[test]
+    fn command_links_are_cleaned_by_the_command_only() -> anyhow::Result<()> {
+        let text = "/clean https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        let message: Message = serde_json::from_value(serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "Test" },
+            "from": { "id": 1, "is_bot": false, "first_name": "Test" },
+            "text": text,
+            "entities": [
+                { "type": "bot_command", "offset": 0, "length": 6 },
+                { "type": "url", "offset": 7, "length": text.len() - 7 },
+            ],
+        }))?;
+
+        // the command branch goes first, so the cleaning endpoint never sees the message
+        assert!(matches!(
+            Command::parse(text, "test_bot"),
+            Ok(Command::Clean(_))
+        ));
+        assert_eq!(own_command_error(text, "test_bot"), None);
+
+        let urls = cleaned_urls(&message, &BotConfig::default(), None);
+        assert_eq!(
+            clean_command_response(&urls),
+            "https://youtu.be/FiwMTquj-rQ"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_own_commands_are_not_cleaned() {
+        let error = own_command_error("/pause https://youtu.be/FiwMTquj-rQ?si=abc", "test_bot");
+        assert!(error.is_some());
+
+        // other bots' commands are cleaned as any other message
+        for text in [
+            "/share https://youtu.be/FiwMTquj-rQ?si=abc",
+            "/clean@other_bot https://youtu.be/FiwMTquj-rQ?si=abc",
+            "https://youtu.be/FiwMTquj-rQ?si=abc",
+        ] {
+            assert!(own_command_error(text, "test_bot").is_none(), "{text}");
+        }
+    }
+
+    #[test]
+    fn clean_command_without_tracked_links() {
+        assert_eq!(clean_command_response(&[]), "No links with tracking found");
+    }
+
+    #[test]
+    fn help_lists_stripped_params_and_commands() {
+        let help = help_text();
+
+        for ruleset in RULESETS {
+            for param in ruleset.params {
+                assert!(help.contains(param), "{param} is missing from {help}");
+            }
+        }
+
+        assert!(help.contains(THANK_EMOJI));
+        assert!(help.contains("/mode"));
+        assert!(help.contains("/quiet"));
+    }
+
+    #[test]
+    fn formatting_stats() {
+        let stats = ChatStats {
+            messages_processed: 10,
+            urls_cleaned: 4,
+        };
+
+        assert_eq!(
+            format_stats("In this chat", stats),
+            "In this chat:\nMessages processed: 10\nLinks cleaned: 4\n"
+        );
+    }
+}
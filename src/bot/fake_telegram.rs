@@ -0,0 +1,276 @@
+//! A fake Telegram Bot API for driving the real handlers and the dispatcher in the tests
+//!
+//! A [`Bot`] pointed at it with [`FakeTelegram::bot`] sends its requests to a local HTTP server,
+//! which records them and answers with made-up but well-formed results
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::{Value, json};
+use teloxide::Bot;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use url::Url;
+
+const TOKEN: &str = "123456:fake-token";
+/// The user id of the bot
+pub const BOT_ID: u64 = 123456;
+/// How long `getUpdates` waits before answering that there are no updates,
+/// so the polling doesn't spin
+const EMPTY_POLL_DELAY: Duration = Duration::from_millis(10);
+
+/// A request the bot sent
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The method name, e.g. `sendMessage`
+    pub method: String,
+    pub body: Value,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requests: Vec<Request>,
+    updates: VecDeque<Value>,
+    /// Responses answering the next requests to a method instead of the made-up results
+    responses: HashMap<String, VecDeque<Value>>,
+    last_update_id: u64,
+    last_message_id: i64,
+}
+
+/// The fake API server, stopped when dropped
+#[derive(Debug)]
+pub struct FakeTelegram {
+    url: Url,
+    state: Arc<Mutex<State>>,
+    server: JoinHandle<()>,
+}
+
+impl Drop for FakeTelegram {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl FakeTelegram {
+    pub async fn start() -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        let state = Arc::new(Mutex::new(State {
+            // the ids of the messages sent by the bot don't collide with the ones in the tests
+            last_message_id: 1000,
+            ..State::default()
+        }));
+
+        let server = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, state.clone()));
+                }
+            }
+        });
+
+        Ok(Self { url, state, server })
+    }
+
+    /// A bot sending its requests to this server
+    pub fn bot(&self) -> Bot {
+        Bot::new(TOKEN).set_api_url(self.url.clone())
+    }
+
+    /// Queue an update with a message, the update id is filled in
+    ///
+    /// `kind` is the field of the update, e.g. `message` or `edited_message`
+    pub fn push_update(&self, kind: &str, message: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.last_update_id += 1;
+        let mut update = json!({ "update_id": state.last_update_id });
+        update[kind] = message;
+        state.updates.push_back(update);
+    }
+
+    /// Answer the next request to `method` with `response` instead of the made-up result,
+    /// e.g. `{"ok": false, "error_code": 400, "description": "..."}` to fail it
+    pub fn respond_once(&self, method: &str, response: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .entry(method.to_ascii_lowercase())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// All the requests received so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The bodies of the requests to `method`, in order
+    pub fn requests_to(&self, method: &str) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.method.eq_ignore_ascii_case(method))
+            .map(|request| request.body)
+            .collect()
+    }
+}
+
+/// A message in a private chat with a user, `content` is merged into it
+pub fn message(message_id: i64, content: Value) -> Value {
+    let mut message = json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": { "id": 1, "type": "private", "first_name": "Test" },
+        "from": { "id": 1, "is_bot": false, "first_name": "Test" },
+    });
+
+    message
+        .as_object_mut()
+        .expect("a message is an object")
+        .extend(
+            content
+                .as_object()
+                .expect("the content is an object")
+                .clone(),
+        );
+
+    message
+}
+
+fn bot_user() -> Value {
+    json!({
+        "id": BOT_ID,
+        "is_bot": true,
+        "first_name": "Fake",
+        "username": "fake_bot",
+        "can_join_groups": true,
+        "can_read_all_group_messages": false,
+        "supports_inline_queries": true,
+        "can_connect_to_business": false,
+        "has_main_web_app": false,
+    })
+}
+
+/// A chat with the id, private for positive ids and a supergroup otherwise
+fn chat(id: i64) -> Value {
+    if id > 0 {
+        json!({ "id": id, "type": "private", "first_name": "Test" })
+    } else {
+        json!({ "id": id, "type": "supergroup", "title": "Test" })
+    }
+}
+
+/// The made-up result of the request
+fn result_for(state: &mut State, method: &str, body: &Value) -> Option<Value> {
+    let chat_id = body["chat_id"].as_i64().unwrap_or(1);
+
+    let result = match method {
+        "getme" => bot_user(),
+        "getupdates" => json!(state.updates.pop_front().into_iter().collect::<Vec<_>>()),
+        "sendmessage" => {
+            state.last_message_id += 1;
+            json!({
+                "message_id": state.last_message_id,
+                "date": 0,
+                "chat": chat(chat_id),
+                "from": bot_user(),
+                "text": body["text"],
+            })
+        }
+        "editmessagetext" => json!({
+            "message_id": body["message_id"],
+            "date": 0,
+            "edit_date": 0,
+            "chat": chat(chat_id),
+            "from": bot_user(),
+            "text": body["text"],
+        }),
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) -> anyhow::Result<()> {
+    let (path, body) = read_request(&mut stream).await?;
+    let method = path.rsplit('/').next().unwrap_or_default().to_owned();
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let key = method.to_ascii_lowercase();
+
+    let (response, no_updates) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            method,
+            body: body.clone(),
+        });
+
+        let canned = state.responses.get_mut(&key).and_then(VecDeque::pop_front);
+        let no_updates = key == "getupdates" && state.updates.is_empty();
+        let response = canned.unwrap_or_else(|| {
+            // methods returning True, e.g. deleteMessage, get it by default
+            let result = result_for(&mut state, &key, &body).unwrap_or(json!(true));
+            json!({ "ok": true, "result": result })
+        });
+
+        (response, no_updates)
+    };
+
+    if no_updates {
+        tokio::time::sleep(EMPTY_POLL_DELAY).await;
+    }
+
+    let response = response.to_string();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// The path and the body of an HTTP request
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+
+        let mut chunk = [0; 4096];
+        let len = stream.read(&mut chunk).await?;
+        anyhow::ensure!(len > 0, "connection closed before the request ended");
+        buf.extend_from_slice(&chunk[..len]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let path = head
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_owned();
+    let content_len = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+
+    let mut body = buf.split_off(head_end);
+    while body.len() < content_len {
+        let mut chunk = [0; 4096];
+        let len = stream.read(&mut chunk).await?;
+        anyhow::ensure!(len > 0, "connection closed before the body ended");
+        body.extend_from_slice(&chunk[..len]);
+    }
+
+    Ok((path, body))
+}
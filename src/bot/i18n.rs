@@ -0,0 +1,83 @@
+/// A language the bot's replies are translated to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+}
+
+impl Lang {
+    /// The language for an IETF language tag, e.g. the `language_code` of a Telegram user,
+    /// English for missing and untranslated languages
+    pub fn from_code(code: Option<&str>) -> Self {
+        let primary = code
+            .and_then(|code| code.split(['-', '_']).next())
+            .unwrap_or_default();
+
+        match primary.to_ascii_lowercase().as_str() {
+            "de" => Self::De,
+            _ => Self::En,
+        }
+    }
+}
+
+/// The translatable texts of the bot's messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// The line before the cleaned links of a reply
+    LinkHeader { plural: bool },
+}
+
+pub fn localized(lang: Lang, message: Message) -> &'static str {
+    match (lang, message) {
+        (Lang::En, Message::LinkHeader { plural: false }) => "The link without tracking:",
+        (Lang::En, Message::LinkHeader { plural: true }) => "The links without tracking:",
+        (Lang::De, Message::LinkHeader { plural: false }) => "Der Link ohne Tracking:",
+        (Lang::De, Message::LinkHeader { plural: true }) => "Die Links ohne Tracking:",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_picked_by_the_primary_subtag() {
+        assert_eq!(Lang::from_code(Some("de")), Lang::De);
+        assert_eq!(Lang::from_code(Some("de-AT")), Lang::De);
+        assert_eq!(Lang::from_code(Some("DE")), Lang::De);
+        assert_eq!(Lang::from_code(Some("en")), Lang::En);
+        assert_eq!(Lang::from_code(Some("en-GB")), Lang::En);
+    }
+
+    #[test]
+    fn missing_and_untranslated_languages_fall_back_to_english() {
+        for code in [None, Some(""), Some("fr"), Some("pt-br"), Some("deu")] {
+            assert_eq!(Lang::from_code(code), Lang::En, "{code:?}");
+        }
+
+        assert_eq!(
+            localized(
+                Lang::from_code(Some("ja")),
+                Message::LinkHeader { plural: false }
+            ),
+            "The link without tracking:"
+        );
+    }
+
+    #[test]
+    fn headers_are_pluralized_in_every_language() {
+        for lang in [Lang::En, Lang::De] {
+            assert_ne!(
+                localized(lang, Message::LinkHeader { plural: false }),
+                localized(lang, Message::LinkHeader { plural: true }),
+                "{lang:?}"
+            );
+        }
+
+        assert_eq!(
+            localized(Lang::De, Message::LinkHeader { plural: true }),
+            "Die Links ohne Tracking:"
+        );
+    }
+}
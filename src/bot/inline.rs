@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{
+        InlineQueryResult, InlineQueryResultArticle, InputMessageContent, InputMessageContentText,
+    },
+};
+use tracing::{debug, instrument};
+use url::Url;
+
+use super::{
+    BotRequester,
+    remove_si::{looks_like_url, try_parse_url},
+};
+use crate::{config::BotConfig, transform::UrlTransform};
+
+/// How long Telegram may cache the answer to the same query, the cleaning never changes
+const INLINE_CACHE_TIME_SECS: u32 = 60 * 60;
+
+/// Answer an inline query (`@bot <link>`) with the link without tracking,
+/// so links can be cleaned in chats the bot isn't a member of
+///
+/// Requires the inline mode to be enabled for the bot with @BotFather
+#[instrument(skip_all, err)]
+pub async fn answer_inline_query(
+    bot: BotRequester,
+    query: InlineQuery,
+    config: Arc<BotConfig>,
+) -> anyhow::Result<()> {
+    let results = inline_results(&query.query, &config);
+    debug!(results = results.len(), "answering the inline query");
+
+    bot.answer_inline_query(query.id, results)
+        .cache_time(INLINE_CACHE_TIME_SECS)
+        .await?;
+
+    Ok(())
+}
+
+/// A single result sending the cleaned link, or the link as is if there's nothing to clean
+///
+/// No results for empty queries and anything but a single link
+fn inline_results(query: &str, config: &BotConfig) -> Vec<InlineQueryResult> {
+    let Some(url) = parse_query(query) else {
+        return Vec::new();
+    };
+
+    let (title, link) = match config.rulesets.stripper().apply(url.clone()) {
+        Some(cleaned) => ("Send the link without tracking", cleaned),
+        None => ("Nothing to clean, send the link as is", url),
+    };
+
+    let content = InputMessageContent::Text(InputMessageContentText::new(link.as_str()));
+    let article =
+        InlineQueryResultArticle::new("cleaned", title, content).description(link.as_str());
+
+    vec![InlineQueryResult::Article(article)]
+}
+
+fn parse_query(query: &str) -> Option<Url> {
+    let mut tokens = query.split_whitespace();
+    let token = tokens.next().filter(|_| tokens.next().is_none())?;
+
+    looks_like_url(token)
+        .then(|| try_parse_url(token))
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text the only result sends
+    fn sent_text(results: &[InlineQueryResult]) -> Option<&str> {
+        match results {
+            [InlineQueryResult::Article(article)] => match &article.input_message_content {
+                InputMessageContent::Text(content) => Some(&content.message_text),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn links_with_tracking_are_cleaned() {
+        let config = BotConfig::default();
+
+        let results = inline_results(
+            " https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173 ",
+            &config,
+        );
+        assert_eq!(
+            sent_text(&results),
+            Some("https://youtu.be/FiwMTquj-rQ?t=173")
+        );
+
+        let results = inline_results("youtube.com/watch?v=3foYyPDp0Ho&si=abc", &config);
+        assert_eq!(
+            sent_text(&results),
+            Some("https://youtube.com/watch?v=3foYyPDp0Ho")
+        );
+    }
+
+    #[test]
+    fn clean_links_are_sent_as_is() {
+        let results = inline_results("https://youtu.be/FiwMTquj-rQ", &BotConfig::default());
+
+        assert_eq!(sent_text(&results), Some("https://youtu.be/FiwMTquj-rQ"));
+    }
+
+    #[test]
+    fn anything_but_a_link_has_no_results() {
+        for query in [
+            "",
+            "   ",
+            "hello",
+            "cat videos",
+            "https://youtu.be/FiwMTquj-rQ?si=abc and more",
+            "mailto:someone@example.org",
+        ] {
+            assert!(
+                inline_results(query, &BotConfig::default()).is_empty(),
+                "{query}"
+            );
+        }
+    }
+}
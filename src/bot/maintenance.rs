@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::clock::SharedClock;
+
+/// What to do with a message with respect to maintenance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intercept {
+    /// Not in maintenance, clean the links as usual
+    Proceed,
+    /// Reply with the maintenance notice instead of cleaning
+    Notify,
+    /// In maintenance, but the chat was notified recently or there's nothing to clean
+    Ignore,
+}
+
+/// Whether the bot is in maintenance, replying with a notice instead of cleaning links
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    inner: Arc<MaintenanceInner>,
+}
+
+#[derive(Debug)]
+struct MaintenanceInner {
+    enabled: AtomicBool,
+    /// When the notice was last sent to each chat
+    last_notices: Mutex<HashMap<i64, Instant>>,
+    notice_interval: Duration,
+    clock: SharedClock,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool, notice_interval: Duration) -> Self {
+        Self::with_clock(enabled, notice_interval, SharedClock::default())
+    }
+
+    pub fn with_clock(enabled: bool, notice_interval: Duration, clock: SharedClock) -> Self {
+        Self {
+            inner: Arc::new(MaintenanceInner {
+                enabled: AtomicBool::new(enabled),
+                last_notices: Mutex::default(),
+                notice_interval,
+                clock,
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.enabled.store(enabled, Ordering::Release);
+
+        if !enabled {
+            // the next maintenance notifies every chat again
+            self.inner.last_notices.lock().unwrap().clear();
+        }
+    }
+
+    /// Decide whether a message with or without tracked links should be cleaned
+    pub fn intercept(&self, chat_id: i64, has_links: bool) -> Intercept {
+        self.intercept_at(chat_id, has_links, self.inner.clock.now())
+    }
+
+    fn intercept_at(&self, chat_id: i64, has_links: bool, now: Instant) -> Intercept {
+        if !self.is_enabled() {
+            Intercept::Proceed
+        } else if has_links && self.should_notify_at(chat_id, now) {
+            Intercept::Notify
+        } else {
+            Intercept::Ignore
+        }
+    }
+
+    /// Whether the maintenance notice should be sent to the chat now,
+    /// at most once per notice interval
+    fn should_notify_at(&self, chat_id: i64, now: Instant) -> bool {
+        let mut last_notices = self.inner.last_notices.lock().unwrap();
+
+        if last_notices
+            .get(&chat_id)
+            .is_some_and(|last| now.duration_since(*last) < self.inner.notice_interval)
+        {
+            return false;
+        }
+
+        last_notices.insert(chat_id, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    const INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    #[test]
+    fn toggling_maintenance() {
+        let maintenance = Maintenance::new(false, INTERVAL);
+        assert!(!maintenance.is_enabled());
+
+        maintenance.set_enabled(true);
+        assert!(maintenance.is_enabled());
+        // clones share the state
+        assert!(maintenance.clone().is_enabled());
+
+        maintenance.set_enabled(false);
+        assert!(!maintenance.is_enabled());
+    }
+
+    #[test]
+    fn maintenance_short_circuits_cleaning() {
+        let maintenance = Maintenance::new(false, INTERVAL);
+        let now = Instant::now();
+        assert_eq!(maintenance.intercept_at(1, true, now), Intercept::Proceed);
+
+        maintenance.set_enabled(true);
+        // messages without links don't trigger the notice
+        assert_eq!(maintenance.intercept_at(1, false, now), Intercept::Ignore);
+        assert_eq!(maintenance.intercept_at(1, true, now), Intercept::Notify);
+        assert_eq!(maintenance.intercept_at(1, true, now), Intercept::Ignore);
+    }
+
+    #[test]
+    fn notices_are_rate_limited_per_chat() {
+        let maintenance = Maintenance::new(true, INTERVAL);
+        let now = Instant::now();
+
+        assert!(maintenance.should_notify_at(1, now));
+        assert!(!maintenance.should_notify_at(1, now + INTERVAL / 2));
+        assert!(maintenance.should_notify_at(2, now + INTERVAL / 2));
+        assert!(maintenance.should_notify_at(1, now + INTERVAL));
+    }
+
+    #[test]
+    fn notice_interval_follows_the_clock() {
+        let clock = Arc::new(FakeClock::default());
+        let maintenance = Maintenance::with_clock(true, INTERVAL, SharedClock::new(clock.clone()));
+
+        assert_eq!(maintenance.intercept(1, true), Intercept::Notify);
+
+        clock.advance(INTERVAL - Duration::from_secs(1));
+        assert_eq!(maintenance.intercept(1, true), Intercept::Ignore);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(maintenance.intercept(1, true), Intercept::Notify);
+    }
+
+    #[test]
+    fn ending_maintenance_resets_the_rate_limit() {
+        let maintenance = Maintenance::new(true, INTERVAL);
+        let now = Instant::now();
+
+        assert!(maintenance.should_notify_at(1, now));
+
+        maintenance.set_enabled(false);
+        maintenance.set_enabled(true);
+        assert!(maintenance.should_notify_at(1, now));
+    }
+}
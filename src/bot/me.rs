@@ -0,0 +1,50 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use teloxide::{prelude::*, types::Me};
+use tracing::{debug, info, warn};
+
+use super::BotRequester;
+use crate::utils::FullErrorDisplay;
+
+/// The bot's own user, periodically refreshed so renaming the bot doesn't leave it stale
+#[derive(Debug, Clone)]
+pub struct SharedMe(Arc<RwLock<Me>>);
+
+impl SharedMe {
+    pub fn new(me: Me) -> Self {
+        Self(Arc::new(RwLock::new(me)))
+    }
+
+    pub fn get(&self) -> Me {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, me: Me) {
+        *self.0.write().unwrap() = me;
+    }
+
+    /// Refresh the user every `interval`, never returns
+    pub async fn refresh_periodically(self, bot: BotRequester, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        // the first tick completes immediately, and we just fetched the user on startup
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            match bot.get_me().await {
+                Ok(me) => {
+                    if me.username() != self.get().username() {
+                        info!(username = me.username(), "bot username changed");
+                    }
+                    debug!("refreshed the bot user");
+                    self.set(me);
+                }
+                Err(e) => warn!(error = %FullErrorDisplay(e), "failed to refresh the bot user"),
+            }
+        }
+    }
+}
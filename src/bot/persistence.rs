@@ -0,0 +1,41 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::{debug, info};
+
+/// Load a value from the JSON file at `path`, or the default value if the file doesn't exist yet
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> anyhow::Result<T> {
+    match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            info!(path = %path.display(), "file not found, starting from scratch");
+            Ok(T::default())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Save a value to the JSON file at `path`
+///
+/// The value is written to a temporary file next to `path` first and then moved over it,
+/// so a crash while saving leaves the previous contents intact
+pub async fn save_json<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    debug!(path = %path.display(), "saving to file");
+    let contents = serde_json::to_vec_pretty(value)?;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    tokio::fs::write(&temp_path, contents)
+        .await
+        .with_context(|| format!("failed to write {}", temp_path.display()))?;
+    tokio::fs::rename(&temp_path, path)
+        .await
+        .with_context(|| format!("failed to replace {}", path.display()))
+}
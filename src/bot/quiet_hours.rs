@@ -0,0 +1,249 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+/// The furthest UTC offsets in use, UTC-12:00 and UTC+14:00
+const MIN_UTC_OFFSET: i16 = -12 * 60;
+const MAX_UTC_OFFSET: i16 = 14 * 60;
+
+/// A daily window in which the bot doesn't clean links or react in a chat
+///
+/// The window may cross midnight, e.g. `22:00-07:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// Minutes since the local midnight the window starts at, inclusive
+    pub start: u16,
+    /// Minutes since the local midnight the window ends at, exclusive
+    pub end: u16,
+    /// The offset of the chat's timezone from UTC in minutes
+    pub utc_offset: i16,
+}
+
+impl QuietHours {
+    pub fn contains(&self, now: SystemTime) -> bool {
+        self.contains_minute(self.local_minute_of_day(now))
+    }
+
+    fn contains_minute(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            // crossing midnight
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    fn local_minute_of_day(&self, now: SystemTime) -> u16 {
+        let utc_minutes = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 60;
+        let local_minutes = utc_minutes as i64 + i64::from(self.utc_offset);
+
+        local_minutes.rem_euclid(MINUTES_PER_DAY) as u16
+    }
+}
+
+/// Parses `HH:MM-HH:MM`, optionally followed by the timezone as `UTC`, `UTC+2` or `UTC-05:30`
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let window = parts
+            .next()
+            .ok_or("expected the quiet hours as `HH:MM-HH:MM`")?;
+        let utc_offset = parts.next().map(parse_utc_offset).transpose()?;
+
+        if parts.next().is_some() {
+            return Err("expected the quiet hours and optionally the timezone".to_owned());
+        }
+
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("expected the quiet hours as `HH:MM-HH:MM`, got `{window}`"))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+
+        if start == end {
+            return Err("the quiet hours must not start and end at the same time".to_owned());
+        }
+
+        Ok(Self {
+            start,
+            end,
+            utc_offset: utc_offset.unwrap_or(0),
+        })
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.utc_offset < 0 { '-' } else { '+' };
+        let offset = self.utc_offset.unsigned_abs();
+
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02} UTC{sign}{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+            offset / 60,
+            offset % 60,
+        )
+    }
+}
+
+/// Minutes since midnight of a `HH:MM` time
+fn parse_time(s: &str) -> Result<u16, String> {
+    let invalid = || format!("expected a time as `HH:MM`, got `{s}`");
+
+    let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+
+    if hours >= 24 || minutes >= 60 {
+        return Err(invalid());
+    }
+
+    Ok(hours * 60 + minutes)
+}
+
+/// The offset in minutes of `UTC`, `UTC+2`, `UTC-05:30` and the like
+fn parse_utc_offset(s: &str) -> Result<i16, String> {
+    let invalid = || format!("expected the timezone as `UTC+HH:MM`, got `{s}`");
+
+    let offset = s
+        .get(..3)
+        .filter(|utc| utc.eq_ignore_ascii_case("utc"))
+        .map_or(s, |_| &s[3..]);
+
+    if offset.is_empty() {
+        return Ok(0);
+    }
+
+    let (sign, offset) = match offset.split_at_checked(1) {
+        Some(("+", offset)) => (1, offset),
+        Some(("-", offset)) => (-1, offset),
+        _ => return Err(invalid()),
+    };
+
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+    let hours: i16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i16 = minutes.parse().map_err(|_| invalid())?;
+
+    if hours > MAX_UTC_OFFSET / 60 || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    let offset = sign * (hours * 60 + minutes);
+    if !(MIN_UTC_OFFSET..=MAX_UTC_OFFSET).contains(&offset) {
+        return Err(invalid());
+    }
+
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A time on the first day of the unix epoch, which started at UTC midnight
+    fn utc_time(hours: u64, minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(hours * 60 * 60 + minutes * 60)
+    }
+
+    #[test]
+    fn parsing_quiet_hours() {
+        assert_eq!(
+            "22:00-07:00".parse(),
+            Ok(QuietHours {
+                start: 22 * 60,
+                end: 7 * 60,
+                utc_offset: 0,
+            })
+        );
+        assert_eq!(
+            "09:30-17:45 UTC+2".parse(),
+            Ok(QuietHours {
+                start: 9 * 60 + 30,
+                end: 17 * 60 + 45,
+                utc_offset: 2 * 60,
+            })
+        );
+        assert_eq!(
+            "0:00-6:00 utc-05:30"
+                .parse::<QuietHours>()
+                .map(|q| q.utc_offset),
+            Ok(-(5 * 60 + 30))
+        );
+
+        for invalid in [
+            "",
+            "22:00",
+            "22-07",
+            "24:00-07:00",
+            "22:60-07:00",
+            "07:00-07:00",
+            "22:00-07:00 Europe/Berlin",
+            "22:00-07:00 UTC+15",
+            "22:00-07:00 UTC+9999",
+            "22:00-07:00 UTC+2 extra",
+        ] {
+            assert!(invalid.parse::<QuietHours>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn displaying_quiet_hours() {
+        let quiet: QuietHours = "22:00-07:00 UTC-05:30".parse().unwrap();
+        assert_eq!(quiet.to_string(), "22:00-07:00 UTC-05:30");
+        assert_eq!(quiet.to_string().parse(), Ok(quiet));
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let quiet: QuietHours = "09:00-17:00".parse().unwrap();
+
+        assert!(!quiet.contains(utc_time(8, 59)));
+        assert!(quiet.contains(utc_time(9, 0)));
+        assert!(quiet.contains(utc_time(16, 59)));
+        assert!(!quiet.contains(utc_time(17, 0)));
+        assert!(!quiet.contains(utc_time(23, 0)));
+    }
+
+    #[test]
+    fn window_crossing_midnight() {
+        let quiet: QuietHours = "22:00-07:00".parse().unwrap();
+
+        assert!(!quiet.contains(utc_time(21, 59)));
+        assert!(quiet.contains(utc_time(22, 0)));
+        assert!(quiet.contains(utc_time(23, 59)));
+        assert!(quiet.contains(utc_time(24, 0)));
+        assert!(quiet.contains(utc_time(24 + 6, 59)));
+        assert!(!quiet.contains(utc_time(24 + 7, 0)));
+        assert!(!quiet.contains(utc_time(24 + 12, 0)));
+    }
+
+    #[test]
+    fn window_is_in_the_chat_timezone() {
+        let quiet: QuietHours = "22:00-07:00 UTC+3".parse().unwrap();
+
+        // 22:00 in UTC+3
+        assert!(!quiet.contains(utc_time(18, 59)));
+        assert!(quiet.contains(utc_time(19, 0)));
+        // 07:00 in UTC+3
+        assert!(quiet.contains(utc_time(24 + 3, 59)));
+        assert!(!quiet.contains(utc_time(24 + 4, 0)));
+
+        // the local day starts before the UTC one for negative offsets
+        let quiet: QuietHours = "22:00-07:00 UTC-2".parse().unwrap();
+        assert!(quiet.contains(utc_time(0, 0)));
+        assert!(quiet.contains(utc_time(8, 59)));
+        assert!(!quiet.contains(utc_time(9, 0)));
+    }
+}
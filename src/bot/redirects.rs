@@ -0,0 +1,349 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{
+    Client, StatusCode,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::LOCATION,
+    redirect::Policy,
+};
+use thiserror::Error;
+use tracing::{debug, instrument};
+use url::{Host, Url};
+
+/// The most redirects followed for a single link
+pub const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("more than {MAX_REDIRECTS} redirects")]
+    TooManyRedirects,
+    #[error("`{0}` is not a public http(s) address")]
+    NotPublic(Url),
+    #[error("`{0}` resolves to addresses that are not public")]
+    NotPublicHost(String),
+    #[error("invalid redirect location: {0}")]
+    InvalidLocation(String),
+    #[error("following the redirects took longer than {0:?}")]
+    TimedOut(Duration),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+/// Follows the redirects of links to find where they lead,
+/// never connecting to private or loopback addresses
+#[derive(Debug, Clone)]
+pub struct RedirectResolver {
+    client: Client,
+    /// How long following the redirects of a link may take in total
+    timeout: Duration,
+    /// Only unset in the tests, which follow redirects on a local server
+    public_only: bool,
+}
+
+impl RedirectResolver {
+    pub fn new(timeout: Duration) -> anyhow::Result<Self> {
+        // redirects are followed by hand to check every address on the way,
+        // a proxy would resolve the hosts itself
+        let client = Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicOnlyResolver))
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self {
+            client,
+            timeout,
+            public_only: true,
+        })
+    }
+
+    /// A resolver following redirects to any address, for the tests
+    #[cfg(test)]
+    pub fn allowing_local(timeout: Duration) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self {
+            client,
+            timeout,
+            public_only: false,
+        })
+    }
+
+    /// The url the link finally redirects to, or the link itself if it doesn't redirect
+    ///
+    /// Gives up if the whole chain takes longer than the timeout
+    #[instrument(skip(self), fields(%url))]
+    pub async fn resolve(&self, url: Url) -> Result<Url, ResolveError> {
+        tokio::time::timeout(self.timeout, self.follow(url))
+            .await
+            .map_err(|_| ResolveError::TimedOut(self.timeout))?
+    }
+
+    async fn follow(&self, mut url: Url) -> Result<Url, ResolveError> {
+        for _ in 0..=MAX_REDIRECTS {
+            if self.public_only {
+                ensure_public(&url)?;
+            }
+
+            let response = self.client.head(url.clone()).send().await?;
+            let Some(next) = redirect_target(&url, response.status(), response.headers())? else {
+                return Ok(url);
+            };
+
+            debug!(from = %url, to = %next, "following the redirect");
+            url = next;
+        }
+
+        Err(ResolveError::TooManyRedirects)
+    }
+}
+
+/// Where the response redirects to, None if it's not a redirect
+fn redirect_target(
+    url: &Url,
+    status: StatusCode,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<Option<Url>, ResolveError> {
+    if !status.is_redirection() {
+        return Ok(None);
+    }
+
+    let Some(location) = headers.get(LOCATION) else {
+        return Ok(None);
+    };
+
+    let location = location.to_str().map_err(|_| {
+        ResolveError::InvalidLocation(String::from_utf8_lossy(location.as_bytes()).into_owned())
+    })?;
+
+    // relative locations are resolved against the current url
+    url.join(location)
+        .map(Some)
+        .map_err(|_| ResolveError::InvalidLocation(location.to_owned()))
+}
+
+/// Check that the url is http(s) and its host, if it's an address, is public
+///
+/// Domains are checked by [`PublicOnlyResolver`] when connecting
+fn ensure_public(url: &Url) -> Result<(), ResolveError> {
+    let not_public = || ResolveError::NotPublic(url.clone());
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(not_public());
+    }
+
+    let ip: IpAddr = match url.host().ok_or_else(not_public)? {
+        Host::Ipv4(ip) => ip.into(),
+        Host::Ipv6(ip) => ip.into(),
+        Host::Domain(_) => return Ok(()),
+    };
+
+    if !is_public_ip(ip) {
+        return Err(not_public());
+    }
+
+    Ok(())
+}
+
+/// Resolves the hosts for the client, failing if any of their addresses is not public
+///
+/// The client connects to the addresses checked here, so a host can't resolve
+/// to a public address for the check and to a private one for the connection
+#[derive(Debug)]
+struct PublicOnlyResolver;
+
+impl Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name))
+    }
+}
+
+async fn resolve_public(name: Name) -> Result<Addrs, Box<dyn Error + Send + Sync>> {
+    // the client sets the port of the url
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public_ip(address.ip())) {
+        return Err(ResolveError::NotPublicHost(name.as_str().to_owned()).into());
+    }
+
+    Ok(Box::new(addresses.into_iter()))
+}
+
+/// Whether the address is reachable over the internet,
+/// as opposed to private, loopback, link-local and other special ranges
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 address leads to: IPv4-mapped, NAT64 (64:ff9b::/96)
+/// and 6to4 (2002::/16) addresses
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return Some(ip);
+    }
+
+    let from_segments =
+        |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(from_segments(high, low)),
+        [0x2002, high, low, ..] => Some(from_segments(high, low)),
+        _ => None,
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // the shared address space of carrier-grade NATs, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // "this network", 0.0.0.0/8
+        || a == 0
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking, 198.18.0.0/15
+        || (a == 198 && (b == 18 || b == 19))
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use super::*;
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:192.168.1.1",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.254",
+            "240.0.0.1",
+            // NAT64 and 6to4 addresses of private ones
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:c0a8:101::1",
+            "2002:7f00:1::",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in [
+            "142.250.74.14",
+            "1.1.1.1",
+            "2a00:1450:4001:82b::200e",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn private_urls_are_rejected() -> anyhow::Result<()> {
+        for url in [
+            "http://127.0.0.1/redirect",
+            "http://[::1]:8080/",
+            "http://192.168.1.1/",
+            "http://[64:ff9b::c0a8:101]/",
+            "ftp://example.org/",
+            "file:///etc/passwd",
+        ] {
+            let result = ensure_public(&Url::parse(url)?);
+            assert!(result.is_err(), "{url}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hosts_resolving_to_private_addresses_are_not_connected_to() -> anyhow::Result<()> {
+        let resolver = RedirectResolver::new(Duration::from_secs(5))?;
+
+        let result = resolver.resolve(Url::parse("http://localhost/")?).await;
+        assert!(
+            matches!(result, Err(ResolveError::Request(_))),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_targets() -> anyhow::Result<()> {
+        let url = Url::parse("https://lnk.to/abc")?;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LOCATION,
+            HeaderValue::from_static("https://youtu.be/FiwMTquj-rQ?si=abc"),
+        );
+
+        assert_eq!(
+            redirect_target(&url, StatusCode::FOUND, &headers)?,
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?si=abc")?)
+        );
+        assert_eq!(redirect_target(&url, StatusCode::OK, &headers)?, None);
+        assert_eq!(
+            redirect_target(&url, StatusCode::FOUND, &HeaderMap::new())?,
+            None
+        );
+
+        headers.insert(LOCATION, HeaderValue::from_static("/def"));
+        assert_eq!(
+            redirect_target(&url, StatusCode::MOVED_PERMANENTLY, &headers)?,
+            Some(Url::parse("https://lnk.to/def")?)
+        );
+
+        Ok(())
+    }
+}
@@ -1,250 +1,3295 @@
-use std::iter;
+use std::{borrow::Cow, collections::HashSet, sync::Arc, time::Duration};
 
-use crate::utils::FullErrorDisplay;
+use crate::{
+    config::{BotConfig, ConfirmationMode, LinkOrder},
+    remove_si::{RULESETS, YOUTUBE_DOMAINS, is_short_link, url_belongs_to_youtube},
+    rulesets::RulesetStripper,
+    transform::{
+        FrontendRewriter, ShareLinkUnwrapper, ShortLinkExpander, TransformChain, UrlTransform,
+    },
+    url_kind::{youtube_url_kind, youtube_video_id},
+    utils::FullErrorDisplay,
+};
 use anyhow::anyhow;
+use futures::future::join_all;
 use teloxide::{
-    RequestError,
+    ApiError, RequestError,
     dispatching::dialogue::GetChatId,
+    payloads::SendMessage,
     prelude::*,
     sugar::request::RequestReplyExt,
-    types::{MessageEntityKind, MessageId},
+    types::{
+        BusinessConnectionId, CopyTextButton, InlineKeyboardButton, InlineKeyboardButtonKind,
+        InlineKeyboardMarkup, Me, MessageEntity, MessageEntityKind, MessageId, MessageKind,
+        ReactionType, ReplyMarkup, ReplyParameters, ThreadId,
+    },
 };
+use thiserror::Error;
 use tracing::{debug, info, instrument, warn};
 use url::Url;
 
-use super::BotRequester;
+use super::{
+    BotRequester, UptimeMetrics,
+    anchor::{forget_anchor, resolve_reply_to},
+    chat_settings::ChatSettingsStore,
+    i18n::{self, Lang},
+    maintenance::{Intercept, Maintenance},
+    me::SharedMe,
+    redirects::RedirectResolver,
+    replies::{ReplyAction, ReplyTracker},
+    reply_limiter::ReplyLimiter,
+    request_id::RequestId,
+    stats::StatsStore,
+};
 
-const YOUTUBE_DOMAINS: &[&str] = &["youtube.com", "www.youtube.com", "youtu.be"];
+const LINK_BUTTON_TEXT: &str = "Open cleaned link";
+const COPY_BUTTON_TEXT: &str = "Copy full link";
+/// Telegram's limit on the length of a message, in UTF-16 code units
+const MAX_MESSAGE_LEN: usize = 4096;
+/// Replies with more links than this are numbered
+const NUMBERED_LINKS_THRESHOLD: usize = 3;
+/// Room left in every message for the header, the prefix, the footer and the notes
+const MESSAGE_LEN_RESERVE: usize = 256;
+/// Characters clients don't consider a part of a link when they end it, see [`link_entity`]
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ':', ';', '!', '?', ')', '\'', '"'];
 
-#[instrument(skip_all, err)]
-pub async fn remove_si(bot: BotRequester, message: Message) -> anyhow::Result<()> {
+#[instrument(skip_all, fields(request_id = %RequestId::generate()), err)]
+#[allow(clippy::too_many_arguments)] // the dependencies are injected by dptree
+pub async fn remove_si(
+    bot: BotRequester,
+    message: Message,
+    config: Arc<BotConfig>,
+    settings: ChatSettingsStore,
+    stats: StatsStore,
+    metrics: UptimeMetrics,
+    replies: ReplyTracker,
+    reply_limiter: ReplyLimiter,
+    maintenance: Maintenance,
+    me: SharedMe,
+    resolver: RedirectResolver,
+) -> anyhow::Result<()> {
     let chat_id = message.chat_id().ok_or(anyhow!("failed to get chat id"))?;
 
-    let urls = message_url_iterator(&message);
-    let mut filtered_urls = urls.filter_map(url_without_si).peekable();
+    if settings.is_muted(chat_id).await {
+        debug!("disabled, paused or in quiet hours in this chat");
+        return Ok(());
+    }
+
+    let frontend_host = settings
+        .frontend_host(chat_id, config.frontend_host.as_deref())
+        .await;
+    let links =
+        cleaned_links_following_redirects(&message, &config, &resolver, frontend_host.as_deref())
+            .await;
+    let filtered_urls: Vec<_> = links.iter().map(|link| link.cleaned.clone()).collect();
 
-    let Some(first) = filtered_urls.next() else {
-        debug!("no youtube urls with si found");
+    match maintenance.intercept(chat_id.0, !filtered_urls.is_empty()) {
+        Intercept::Proceed => {}
+        Intercept::Notify => {
+            info!("in maintenance, sending the notice");
+            bot.send_message(chat_id, &config.maintenance_notice)
+                .reply_to(message.id)
+                .await?;
+
+            return Ok(());
+        }
+        Intercept::Ignore => {
+            debug!("in maintenance, not cleaning");
+            return Ok(());
+        }
+    }
+
+    stats.record(chat_id, filtered_urls.len());
+    metrics.record(filtered_urls.len());
+    metrics.record_removed_params(links.iter().flat_map(|link| &link.removed_params));
+
+    if config.repost_links
+        && !filtered_urls.is_empty()
+        && settings
+            .confirmation_mode(chat_id, config.confirmation_mode)
+            .await
+            == ConfirmationMode::Reply
+        && repost(
+            &bot,
+            &message,
+            &config,
+            &metrics,
+            &reply_limiter,
+            &me.get(),
+            &links,
+        )
+        .await?
+    {
         return Ok(());
-    };
+    }
 
-    let mut response = String::new();
+    let footer = dm_footer(&config, &message, &stats);
 
-    response.push_str(if filtered_urls.peek().is_some() {
-        "The links without tracking:\n"
-    } else {
-        "The link without tracking:\n"
-    });
+    respond(
+        &bot,
+        &message,
+        &config,
+        &settings,
+        &metrics,
+        &replies,
+        &reply_limiter,
+        &links,
+        footer.as_deref(),
+    )
+    .await
+}
 
-    for url in iter::once(first).chain(filtered_urls) {
-        response.push_str(url.as_str());
-        response.push('\n');
+/// Brings the bot's reply up to date with the edited message
+#[instrument(skip_all, fields(request_id = %RequestId::generate()), err)]
+#[allow(clippy::too_many_arguments)] // the dependencies are injected by dptree
+pub async fn remove_si_edited(
+    bot: BotRequester,
+    message: Message,
+    config: Arc<BotConfig>,
+    settings: ChatSettingsStore,
+    stats: StatsStore,
+    metrics: UptimeMetrics,
+    replies: ReplyTracker,
+    reply_limiter: ReplyLimiter,
+    maintenance: Maintenance,
+    resolver: RedirectResolver,
+) -> anyhow::Result<()> {
+    let chat_id = message.chat_id().ok_or(anyhow!("failed to get chat id"))?;
+
+    if settings.is_muted(chat_id).await {
+        debug!("disabled, paused or in quiet hours in this chat");
+        return Ok(());
     }
 
-    send_message_retrying(&bot, chat_id, message.id, &response).await?;
+    if maintenance.is_enabled() {
+        debug!("in maintenance, not updating the reply");
+        return Ok(());
+    }
 
-    Ok(())
+    let frontend_host = settings
+        .frontend_host(chat_id, config.frontend_host.as_deref())
+        .await;
+    let links =
+        cleaned_links_following_redirects(&message, &config, &resolver, frontend_host.as_deref())
+            .await;
+
+    let footer = dm_footer(&config, &message, &stats);
+
+    respond(
+        &bot,
+        &message,
+        &config,
+        &settings,
+        &metrics,
+        &replies,
+        &reply_limiter,
+        &links,
+        footer.as_deref(),
+    )
+    .await
 }
 
-/// Try parsing a URL from an entity string
+/// A link found in a message and its cleaned version
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CleanedLink {
+    original: Url,
+    cleaned: Url,
+    /// The tracking parameters the stripper removed, in the order they first appeared
+    removed_params: Vec<String>,
+}
+
+/// The cleaned links of all YouTube links with si in the message
 ///
-/// If the url has no base, tries using `https://` by default
+/// YouTube links are rewritten to `frontend_host` if given, see [`ChatSettingsStore::frontend_host`]
+pub(super) fn cleaned_urls(
+    message: &Message,
+    config: &BotConfig,
+    frontend_host: Option<&str>,
+) -> Vec<Url> {
+    cleaned_links(message, config, frontend_host)
+        .into_iter()
+        .map(|link| link.cleaned)
+        .collect()
+}
+
+/// Same as [`cleaned_urls`], keeping the links they were cleaned from
+fn cleaned_links(
+    message: &Message,
+    config: &BotConfig,
+    frontend_host: Option<&str>,
+) -> Vec<CleanedLink> {
+    let keyboard_urls = config
+        .scan_keyboard_urls
+        .then(|| keyboard_url_iterator(message))
+        .into_iter()
+        .flatten();
+
+    let cleaner = LinkCleaner::new(config, frontend_host);
+    let mut links: Vec<_> = message_url_iterator(message)
+        .chain(keyboard_urls)
+        .filter_map(|original| cleaner.clean(original))
+        .collect();
+
+    // the same link may come from several sources, e.g. an entity and a keyboard button
+    let mut seen = HashSet::new();
+    links.retain(|link| seen.insert(link.cleaned.as_str().to_owned()));
+
+    if config.first_link_only && links.len() > 1 {
+        debug!(skipped = links.len() - 1, "only cleaning the first link");
+        links.truncate(1);
+    }
+
+    links
+}
+
+/// The most links of a message whose redirects are followed, so a message can't make
+/// the bot send lots of requests
+const MAX_REDIRECTED_LINKS: usize = 5;
+
+/// Same as [`cleaned_links`], but also cleaning the links that links to other sites redirect to,
+/// if following redirects is enabled in the config
+async fn cleaned_links_following_redirects(
+    message: &Message,
+    config: &BotConfig,
+    resolver: &RedirectResolver,
+    frontend_host: Option<&str>,
+) -> Vec<CleanedLink> {
+    let mut links = cleaned_links(message, config, frontend_host);
+
+    if !config.follow_redirects {
+        return links;
+    }
+
+    let rulesets = config.rulesets.get();
+    let candidates: Vec<_> = message_url_iterator(message)
+        .filter(|url| !rulesets.iter().any(|ruleset| ruleset.matches(url)))
+        .take(MAX_REDIRECTED_LINKS)
+        .collect();
+    let resolved = join_all(candidates.into_iter().map(|url| resolver.resolve(url))).await;
+
+    let cleaner = LinkCleaner::new(config, frontend_host);
+    for result in resolved {
+        let target = match result {
+            Ok(target) => target,
+            Err(e) => {
+                debug!(error = %FullErrorDisplay(e), "failed to follow the redirects");
+                continue;
+            }
+        };
+
+        match cleaner.clean(target) {
+            Some(link) if !links.iter().any(|known| known.cleaned == link.cleaned) => {
+                links.push(link);
+            }
+            _ => {}
+        }
+    }
+
+    if config.first_link_only {
+        links.truncate(1);
+    }
+
+    links
+}
+
+/// The transforms the links are cleaned with, around the [`RulesetStripper`]
 ///
-/// On error, logs it and returns None
-fn try_parse_url(s: &str) -> Option<Url> {
-    Url::parse(s)
-        .or_else(|e| match e {
-            url::ParseError::RelativeUrlWithoutBase => Url::parse(&format!("https://{s}")),
-            other_error => Err(other_error),
-        })
-        .inspect_err(
-            |e| warn!(error = %FullErrorDisplay(e), entity = s, "Failed to parse the url from the entity"),
-        )
-        .ok()
+/// The stripper is kept out of the chains, so the parameters it removed are known
+struct LinkCleaner<'a> {
+    before: TransformChain<'a>,
+    stripper: RulesetStripper,
+    after: TransformChain<'a>,
 }
 
-fn message_url_iterator(m: &Message) -> impl Iterator<Item = Url> {
-    // this allows us to more conveniently handle Nones
-    // while the outer function flattens None into an empty iterator
-    fn maybe_url_iterator(m: &Message) -> Option<impl Iterator<Item = Url>> {
-        let text = m.text()?;
-        let entities = m.entities()?.iter();
-        debug!(%text, ?entities, "parsing url");
-        let urls = entities.filter_map(|entity| match entity.kind {
-            MessageEntityKind::Url => text
-                .get(entity.offset..entity.offset + entity.length)
-                .or_else(|| {
-                    warn!("Failed to slice the URL entity from the message");
-
-                    None
-                })
-                .and_then(try_parse_url),
-            MessageEntityKind::TextLink { ref url } => Some(url.clone()),
-            _ => None,
-        });
+impl<'a> LinkCleaner<'a> {
+    fn new(config: &'a BotConfig, frontend_host: Option<&str>) -> Self {
+        let mut before = TransformChain::empty();
+
+        if config.unwrap_share_links {
+            before = before.then(ShareLinkUnwrapper);
+        }
 
-        Some(urls)
+        let mut after =
+            TransformChain::empty().then(|url: Url| (!is_denied(&url, config)).then_some(url));
+
+        if config.expand_short_links {
+            after = after.then(ShortLinkExpander);
+        }
+
+        if let Some(host) = frontend_host {
+            after = after.then(FrontendRewriter {
+                host: host.to_owned(),
+            });
+        }
+
+        Self {
+            before,
+            stripper: config.rulesets.stripper(),
+            after,
+        }
     }
 
-    maybe_url_iterator(m).into_iter().flatten()
+    /// Links without tracking are already fine and dropped by the stripper,
+    /// so they are left out of the reply
+    fn clean(&self, original: Url) -> Option<CleanedLink> {
+        let url = self.before.apply(original.clone())?;
+        let stripped = self.stripper.strip(url)?;
+        let cleaned = self.after.apply(stripped.url)?;
+
+        Some(CleanedLink {
+            original,
+            cleaned,
+            removed_params: stripped.removed_params,
+        })
+    }
 }
 
-async fn send_message_retrying(
+/// React or reply to the message with the cleaned links according to the confirmation mode
+///
+/// If the bot already replied to an earlier version of the message, the reply is edited,
+/// or deleted if there are no tracked links left
+#[allow(clippy::too_many_arguments)] // the dependencies of the handlers, passed on
+async fn respond(
     bot: &BotRequester,
-    to: ChatId,
-    reply_to: MessageId,
-    message: &str,
-) -> anyhow::Result<()> //
-{
-    const RETRY_LIMIT: u32 = 20;
+    message: &Message,
+    config: &BotConfig,
+    settings: &ChatSettingsStore,
+    metrics: &UptimeMetrics,
+    replies: &ReplyTracker,
+    reply_limiter: &ReplyLimiter,
+    links: &[CleanedLink],
+    footer: Option<&str>,
+) -> anyhow::Result<()> {
+    let chat_id = message.chat.id;
+    let filtered_urls: Vec<_> = links.iter().map(|link| link.cleaned.clone()).collect();
+    let has_urls = if links.is_empty() {
+        debug!("no youtube urls with si found");
+        false
+    } else if let Some(threshold) = config.cosmetic_link_threshold
+        && links.iter().all(|link| is_cosmetic_change(link, threshold))
+    {
+        debug!("only cosmetic changes, not replying");
+        false
+    } else {
+        true
+    };
 
-    let mut last_err = None;
+    let mode = settings
+        .confirmation_mode(chat_id, config.confirmation_mode)
+        .await;
 
-    for _ in 0..RETRY_LIMIT {
-        let result = bot.send_message(to, message).reply_to(reply_to).await;
+    if mode == ConfirmationMode::Silent {
+        debug!("staying silent about tracked links");
+        return Ok(());
+    }
 
-        match result {
-            Ok(_) => break,
-            Err(ref e @ (RequestError::Network(_) | RequestError::Io(_))) => {
-                warn!(error=%FullErrorDisplay(e), "error while sending message, retrying...")
-            }
-            Err(ref e @ RequestError::RetryAfter(secs)) => {
-                warn!(error=%FullErrorDisplay(e), delay=%secs, "error while sending message, retrying after a delay..");
-                tokio::time::sleep(secs.duration()).await;
+    if mode == ConfirmationMode::Reaction {
+        let Some(first) = filtered_urls.first().filter(|_| has_urls) else {
+            return Ok(());
+        };
+
+        let emoji = config.reaction_emojis.emoji_for(youtube_url_kind(first));
+        info!(%emoji, "reacting to a message with tracked links");
+
+        let mut react = bot.set_message_reaction(chat_id, message.id);
+        react.reaction = Some(vec![ReactionType::Emoji {
+            emoji: emoji.to_owned(),
+        }]);
+        react.await?;
+
+        return Ok(());
+    }
+
+    let ordered_urls = order_links(&filtered_urls, config.link_order);
+    let prefix = settings.get(chat_id).await.reply_prefix;
+    let format = ReplyFormat {
+        max_displayed_len: config.max_displayed_url_len,
+        spoiler: config.spoiler_links && has_spoilered_links(message),
+        prefix: prefix.as_deref(),
+        numbered_from: (ordered_urls.len() > NUMBERED_LINKS_THRESHOLD).then_some(1),
+        lang: Lang::from_code(
+            message
+                .from
+                .as_ref()
+                .and_then(|user| user.language_code.as_deref()),
+        ),
+    };
+    let messages = reply_messages(config, &ordered_urls, &format, footer);
+
+    match replies.action(chat_id, message.id, has_urls) {
+        ReplyAction::Send => {
+            if !reply_limiter.try_acquire(chat_id, messages.len()) {
+                warn!("too many replies in this chat, dropping the reply");
+                return Ok(());
             }
-            Err(e) => return Err(e.into()),
+
+            let sent = send_reply(bot, config, settings, metrics, message, &messages).await?;
+            replies.track(chat_id, message.id, sent);
         }
+        ReplyAction::Edit(sent) => {
+            // edits count against the flood limits like new messages
+            if !reply_limiter.try_acquire(chat_id, sent.len().min(messages.len())) {
+                warn!("too many replies in this chat, not updating the reply");
+                return Ok(());
+            }
+
+            info!(
+                messages = sent.len(),
+                "updating the reply to the edited message"
+            );
+
+            // the messages of the reply are edited in place,
+            // the ones no longer needed are deleted and the missing ones are sent
+            for (&reply, part) in sent.iter().zip(&messages) {
+                edit_reply(bot, chat_id, reply, part).await?;
+            }
 
-        last_err = result.err().map(Into::into);
+            let mut kept: Vec<_> = sent.iter().copied().take(messages.len()).collect();
+            let unneeded = &sent[kept.len()..];
+            if !unneeded.is_empty() {
+                bot.delete_messages(chat_id, unneeded.to_vec()).await?;
+            }
+
+            let missing = &messages[kept.len()..];
+            if !missing.is_empty() {
+                if reply_limiter.try_acquire(chat_id, missing.len()) {
+                    kept.extend(
+                        send_reply(bot, config, settings, metrics, message, missing).await?,
+                    );
+                } else {
+                    warn!("too many replies in this chat, not extending the reply");
+                }
+            }
+
+            replies.track(chat_id, message.id, kept);
+        }
+        ReplyAction::Delete(sent) => {
+            info!("the edited message has no tracked links anymore, deleting the reply");
+            bot.delete_messages(chat_id, sent).await?;
+        }
+        ReplyAction::Nothing => {}
     }
 
-    last_err.map(Err).unwrap_or(Ok(()))
+    Ok(())
 }
 
-/// If the url belongs to YouTube and contains an `si`` query parameter,
-/// returns a copy of that url without the `si` parameter
-fn url_without_si(url: Url) -> Option<Url> {
-    if !url_belongs_to_youtube(&url) || !url_has_si(&url) {
-        return None;
+/// Send the messages of the reply to the message, returning their ids
+///
+/// The reply goes where [`resolve_reply_to`] says,
+/// or to the message if the anchor it should go to was deleted
+async fn send_reply(
+    bot: &BotRequester,
+    config: &BotConfig,
+    settings: &ChatSettingsStore,
+    metrics: &UptimeMetrics,
+    message: &Message,
+    parts: &[ReplyMessage],
+) -> anyhow::Result<Vec<MessageId>> {
+    let chat_id = message.chat.id;
+    let reply_to =
+        resolve_reply_to(bot, settings, config.thread_replies, chat_id, message.id).await?;
+    let mut context = ReplyContext::of(message, reply_to.id);
+    let mut sent = Vec::with_capacity(parts.len());
+
+    for part in parts {
+        let reply = match send_message_retrying(bot, config, metrics, chat_id, &context, part).await
+        {
+            Err(e) if reply_to.is_anchor && is_reply_target_missing(&e) => {
+                warn!("the anchor message is gone, replying to the message instead");
+                forget_anchor(settings, chat_id).await?;
+                context = ReplyContext::of(message, message.id);
+
+                send_message_retrying(bot, config, metrics, chat_id, &context, part).await?
+            }
+            reply => reply?,
+        };
+
+        sent.push(reply);
     }
 
-    Some(remove_si_from_url(url))
+    Ok(sent)
+}
+
+/// Replace one message of the bot's reply, it's fine if it didn't change
+async fn edit_reply(
+    bot: &BotRequester,
+    chat_id: ChatId,
+    reply: MessageId,
+    part: &ReplyMessage,
+) -> anyhow::Result<()> {
+    let mut request = bot.edit_message_text(chat_id, reply, &part.text);
+    request.entities = (!part.entities.is_empty()).then(|| part.entities.clone());
+    request.reply_markup = part.keyboard.clone();
+
+    match request.await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
 }
 
-fn remove_si_from_url(mut url: Url) -> Url {
-    use std::fmt::Write;
+/// Repost the message with its links cleaned, attributed to the author, and delete the original
+///
+/// Returns false if the message was not replaced, e.g. because the bot lacks the rights
+/// or the message can't be reposted as is, so the caller can reply as usual
+async fn repost(
+    bot: &BotRequester,
+    message: &Message,
+    config: &BotConfig,
+    metrics: &UptimeMetrics,
+    reply_limiter: &ReplyLimiter,
+    me: &Me,
+    links: &[CleanedLink],
+) -> anyhow::Result<bool> {
+    let Some((text, entities)) = repost_text(message, links) else {
+        debug!("the message can't be reposted as is, replying instead");
+        return Ok(false);
+    };
 
-    debug!(%url, "removing si from URL");
+    if text.encode_utf16().count() > MAX_MESSAGE_LEN {
+        debug!("too many links for a repost, replying instead");
+        return Ok(false);
+    }
 
-    let mut query_pairs = url
-        .query_pairs()
-        .filter(|(key, _value)| key != "si")
-        .peekable();
+    match can_delete_messages(bot, message, me).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("can't delete messages in this chat, replying instead");
+            return Ok(false);
+        }
+        Err(e) => {
+            warn!(error = %FullErrorDisplay(e), "failed to check the rights, replying instead");
+            return Ok(false);
+        }
+    }
 
-    if query_pairs.peek().is_none() {
-        url.set_query(None);
-        debug!(%url, "URL has no other query params, cleared the query");
-        return url;
+    if !reply_limiter.try_acquire(message.chat.id, 1) {
+        debug!("too many replies in this chat, not reposting");
+        return Ok(false);
     }
 
-    let mut new_query = String::with_capacity(url.query().unwrap_or_default().len());
-    for (key, value) in query_pairs {
-        if !new_query.is_empty() {
-            new_query.push('&');
+    // the original is only deleted once the repost is sent, so the message is never lost
+    let repost = ReplyMessage {
+        text,
+        entities,
+        keyboard: None,
+    };
+    let sent = match send_message_retrying(
+        bot,
+        config,
+        metrics,
+        message.chat.id,
+        &ReplyContext::repost_of(message),
+        &repost,
+    )
+    .await
+    {
+        Ok(sent) => sent,
+        Err(e) => {
+            warn!(error = %e, "failed to repost the message, replying instead");
+            return Ok(false);
         }
+    };
 
-        write!(new_query, "{key}={value}").unwrap();
+    // the rights may have changed since the check
+    if let Err(e) = bot.delete_message(message.chat.id, message.id).await {
+        warn!(error = %FullErrorDisplay(e), "failed to delete the message, replying instead");
+        // the message would be there twice otherwise
+        bot.delete_message(message.chat.id, sent).await?;
+        return Ok(false);
     }
 
-    url.set_query(Some(&new_query));
-    debug!(%url, "restored other query params");
-    url
+    info!("reposted the message with tracked links and deleted it");
+
+    Ok(true)
 }
 
-fn url_has_si(url: &Url) -> bool {
-    debug!(%url, "checking if the URL contains an si parameter");
+/// Whether the bot can delete other users' messages in the chat of the message
+///
+/// Private chats are left alone, there's no one else to attribute the links for
+async fn can_delete_messages(
+    bot: &BotRequester,
+    message: &Message,
+    me: &Me,
+) -> Result<bool, RequestError> {
+    if message.chat.is_private() {
+        return Ok(false);
+    }
+
+    let member = bot.get_chat_member(message.chat.id, me.id).await?;
 
-    let Some(query) = url.query() else {
-        return false;
+    Ok(member.kind.can_delete_messages())
+}
+
+/// The text of the repost: the author and the text of the message with the links cleaned
+///
+/// Users without a username are mentioned by name, messages sent on behalf of a chat
+/// (e.g. by anonymous admins) are attributed to that chat.
+/// Only plain text messages whose own link entities hold all the links are reposted,
+/// anything else (media, quotes, keyboards, forwards) would be lost with the original.
+/// Returns None for the other messages and for messages without an author
+fn repost_text(message: &Message, links: &[CleanedLink]) -> Option<(String, Vec<MessageEntity>)> {
+    let original = message.text()?;
+    if message.quote().is_some()
+        || message.reply_markup().is_some()
+        || message.forward_origin().is_some()
+    {
+        return None;
+    }
+
+    let mut text = "Shared by ".to_owned();
+    let mut entities = Vec::new();
+
+    match (&message.sender_chat, &message.from) {
+        (Some(chat), _) => text.push_str(chat.title()?),
+        (None, Some(user)) => match &user.username {
+            Some(username) => text.push_str(&format!("@{username}")),
+            None => {
+                let name = user.full_name();
+                // entity offsets are in UTF-16 code units
+                entities.push(MessageEntity::text_mention(
+                    user.clone(),
+                    text.encode_utf16().count(),
+                    name.encode_utf16().count(),
+                ));
+                text.push_str(&name);
+            }
+        },
+        (None, None) => return None,
+    }
+
+    text.push_str(":\n");
+
+    // entity offsets are in UTF-16 code units
+    let prefix_len = text.encode_utf16().count();
+    let original: Vec<u16> = original.encode_utf16().collect();
+    let link_of = |url: &Url| links.iter().find(|link| link.original == *url);
+    let mut replaced = HashSet::new();
+
+    // the url entities with the cleaned links replacing them, in the order of the text
+    let mut replacements = Vec::new();
+    for entity in message.entities().unwrap_or_default() {
+        if entity.kind != MessageEntityKind::Url {
+            continue;
+        }
+
+        let url = try_parse_url(&entity_text(&original, entity)?);
+        if let Some(link) = url.as_ref().and_then(link_of) {
+            replaced.insert(&link.original);
+            let cleaned: Vec<u16> = link.cleaned.as_str().encode_utf16().collect();
+            replacements.push((entity.offset, entity.length, cleaned));
+        }
+    }
+    replacements.sort_by_key(|&(offset, _, _)| offset);
+
+    // where a position in the original text ends up, positions within a replaced link
+    // go to the start or the end of the cleaned one
+    let moved = |position: usize, is_end: bool| {
+        let mut moved = position;
+        for (offset, length, cleaned) in &replacements {
+            if position >= offset + length {
+                moved = moved + cleaned.len() - length;
+            } else if position > *offset {
+                let start = moved - (position - offset);
+                return if is_end { start + cleaned.len() } else { start };
+            }
+        }
+
+        moved
     };
 
-    query.starts_with("si=") || query.contains("&si=")
+    for entity in message.entities().unwrap_or_default() {
+        let mut entity = entity.clone();
+        if let MessageEntityKind::TextLink { url } = &mut entity.kind
+            && let Some(link) = link_of(url)
+        {
+            replaced.insert(&link.original);
+            *url = link.cleaned.clone();
+        }
+
+        let start = moved(entity.offset, false);
+        let end = moved(entity.offset + entity.length, true);
+        entity.offset = prefix_len + start;
+        entity.length = end - start;
+        entities.push(entity);
+    }
+
+    // links found by scanning the text or following redirects would keep their tracking
+    if !links.iter().all(|link| replaced.contains(&link.original)) {
+        return None;
+    }
+
+    let mut reposted = Vec::with_capacity(original.len());
+    let mut position = 0;
+    for (offset, length, cleaned) in &replacements {
+        reposted.extend_from_slice(&original[position..*offset]);
+        reposted.extend_from_slice(cleaned);
+        position = offset + length;
+    }
+    reposted.extend_from_slice(&original[position..]);
+    text.push_str(&String::from_utf16(&reposted).ok()?);
+
+    Some((text, entities))
 }
 
-fn url_belongs_to_youtube(url: &Url) -> bool {
-    debug!(%url, "checking if URL belongs to YouTube");
+/// Order the cleaned links for the reply
+///
+/// Grouping keeps the order of the links within a service, services follow the order of
+/// [`RULESETS`], links matching no ruleset (e.g. rewritten to a frontend) go last
+fn order_links(urls: &[Url], order: LinkOrder) -> Vec<Url> {
+    let mut urls = urls.to_vec();
 
-    matches!(
-        url.host(),
-        Some(url::Host::Domain(domain)) if YOUTUBE_DOMAINS.contains(&domain)
-    )
+    if order == LinkOrder::Grouped {
+        urls.sort_by_key(|url| {
+            RULESETS
+                .iter()
+                .position(|ruleset| ruleset.matches(url))
+                .unwrap_or(RULESETS.len())
+        });
+    }
+
+    urls
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use url::Url;
+/// A line with the number of links cleaned for the user in their private chat with the bot,
+/// if enabled in the config
+///
+/// The stats of a private chat are the stats of the user, as the chat id is the user id
+fn dm_footer(config: &BotConfig, message: &Message, stats: &StatsStore) -> Option<String> {
+    if !config.dm_user_stats || !message.chat.is_private() {
+        return None;
+    }
 
-    #[test]
-    fn non_youtube_urls_return_none() -> anyhow::Result<()> {
-        let urls = [
-            Url::parse("https://google.com/hii")?,
-            Url::parse("https://example.org/meow?si=23")?,
-            Url::parse("https://you.tube/watch?v=XqC")?,
-        ];
+    let cleaned = stats.chat(message.chat.id).urls_cleaned;
+    Some(format!("\nLinks I've cleaned for you: {cleaned}\n"))
+}
+
+/// One message of the reply
+#[derive(Debug, Clone, PartialEq)]
+struct ReplyMessage {
+    text: String,
+    entities: Vec<MessageEntity>,
+    keyboard: Option<InlineKeyboardMarkup>,
+}
+
+/// The messages of the reply, as many as needed to fit the links,
+/// but at most [`BotConfig::max_reply_messages`]
+///
+/// The links that don't fit are counted in a note at the end of the last message,
+/// followed by the footer
+fn reply_messages(
+    config: &BotConfig,
+    urls: &[Url],
+    format: &ReplyFormat,
+    footer: Option<&str>,
+) -> Vec<ReplyMessage> {
+    let (chunks, left_out) = split_into_messages(urls, format, config.max_reply_messages);
+
+    let mut listed = 0;
+    let mut messages: Vec<_> = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let format = ReplyFormat {
+                // the prefix starts the reply, not every message of it
+                prefix: format.prefix.filter(|_| i == 0),
+                // the numbers continue across the messages
+                numbered_from: format.numbered_from.map(|first| first + listed),
+                ..*format
+            };
+            listed += chunk.len();
+            let (text, entities) = reply_text(chunk, &format);
+
+            ReplyMessage {
+                text,
+                entities,
+                keyboard: reply_keyboard(config, chunk),
+            }
+        })
+        .collect();
 
-        for url in urls {
-            assert!(url_without_si(url).is_none());
+    if let Some(last) = messages.last_mut() {
+        if left_out > 0 {
+            debug!(left_out, "too many links for the reply");
+            last.text.push_str(&format!("…and {left_out} more\n"));
         }
 
-        Ok(())
+        if let Some(footer) = footer {
+            last.text.push_str(footer);
+        }
     }
 
-    #[test]
-    fn urls_without_si_return_none() -> anyhow::Result<()> {
-        let urls = [
-            Url::parse("https://www.youtube.com/watch?v=nFuAJl46w_w")?,
-            Url::parse("https://www.youtube.com/watch?v=0FwBHrVsiMJc&t=229s")?,
-            Url::parse("https://youtu.be/0FwBHrVuMJc")?,
-            Url::parse("https://www.youtube.com/watch?psi=nFuAJl46w_w")?,
-            Url::parse("https://www.youtube.com/watch?v=nFuAJl46w_w&sip=jsdhfjhbf")?,
-        ];
+    messages
+}
 
-        for url in urls {
-            assert!(url_without_si(url).is_none());
+/// Split the links into groups fitting in one message each, keeping at most `max_messages` groups
+///
+/// Returns the groups and the number of links left out
+fn split_into_messages<'a>(
+    urls: &'a [Url],
+    format: &ReplyFormat,
+    max_messages: usize,
+) -> (Vec<&'a [Url]>, usize) {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut len = 0;
+
+    for (i, url) in urls.iter().enumerate() {
+        let displayed = match format.max_displayed_len {
+            Some(max_len) => truncate_for_display(url.as_str(), max_len),
+            None => Cow::Borrowed(url.as_str()),
+        };
+        let number_len = format
+            .numbered_from
+            .map_or(0, |first| number_prefix(first + i).len());
+        let line_len = number_len + displayed.encode_utf16().count() + 1;
+
+        if i > start && len + line_len > MAX_MESSAGE_LEN - MESSAGE_LEN_RESERVE {
+            chunks.push(&urls[start..i]);
+            start = i;
+            len = 0;
         }
 
-        Ok(())
+        len += line_len;
     }
 
-    #[test]
-    fn removing_si_works() -> anyhow::Result<()> {
-        assert_eq!(
-            url_without_si(Url::parse(
-                "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce"
-            )?),
-            Some(Url::parse("https://youtu.be/0FwBHrVuMJc")?)
-        );
+    if start < urls.len() {
+        chunks.push(&urls[start..]);
+    }
 
-        assert_eq!(
-            url_without_si(Url::parse(
-                "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up"
-            )?),
-            Some(Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?)
-        );
+    chunks.truncate(max_messages.max(1));
+    let kept: usize = chunks.iter().map(|chunk| chunk.len()).sum();
 
-        Ok(())
+    (chunks, urls.len() - kept)
+}
+
+/// How the cleaned links are presented in the reply
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplyFormat<'a> {
+    /// Links longer than this are shown truncated,
+    /// with text link entities keeping the full links as their targets
+    max_displayed_len: Option<usize>,
+    /// Hide each link under a spoiler
+    spoiler: bool,
+    /// The chat's custom text put before the reply
+    prefix: Option<&'a str>,
+    /// Number the links starting from this number
+    numbered_from: Option<usize>,
+    /// The language of the texts around the links
+    lang: Lang,
+}
+
+/// The text of the reply listing the cleaned links
+///
+/// Replies are plain text with entities, so `_` or `)` in links and the prefix need no escaping
+fn reply_text(filtered_urls: &[Url], format: &ReplyFormat) -> (String, Vec<MessageEntity>) {
+    let mut response = String::new();
+    let mut entities = Vec::new();
+
+    if let Some(prefix) = format.prefix {
+        response.push_str(prefix);
+        response.push(' ');
     }
 
-    #[test]
-    fn removing_si_from_the_middle_is_correct() -> anyhow::Result<()> {
-        assert_eq!(
-            url_without_si(Url::parse(
-                "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173"
-            )?),
-            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
-        );
+    response.push_str(i18n::localized(
+        format.lang,
+        i18n::Message::LinkHeader {
+            plural: filtered_urls.len() > 1,
+        },
+    ));
+    response.push('\n');
 
-        Ok(())
+    for (i, url) in filtered_urls.iter().enumerate() {
+        if let Some(first) = format.numbered_from {
+            response.push_str(&number_prefix(first + i));
+        }
+
+        let displayed = match format.max_displayed_len {
+            Some(max_len) => truncate_for_display(url.as_str(), max_len),
+            None => Cow::Borrowed(url.as_str()),
+        };
+
+        // entity offsets are in UTF-16 code units
+        let offset = response.encode_utf16().count();
+        let length = displayed.encode_utf16().count();
+
+        entities.extend(link_entity(url, &displayed, offset, length));
+
+        if format.spoiler {
+            entities.push(MessageEntity::spoiler(offset, length));
+        }
+
+        response.push_str(&displayed);
+        response.push('\n');
+    }
+
+    (response, entities)
+}
+
+/// The entity linking the displayed link to the full one, if clients wouldn't link it as a whole
+///
+/// Truncated links need a text link, links ending with punctuation need an explicit url entity,
+/// otherwise clients stop the link before the punctuation
+fn link_entity(url: &Url, displayed: &str, offset: usize, length: usize) -> Option<MessageEntity> {
+    if displayed != url.as_str() {
+        Some(MessageEntity::text_link(url.clone(), offset, length))
+    } else if displayed.ends_with(TRAILING_PUNCTUATION) {
+        Some(MessageEntity::new(MessageEntityKind::Url, offset, length))
+    } else {
+        None
+    }
+}
+
+/// The `1. ` before a numbered link
+fn number_prefix(number: usize) -> String {
+    format!("{number}. ")
+}
+
+/// Shorten the string to `max_len` characters, the last of which is an ellipsis
+///
+/// The ellipsis counts toward `max_len`, but is kept even if `max_len` is zero.
+/// Returns the string as is if it's short enough
+fn truncate_for_display(s: &str, max_len: usize) -> Cow<'_, str> {
+    if s.chars().count() <= max_len {
+        return Cow::Borrowed(s);
+    }
+
+    let kept: String = s.chars().take(max_len.saturating_sub(1)).collect();
+    Cow::Owned(format!("{kept}…"))
+}
+
+/// The inline keyboard of the reply: the link button if enabled,
+/// and buttons copying the full links that are displayed truncated
+fn reply_keyboard(config: &BotConfig, urls: &[Url]) -> Option<InlineKeyboardMarkup> {
+    let mut rows = urls
+        .first()
+        .filter(|_| config.link_button)
+        .map(|url| link_keyboard(url).inline_keyboard)
+        .unwrap_or_default();
+
+    if let Some(max_len) = config.max_displayed_url_len {
+        let copy_rows = urls
+            .iter()
+            .enumerate()
+            .filter(|(_, url)| matches!(truncate_for_display(url.as_str(), max_len), Cow::Owned(_)))
+            .map(|(i, url)| {
+                let text = if urls.len() > 1 {
+                    format!("{COPY_BUTTON_TEXT} {}", i + 1)
+                } else {
+                    COPY_BUTTON_TEXT.to_owned()
+                };
+
+                vec![InlineKeyboardButton::new(
+                    text,
+                    InlineKeyboardButtonKind::CopyText(CopyTextButton {
+                        text: url.to_string(),
+                    }),
+                )]
+            });
+
+        rows.extend(copy_rows);
+    }
+
+    (!rows.is_empty()).then(|| InlineKeyboardMarkup::new(rows))
+}
+
+/// Whether the url points to a video the bot must never post
+fn is_denied(url: &Url, config: &BotConfig) -> bool {
+    if config.denied_video_ids.is_empty() || !url_belongs_to_youtube(url) {
+        return false;
+    }
+
+    let Some(id) = youtube_video_id(url) else {
+        return false;
+    };
+
+    let denied = config.denied_video_ids.contains(&id);
+    if denied {
+        debug!(
+            video_id = id,
+            "video is denied, leaving it out of the reply"
+        );
+    }
+
+    denied
+}
+
+/// Whether removing `si` was the only change and left a bare short link,
+/// which probably looked clean to the user already
+fn is_cosmetic_change(link: &CleanedLink, threshold: usize) -> bool {
+    let cleaned = &link.cleaned;
+
+    link.removed_params == ["si"]
+        && is_short_link(cleaned)
+        && cleaned.query().is_none()
+        && cleaned.as_str().len() < threshold
+}
+
+/// A keyboard with a single button opening the url
+fn link_keyboard(url: &Url) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::url(LINK_BUTTON_TEXT, url.clone())]])
+}
+
+/// Try parsing a URL from an entity string
+///
+/// If the url has no base, tries using `https://` by default
+///
+/// On error, logs it and returns None
+///
+/// Urls without a host (e.g. `mailto:` or `data:` ones) parse fine but can't be links
+/// worth cleaning, they are skipped as well
+pub(super) fn try_parse_url(s: &str) -> Option<Url> {
+    let s = trim_url_candidate(s);
+    let url = Url::parse(s)
+        .or_else(|e| match e {
+            url::ParseError::RelativeUrlWithoutBase => Url::parse(&format!("https://{s}")),
+            other_error => Err(other_error),
+        })
+        .inspect_err(
+            |e| warn!(error = %FullErrorDisplay(e), entity = s, "Failed to parse the url from the entity"),
+        )
+        .ok()?;
+
+    if url.host_str().is_none_or(str::is_empty) {
+        debug!(%url, entity = s, "skipping the url without a host");
+        return None;
+    }
+
+    Some(url)
+}
+
+/// Strip the whitespace and punctuation around a link in text, e.g. `(https://youtu.be/x).`
+///
+/// Parentheses are only stripped if they aren't balanced within the link,
+/// so links with parentheses in the path stay intact
+fn trim_url_candidate(s: &str) -> &str {
+    let mut s = s.trim();
+
+    loop {
+        let trimmed = s.trim_end_matches(['.', ',', ';', '!', '?']);
+        let opening = trimmed.matches('(').count();
+        let closing = trimmed.matches(')').count();
+
+        let trimmed = if let Some(inner) = trimmed
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            inner
+        } else if opening < closing {
+            trimmed.strip_suffix(')').unwrap_or(trimmed)
+        } else if opening > closing {
+            trimmed.strip_prefix('(').unwrap_or(trimmed)
+        } else {
+            trimmed
+        };
+
+        if trimmed == s {
+            return s;
+        }
+
+        s = trimmed;
+    }
+}
+
+/// Whether any link of the message is hidden under a spoiler
+///
+/// Telegram sends a spoiler as a separate entity overlapping the url entity
+fn has_spoilered_links(m: &Message) -> bool {
+    let Some((_, entities)) = message_text_and_entities(m) else {
+        return false;
+    };
+
+    let overlaps = |a: &MessageEntity, b: &MessageEntity| {
+        a.offset < b.offset + b.length && b.offset < a.offset + a.length
+    };
+
+    entities
+        .iter()
+        .filter(|entity| {
+            matches!(
+                entity.kind,
+                MessageEntityKind::Url | MessageEntityKind::TextLink { .. }
+            )
+        })
+        .any(|link| {
+            entities
+                .iter()
+                .any(|entity| entity.kind == MessageEntityKind::Spoiler && overlaps(link, entity))
+        })
+}
+
+/// Get the text of the message along with its entities
+///
+/// For media messages (photo, video, animation, audio, document, etc.) the caption is used instead.
+/// Forwarded stories are skipped, the Bot API only sends the chat and the id of a story, not its caption
+fn message_text_and_entities(m: &Message) -> Option<(&str, &[MessageEntity])> {
+    match m.text() {
+        Some(text) => Some((text, m.entities().unwrap_or_default())),
+        None => Some((m.caption()?, m.caption_entities().unwrap_or_default())),
+    }
+}
+
+/// Whether a whitespace-separated token of a message could be a link worth parsing
+pub(super) fn looks_like_url(token: &str) -> bool {
+    if token.starts_with("http://") || token.starts_with("https://") {
+        return true;
+    }
+
+    let token = token.to_ascii_lowercase();
+    YOUTUBE_DOMAINS.iter().any(|domain| token.contains(domain))
+}
+
+/// Find links in text that Telegram didn't turn into entities
+fn scan_text_for_urls(text: &str) -> impl Iterator<Item = Url> {
+    text.split_whitespace()
+        .filter(|token| looks_like_url(token))
+        .filter_map(try_parse_url)
+}
+
+fn message_url_iterator(m: &Message) -> impl Iterator<Item = Url> {
+    let text_urls = message_text_and_entities(m)
+        .into_iter()
+        .flat_map(|(text, entities)| urls_in_text(text, entities));
+    // the part of the replied message quoted by the user, with entities relative to the quote
+    let quote_urls = m
+        .quote()
+        .into_iter()
+        .flat_map(|quote| urls_in_text(&quote.text, &quote.entities));
+
+    text_urls.chain(quote_urls)
+}
+
+/// Links from the url entities of the text
+fn urls_in_text<'a>(
+    text: &'a str,
+    entities: &'a [MessageEntity],
+) -> impl Iterator<Item = Url> + 'a {
+    // some clients send links without entities, falling back to scanning the text
+    let scanned_urls = entities
+        .is_empty()
+        .then(|| scan_text_for_urls(text))
+        .into_iter()
+        .flatten();
+
+    debug!(%text, ?entities, "parsing url");
+    // entity offsets are in UTF-16 code units
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let urls = entities.iter().filter_map(move |entity| match entity.kind {
+        MessageEntityKind::Url => entity_text(&utf16, entity)
+            .or_else(|| {
+                warn!("Failed to slice the URL entity from the message");
+
+                None
+            })
+            .and_then(|url| try_parse_url(&url)),
+        MessageEntityKind::TextLink { ref url } => Some(url.clone()),
+        _ => None,
+    });
+
+    urls.chain(scanned_urls)
+}
+
+/// The part of the UTF-16 encoded `text` covered by `entity`
+fn entity_text(text: &[u16], entity: &MessageEntity) -> Option<String> {
+    String::from_utf16(text.get(entity.offset..entity.offset + entity.length)?).ok()
+}
+
+/// Links from the url buttons of the message's inline keyboard
+fn keyboard_url_iterator(m: &Message) -> impl Iterator<Item = Url> {
+    m.reply_markup()
+        .into_iter()
+        .flat_map(|markup| markup.inline_keyboard.iter().flatten())
+        .filter_map(|button| match &button.kind {
+            InlineKeyboardButtonKind::Url(url) => Some(url.clone()),
+            _ => None,
+        })
+}
+
+/// Where in the chat the reply goes and on whose behalf it's sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ReplyContext {
+    reply_to: Option<MessageId>,
+    /// The forum topic of the message, the reply has to be sent to the same topic
+    thread_id: Option<ThreadId>,
+    /// Set if the message came through a business account the bot is connected to,
+    /// the reply is then sent on behalf of that account
+    business_connection_id: Option<BusinessConnectionId>,
+}
+
+impl ReplyContext {
+    fn of(message: &Message, reply_to: MessageId) -> Self {
+        Self {
+            reply_to: Some(reply_to),
+            thread_id: message.thread_id.filter(|_| message.is_topic_message),
+            // only the common messages can come through a business connection
+            business_connection_id: match &message.kind {
+                MessageKind::Common(common) => common.business_connection_id.clone(),
+                _ => None,
+            },
+        }
+    }
+
+    /// The context of a message replacing the deleted one,
+    /// replying to what the deleted message replied to
+    fn repost_of(message: &Message) -> Self {
+        Self {
+            reply_to: message.reply_to_message().map(|reply| reply.id),
+            ..Self::of(message, message.id)
+        }
+    }
+
+    fn apply(&self, request: &mut SendMessage) {
+        request.reply_parameters = self.reply_to.map(ReplyParameters::new);
+        request.message_thread_id = self.thread_id;
+        request.business_connection_id = self.business_connection_id.clone();
+    }
+}
+
+/// Send the message, retrying as described in [`retrying`] and counting the retries and errors
+#[instrument(skip_all)]
+async fn send_message_retrying(
+    bot: &BotRequester,
+    config: &BotConfig,
+    metrics: &UptimeMetrics,
+    to: ChatId,
+    context: &ReplyContext,
+    message: &ReplyMessage,
+) -> anyhow::Result<MessageId> //
+{
+    retrying(config, metrics, tokio::time::sleep, || {
+        let mut request = bot.send_message(to, &message.text);
+        context.apply(&mut request);
+        request.entities = (!message.entities.is_empty()).then(|| message.entities.clone());
+        request.reply_markup = message.keyboard.clone().map(ReplyMarkup::InlineKeyboard);
+
+        async move { request.await.map(|sent| sent.id) }
+    })
+    .await
+    .map_err(Into::into)
+}
+
+/// Whether sending failed because the message to reply to was deleted
+fn is_reply_target_missing(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref(),
+        Some(SendError::Request(RequestError::Api(
+            ApiError::MessageToReplyNotFound
+        )))
+    )
+}
+
+/// The delay before the first retry after a network error, doubled for every next one
+const INITIAL_NETWORK_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_NETWORK_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Why sending a message failed
+#[derive(Debug, Error)]
+enum SendError {
+    #[error("failed to send the message after {attempts} attempt(s)")]
+    Exhausted {
+        attempts: u32,
+        #[source]
+        last: RequestError,
+    },
+    #[error("retry delay of {requested:?} exceeds the cap of {cap:?}")]
+    RetryAfterTooLong { requested: Duration, cap: Duration },
+    /// An error that retrying can't fix
+    #[error(transparent)]
+    Request(#[from] RequestError),
+}
+
+/// Attempt `send` at most `config.send_retry_limit` times, waiting with `sleep` between the attempts
+///
+/// The limit is never zero, the config rejects it, so `send` is always attempted.
+/// Network errors are retried with an exponential backoff,
+/// `RetryAfter` after the delay requested by the server.
+/// Every error and retry is counted in `metrics`
+async fn retrying<T, F, Fut, S, SleepFut>(
+    config: &BotConfig,
+    metrics: &UptimeMetrics,
+    mut sleep: S,
+    mut send: F,
+) -> Result<T, SendError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+    S: FnMut(Duration) -> SleepFut,
+    SleepFut: Future<Output = ()>,
+{
+    let mut backoff = INITIAL_NETWORK_BACKOFF;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let e = match send().await {
+            Ok(sent) => return Ok(sent),
+            Err(e) => e,
+        };
+        metrics.record_api_error(&e);
+
+        let delay = match e {
+            RequestError::Network(_) | RequestError::Io(_) => {
+                let delay = backoff;
+                backoff = (backoff * 2).min(MAX_NETWORK_BACKOFF);
+                delay
+            }
+            RequestError::RetryAfter(secs) => {
+                let requested = secs.duration();
+                let Some(delay) = honored_retry_after(requested, config.max_retry_after) else {
+                    warn!(error=%FullErrorDisplay(&e), delay=%secs, "retry delay is too long, giving up");
+                    return Err(SendError::RetryAfterTooLong {
+                        requested,
+                        cap: config.max_retry_after,
+                    });
+                };
+                delay
+            }
+            e => return Err(e.into()),
+        };
+
+        if attempts >= config.send_retry_limit {
+            warn!(error=%FullErrorDisplay(&e), attempts, "error while sending message, giving up");
+            return Err(SendError::Exhausted { attempts, last: e });
+        }
+
+        warn!(error=%FullErrorDisplay(&e), ?delay, "error while sending message, retrying after a delay...");
+        metrics.record_send_retry();
+        sleep(delay).await;
+    }
+}
+
+/// Returns the delay to wait for before retrying,
+/// or None if the server asks to wait longer than the cap
+fn honored_retry_after(requested: Duration, cap: Duration) -> Option<Duration> {
+    (requested <= cap).then_some(requested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::{
+            fake_telegram::{BOT_ID, FakeTelegram},
+            stats::ChatStats,
+        },
+        rulesets::DynamicRuleset,
+    };
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use url::Url;
+
+    /// Build a message from the JSON representation of its content
+    fn message_with(content: serde_json::Value) -> Message {
+        let mut message = json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "Test" },
+            "from": { "id": 1, "is_bot": false, "first_name": "Test" },
+        });
+
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(content.as_object().unwrap().clone());
+
+        serde_json::from_value(message).unwrap()
+    }
+
+    /// A `url` entity covering the first occurrence of `url` in `text`
+    fn url_entity(text: &str, url: &str) -> serde_json::Value {
+        // entity offsets are in UTF-16 code units
+        let offset = text[..text.find(url).unwrap()].encode_utf16().count();
+        json!({ "type": "url", "offset": offset, "length": url.encode_utf16().count() })
+    }
+
+    /// The dependencies of the handlers, with the bot talking to a fake Telegram
+    struct Handlers {
+        telegram: FakeTelegram,
+        bot: BotRequester,
+        config: Arc<BotConfig>,
+        settings: ChatSettingsStore,
+        stats: StatsStore,
+        metrics: UptimeMetrics,
+        replies: ReplyTracker,
+        reply_limiter: ReplyLimiter,
+        maintenance: Maintenance,
+        me: SharedMe,
+        resolver: RedirectResolver,
+    }
+
+    impl Handlers {
+        async fn new(config: BotConfig) -> anyhow::Result<Self> {
+            let telegram = FakeTelegram::start().await?;
+            let bot = telegram.bot();
+
+            Ok(Self {
+                me: SharedMe::new(bot.get_me().await?),
+                settings: ChatSettingsStore::default(),
+                stats: StatsStore::default(),
+                metrics: UptimeMetrics::default(),
+                replies: ReplyTracker::default(),
+                reply_limiter: ReplyLimiter::new(config.replies_per_minute),
+                maintenance: Maintenance::new(
+                    config.maintenance,
+                    config.maintenance_notice_interval,
+                ),
+                resolver: RedirectResolver::new(config.redirect_timeout)?,
+                config: Arc::new(config),
+                bot,
+                telegram,
+            })
+        }
+
+        async fn message(&self, message: Message) -> anyhow::Result<()> {
+            remove_si(
+                self.bot.clone(),
+                message,
+                self.config.clone(),
+                self.settings.clone(),
+                self.stats.clone(),
+                self.metrics.clone(),
+                self.replies.clone(),
+                self.reply_limiter.clone(),
+                self.maintenance.clone(),
+                self.me.clone(),
+                self.resolver.clone(),
+            )
+            .await
+        }
+
+        async fn edited_message(&self, message: Message) -> anyhow::Result<()> {
+            remove_si_edited(
+                self.bot.clone(),
+                message,
+                self.config.clone(),
+                self.settings.clone(),
+                self.stats.clone(),
+                self.metrics.clone(),
+                self.replies.clone(),
+                self.reply_limiter.clone(),
+                self.maintenance.clone(),
+                self.resolver.clone(),
+            )
+            .await
+        }
+    }
+
+    #[tokio::test]
+    async fn uptime_metrics_count_every_message() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig::default()).await?;
+
+        for (id, text) in [
+            "https://youtu.be/video0001?si=abc https://youtu.be/video0002?si=abc",
+            "no links here",
+            "https://youtu.be/video0003?si=abc",
+            "https://youtu.be/video0004?si=abc https://youtu.be/video0005?si=abc https://youtu.be/video0006?si=abc",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            handlers
+                .message(message_with(json!({ "message_id": id, "text": text })))
+                .await?;
+        }
+
+        assert_eq!(
+            handlers.metrics.get(),
+            ChatStats {
+                messages_processed: 4,
+                urls_cleaned: 6,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn edits_of_replies_are_limited() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig {
+            replies_per_minute: Some(2),
+            ..BotConfig::default()
+        })
+        .await?;
+
+        for link in [
+            "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+            "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+            "https://youtu.be/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r",
+        ] {
+            handlers
+                .edited_message(message_with(json!({ "text": link })))
+                .await?;
+        }
+
+        // the reply and one edit, the last edit is over the limit
+        assert_eq!(handlers.telegram.requests_to("sendMessage").len(), 1);
+        assert_eq!(handlers.telegram.requests_to("editMessageText").len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn edits_update_every_message_of_a_split_reply() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig::default()).await?;
+        let links: Vec<_> = (0..150)
+            .map(|i| format!("https://youtu.be/video{i:04}?si=abc"))
+            .collect();
+
+        handlers
+            .message(message_with(json!({ "text": links.join(" ") })))
+            .await?;
+        let sent: Vec<_> = (1001..)
+            .take(handlers.telegram.requests_to("sendMessage").len())
+            .collect();
+        assert!(sent.len() > 1, "the reply is split: {sent:?}");
+
+        // fits in one message now, the rest of the reply is deleted
+        handlers
+            .edited_message(message_with(json!({ "text": links[0] })))
+            .await?;
+        let edits = handlers.telegram.requests_to("editMessageText");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0]["message_id"], sent[0]);
+        let deleted = handlers.telegram.requests_to("deleteMessages");
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0]["message_ids"], json!(sent[1..]));
+
+        handlers
+            .edited_message(message_with(json!({ "text": "no links anymore" })))
+            .await?;
+        let deleted = handlers.telegram.requests_to("deleteMessages");
+        assert_eq!(deleted.len(), 2);
+        assert_eq!(deleted[1]["message_ids"], json!([sent[0]]));
+        assert_eq!(
+            handlers.telegram.requests_to("sendMessage").len(),
+            sent.len()
+        );
+
+        Ok(())
+    }
+
+    /// Collects the formatted logs
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn all_logs_of_a_message_share_its_request_id() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig::default()).await?;
+        // failing once, so sending the reply logs a retry
+        handlers.telegram.respond_once(
+            "sendMessage",
+            json!({
+                "ok": false,
+                "error_code": 429,
+                "description": "Too Many Requests: retry after 0",
+                "parameters": { "retry_after": 0 },
+            }),
+        );
+
+        let logs = LogBuffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_env_filter(tracing_subscriber::EnvFilter::new(
+                "youtube_no_si_redux=debug",
+            ))
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let default = tracing::subscriber::set_default(subscriber);
+
+        handlers
+            .message(message_with(json!({
+                "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+            })))
+            .await?;
+        handlers
+            .message(message_with(json!({
+                "message_id": 2,
+                "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+            })))
+            .await?;
+        drop(default);
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        let ids: Vec<_> = output
+            .lines()
+            .map(|line| {
+                let (_, rest) = line
+                    .split_once("request_id=")
+                    .unwrap_or_else(|| panic!("no request id: {line}"));
+                &rest[..8]
+            })
+            .collect();
+        let mut distinct = ids.clone();
+        distinct.dedup();
+
+        // one id per message, shared by all of its logs
+        assert_eq!(distinct.len(), 2, "{output}");
+        assert_ne!(distinct[0], distinct[1]);
+        let retry = output
+            .lines()
+            .find(|line| line.contains("retrying after a delay"))
+            .expect("the retry is logged");
+        assert!(retry.contains("send_message_retrying"), "{retry}");
+        assert!(
+            retry.contains(&format!("request_id={}", distinct[0])),
+            "{retry}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replies_go_to_the_message_once_the_anchor_is_gone() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig {
+            thread_replies: true,
+            ..BotConfig::default()
+        })
+        .await?;
+        let chat = ChatId(1);
+        handlers
+            .settings
+            .update(chat, |s| s.anchor_message_id = Some(5))
+            .await?;
+        handlers.telegram.respond_once(
+            "sendMessage",
+            json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: message to be replied not found",
+            }),
+        );
+
+        handlers
+            .message(message_with(json!({
+                "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+            })))
+            .await?;
+
+        let sent = handlers.telegram.requests_to("sendMessage");
+        assert_eq!(sent.len(), 2, "{sent:?}");
+        assert_eq!(sent[0]["reply_parameters"]["message_id"], 5);
+        assert_eq!(sent[1]["reply_parameters"]["message_id"], 1);
+        // a new anchor is created for the next reply
+        assert_eq!(handlers.settings.get(chat).await.anchor_message_id, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn caption_urls_are_extracted_for_all_media_types() -> anyhow::Result<()> {
+        let thumbnail = json!({
+            "file_id": "thumb",
+            "file_unique_id": "thumb_unique",
+            "width": 1,
+            "height": 1,
+            "file_size": 1,
+        });
+        // the media have all their fields, messages with media that fail to deserialize
+        // turn into messages without a caption
+        let media = [
+            ("photo", json!([thumbnail])),
+            (
+                "video",
+                json!({
+                    "file_id": "file",
+                    "file_unique_id": "unique",
+                    "width": 1,
+                    "height": 1,
+                    "duration": 1,
+                    "thumbnail": thumbnail,
+                    "cover": [thumbnail],
+                    "start_timestamp": 0,
+                    "file_name": "video.mp4",
+                    "mime_type": "video/mp4",
+                    "file_size": 1,
+                }),
+            ),
+            (
+                "animation",
+                json!({
+                    "file_id": "file",
+                    "file_unique_id": "unique",
+                    "width": 1,
+                    "height": 1,
+                    "duration": 1,
+                    "thumbnail": thumbnail,
+                    "file_name": "animation.mp4",
+                    "mime_type": "video/mp4",
+                    "file_size": 1,
+                }),
+            ),
+            (
+                "audio",
+                json!({
+                    "file_id": "file",
+                    "file_unique_id": "unique",
+                    "duration": 1,
+                    "performer": "Test",
+                    "title": "Test",
+                    "file_name": "audio.mp3",
+                    "mime_type": "audio/mpeg",
+                    "file_size": 1,
+                }),
+            ),
+            (
+                "document",
+                json!({ "file_id": "file", "file_unique_id": "unique", "file_size": 1 }),
+            ),
+        ];
+
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let caption = format!("look at this {link}");
+
+        for (kind, value) in media {
+            let mut content = json!({
+                "caption": caption,
+                "caption_entities": [url_entity(&caption, link)],
+                "show_caption_above_media": false,
+                "has_media_spoiler": false,
+            });
+            content[kind] = value;
+
+            let message = message_with(content);
+
+            let has_media = match kind {
+                "photo" => message.photo().is_some(),
+                "video" => message.video().is_some(),
+                "animation" => message.animation().is_some(),
+                "audio" => message.audio().is_some(),
+                _ => message.document().is_some(),
+            };
+            assert!(has_media, "{kind}: {message:?}");
+            assert_eq!(message.caption(), Some(caption.as_str()), "{kind}");
+
+            let urls: Vec<_> = message_url_iterator(&message).collect();
+            assert_eq!(urls, [Url::parse(link)?], "{kind}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_params_are_named() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r&pp=ygU https://youtu.be/0FwBHrVuMJc?si=abc",
+        }));
+
+        let removed: Vec<_> = cleaned_links(&message, &BotConfig::default(), None)
+            .into_iter()
+            .flat_map(|link| link.removed_params)
+            .collect();
+        assert_eq!(removed, ["si", "pp", "si"]);
+
+        // the parameters of the unwrapped link, not the ones of the share link
+        let message = message_with(json!({
+            "text": "https://t.me/share/url?url=https%3A%2F%2Fyoutu.be%2F0FwBHrVuMJc%3Fsi%3Dabc",
+        }));
+        let config = BotConfig {
+            unwrap_share_links: true,
+            ..BotConfig::default()
+        };
+        let links = cleaned_links(&message, &config, None);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].removed_params, ["si"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn caption_text_links_are_extracted() -> anyhow::Result<()> {
+        let link = "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up";
+        let message = message_with(json!({
+            "photo": [{ "file_id": "file", "file_unique_id": "unique", "width": 1, "height": 1 }],
+            "caption": "a video",
+            "caption_entities": [{ "type": "text_link", "offset": 2, "length": 5, "url": link }],
+        }));
+
+        let urls: Vec<_> = message_url_iterator(&message).collect();
+        assert_eq!(urls, [Url::parse(link)?]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn caption_only_messages_are_cleaned() -> anyhow::Result<()> {
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let message = message_with(json!({
+            "document": { "file_id": "file", "file_unique_id": "unique" },
+            "caption": link,
+            "caption_entities": [url_entity(link, link)],
+        }));
+
+        assert_eq!(message.text(), None);
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/0FwBHrVuMJc")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_links_that_had_tracking_are_replied_with() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://youtu.be/0FwBHrVuMJc https://www.youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r https://youtu.be/FiwMTquj-rQ?t=173",
+        }));
+
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?]
+        );
+
+        // nothing to reply with if none of the links had tracking
+        let message = message_with(json!({
+            "text": "https://youtu.be/0FwBHrVuMJc https://www.youtube.com/watch?v=3foYyPDp0Ho https://youtu.be/FiwMTquj-rQ?t=173",
+        }));
+        assert!(cleaned_urls(&message, &BotConfig::default(), None).is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn edits_update_or_delete_the_tracked_reply() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig::default()).await?;
+
+        handlers
+            .message(message_with(json!({ "text": "no links yet" })))
+            .await?;
+        assert!(handlers.telegram.requests_to("sendMessage").is_empty());
+
+        // the first version with a link gets a reply
+        handlers
+            .edited_message(message_with(json!({
+                "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+            })))
+            .await?;
+        let sent = handlers.telegram.requests_to("sendMessage");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["reply_parameters"]["message_id"], 1);
+
+        // the reply is updated with the new link
+        handlers
+            .edited_message(message_with(json!({
+                "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+            })))
+            .await?;
+        let edits = handlers.telegram.requests_to("editMessageText");
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0]["message_id"], 1001);
+        let text = edits[0]["text"].as_str().unwrap_or_default();
+        assert!(text.contains("https://youtu.be/FiwMTquj-rQ"), "{text}");
+
+        // and deleted once the link has no tracking
+        handlers
+            .edited_message(message_with(
+                json!({ "text": "https://youtu.be/FiwMTquj-rQ" }),
+            ))
+            .await?;
+        let deleted = handlers.telegram.requests_to("deleteMessages");
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0]["message_ids"], json!([1001]));
+
+        assert_eq!(handlers.telegram.requests_to("sendMessage").len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn quoted_links_are_cleaned() -> anyhow::Result<()> {
+        let quoted_link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        let quote = format!("watch {quoted_link}");
+        let message = message_with(json!({
+            "text": "this one",
+            "reply_to_message": {
+                "message_id": 0,
+                "date": 0,
+                "chat": { "id": 1, "type": "private", "first_name": "Test" },
+                "text": format!("earlier text, {quote}"),
+            },
+            "quote": {
+                // relative to the quote, not the quoted message
+                "entities": [url_entity(&quote, quoted_link)],
+                "text": quote,
+                "position": 14,
+                "is_manual": true,
+            },
+        }));
+
+        assert!(message.quote().is_some());
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/FiwMTquj-rQ")?]
+        );
+
+        // quotes without entities are scanned as well
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let message = message_with(json!({
+            "text": link,
+            "entities": [url_entity(link, link)],
+            "quote": { "text": quoted_link, "position": 0, "is_manual": true },
+        }));
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [
+                Url::parse("https://youtu.be/0FwBHrVuMJc")?,
+                Url::parse("https://youtu.be/FiwMTquj-rQ")?,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn forwarded_stories_have_no_urls() {
+        let message = message_with(json!({
+            "story": {
+                "chat": { "id": 2, "type": "private", "first_name": "Poster" },
+                "id": 5,
+            },
+            "forward_origin": {
+                "type": "user",
+                "date": 0,
+                "sender_user": { "id": 2, "is_bot": false, "first_name": "Poster" },
+            },
+        }));
+
+        assert_eq!(message_url_iterator(&message).count(), 0);
+    }
+
+    #[test]
+    fn urls_are_found_in_text_without_entities() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "check this https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce out, and www.youtube.com/watch?v=3foYyPDp0Ho",
+        }));
+
+        let urls: Vec<_> = message_url_iterator(&message).collect();
+        assert_eq!(
+            urls,
+            [
+                Url::parse("https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce")?,
+                Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bare_links_without_scheme_are_cleaned() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "check this youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r out",
+        }));
+
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtube.com/watch?v=3foYyPDp0Ho")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn text_is_not_scanned_when_entities_are_present() -> anyhow::Result<()> {
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let text = format!("{link} and https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r");
+        let message = message_with(json!({
+            "text": text,
+            "entities": [url_entity(&text, link)],
+        }));
+
+        let urls: Vec<_> = message_url_iterator(&message).collect();
+        assert_eq!(urls, [Url::parse(link)?]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn entity_offsets_are_in_utf16_code_units() -> anyhow::Result<()> {
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        // 7 code units for the cyrillic word and the space, 2 for the emoji and 1 for the space
+        let text = format!("привет 🎉 {link}");
+        let message = message_with(json!({
+            "text": text,
+            "entities": [{ "type": "url", "offset": 10, "length": link.len() }],
+        }));
+
+        let urls: Vec<_> = message_url_iterator(&message).collect();
+        assert_eq!(urls, [Url::parse(link)?]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn punctuation_around_links_is_stripped() -> anyhow::Result<()> {
+        let expected = Url::parse("https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce")?;
+        let inputs = [
+            "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce.",
+            "(https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce)",
+            "(https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce).",
+            " https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce\n",
+        ];
+
+        for input in inputs {
+            assert_eq!(try_parse_url(input).as_ref(), Some(&expected), "{input:?}");
+        }
+
+        // balanced parentheses are part of the link
+        let wiki = "https://en.wikipedia.org/wiki/Rust_(programming_language)";
+        assert_eq!(try_parse_url(wiki), Some(Url::parse(wiki)?));
+
+        let message = message_with(json!({
+            "text": "watch this (youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce).",
+        }));
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/0FwBHrVuMJc")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn urls_without_hosts_are_skipped() -> anyhow::Result<()> {
+        let inputs = [
+            "mailto:someone@example.org",
+            "data:text/plain,https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+            "javascript:alert(1)",
+            "file:///home/user/video.mp4",
+            "localhost:8080",
+            "urn:isbn:0451450523",
+        ];
+
+        for input in inputs {
+            assert_eq!(try_parse_url(input), None, "{input}");
+        }
+
+        assert_eq!(
+            try_parse_url("youtu.be/0FwBHrVuMJc"),
+            Some(Url::parse("https://youtu.be/0FwBHrVuMJc")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn non_url_entities_produce_no_urls() {
+        // a link-looking text under every entity kind, none of which should be parsed as a link
+        let text = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let length = text.len();
+        let kinds = [
+            json!({ "type": "bold" }),
+            json!({ "type": "italic" }),
+            json!({ "type": "underline" }),
+            json!({ "type": "strikethrough" }),
+            json!({ "type": "spoiler" }),
+            json!({ "type": "code" }),
+            json!({ "type": "pre", "language": "rust" }),
+            json!({ "type": "mention" }),
+            json!({ "type": "hashtag" }),
+            json!({ "type": "cashtag" }),
+            json!({ "type": "bot_command" }),
+            json!({ "type": "email" }),
+            json!({ "type": "phone_number" }),
+            json!({ "type": "blockquote" }),
+            json!({ "type": "expandable_blockquote" }),
+            json!({ "type": "custom_emoji", "custom_emoji_id": "1" }),
+            json!({
+                "type": "text_mention",
+                "user": { "id": 2, "is_bot": false, "first_name": "Someone" },
+            }),
+        ];
+
+        for mut entity in kinds {
+            entity["offset"] = json!(0);
+            entity["length"] = json!(length);
+
+            let message = message_with(json!({ "text": text, "entities": [entity.clone()] }));
+
+            assert_eq!(message_url_iterator(&message).count(), 0, "{entity}");
+        }
+    }
+
+    #[test]
+    fn urls_are_extracted_from_keyboard_buttons() -> anyhow::Result<()> {
+        let link = "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up";
+        let message = message_with(json!({
+            "text": "New video!",
+            "reply_markup": {
+                "inline_keyboard": [
+                    [{ "text": "Watch", "url": link }],
+                    [{ "text": "Like", "callback_data": "like" }],
+                ],
+            },
+        }));
+
+        let urls: Vec<_> = keyboard_url_iterator(&message).collect();
+        assert_eq!(urls, [Url::parse(link)?]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_links_from_several_sources_are_listed_once() -> anyhow::Result<()> {
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        // the same video with another si cleans to the same link
+        let same_video = "https://youtu.be/0FwBHrVuMJc?si=KuczOyCr1s5_Ou0r";
+        let text = format!("{link} or here");
+        let message = message_with(json!({
+            "text": text,
+            "entities": [
+                url_entity(&text, link),
+                { "type": "text_link", "offset": text.find("here").unwrap(), "length": 4, "url": same_video },
+            ],
+            "reply_markup": {
+                "inline_keyboard": [[{ "text": "Watch", "url": link }]],
+            },
+        }));
+
+        let config = BotConfig {
+            scan_keyboard_urls: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            cleaned_urls(&message, &config, None),
+            [Url::parse("https://youtu.be/0FwBHrVuMJc")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn links_found_by_an_entity_and_by_scanning_are_listed_once() -> anyhow::Result<()> {
+        let link = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        // the link of the text has an entity, the quote has none and is scanned
+        let message = message_with(json!({
+            "text": link,
+            "entities": [url_entity(link, link)],
+            "quote": { "text": format!("see {link}"), "position": 0, "is_manual": true },
+        }));
+
+        assert_eq!(message_url_iterator(&message).count(), 2);
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/0FwBHrVuMJc")?]
+        );
+
+        Ok(())
+    }
+
+    /// A local server redirecting `/short` to a tracked link on `localhost`, returns its port
+    async fn redirecting_server() -> anyhow::Result<u16> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let Ok(len) = stream.read(&mut request).await else {
+                    continue;
+                };
+
+                let response = if request[..len].starts_with(b"HEAD /short ") {
+                    format!(
+                        "HTTP/1.1 302 Found\r\nLocation: http://localhost:{port}/watch?v=FiwMTquj-rQ&si=abc\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    )
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        Ok(port)
+    }
+
+    #[tokio::test]
+    async fn redirects_are_followed_to_tracked_links() -> anyhow::Result<()> {
+        let port = redirecting_server().await?;
+        let config = BotConfig {
+            follow_redirects: true,
+            ..BotConfig::default()
+        };
+        // the local server stands in for YouTube
+        let mut rulesets = config.rulesets.get().to_vec();
+        rulesets.push(DynamicRuleset {
+            name: "local".to_owned(),
+            domains: vec!["localhost".to_owned()],
+            params: vec!["si".to_owned()],
+        });
+        config.rulesets.set(rulesets);
+        let resolver = RedirectResolver::allowing_local(Duration::from_secs(5))?;
+
+        let short = format!("http://127.0.0.1:{port}/short");
+        let cleaned = Url::parse(&format!("http://localhost:{port}/watch?v=FiwMTquj-rQ"))?;
+
+        for text in [
+            short.clone(),
+            // the link the short one redirects to is listed once
+            format!("{short} http://localhost:{port}/watch?v=FiwMTquj-rQ&si=abc"),
+        ] {
+            let message = message_with(json!({ "text": text }));
+            let links = cleaned_links_following_redirects(&message, &config, &resolver, None).await;
+
+            assert_eq!(
+                links.iter().map(|link| &link.cleaned).collect::<Vec<_>>(),
+                [&cleaned],
+                "{text}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn mixed_domain_links_are_cleaned_together() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce and https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123",
+        }));
+
+        let config = BotConfig {
+            frontend_host: Some("yewtu.be".to_owned()),
+            ..Default::default()
+        };
+        let urls = cleaned_urls(&message, &config, config.frontend_host.as_deref());
+
+        // only the YouTube link is rewritten to the frontend
+        assert_eq!(
+            urls,
+            [
+                Url::parse("https://yewtu.be/0FwBHrVuMJc")?,
+                Url::parse("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC")?,
+            ]
+        );
+        assert_eq!(
+            reply_text(&urls, &ReplyFormat::default()).0,
+            "The links without tracking:\nhttps://yewtu.be/0FwBHrVuMJc\nhttps://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn short_links_are_expanded_when_enabled() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173",
+        }));
+
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?]
+        );
+
+        let config = BotConfig {
+            expand_short_links: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            cleaned_urls(&message, &config, None),
+            [Url::parse(
+                "https://www.youtube.com/watch?v=FiwMTquj-rQ&t=173"
+            )?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn share_links_are_unwrapped_when_enabled() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://t.me/share/url?url=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ%3Fsi%3Dabc and https://t.me/durov",
+        }));
+
+        assert!(cleaned_urls(&message, &BotConfig::default(), None).is_empty());
+
+        let config = BotConfig {
+            unwrap_share_links: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            cleaned_urls(&message, &config, None),
+            [Url::parse("https://youtu.be/FiwMTquj-rQ")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn replies_are_in_the_language_of_the_sender() -> anyhow::Result<()> {
+        let urls = [
+            Url::parse("https://youtu.be/FiwMTquj-rQ")?,
+            Url::parse("https://youtu.be/0FwBHrVuMJc")?,
+        ];
+        let format = ReplyFormat {
+            lang: Lang::De,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reply_text(&urls[..1], &format).0,
+            "Der Link ohne Tracking:\nhttps://youtu.be/FiwMTquj-rQ\n"
+        );
+        assert_eq!(
+            reply_text(&urls, &format).0,
+            "Die Links ohne Tracking:\nhttps://youtu.be/FiwMTquj-rQ\nhttps://youtu.be/0FwBHrVuMJc\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn already_clean_links_are_left_out() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://www.youtube.com/watch?v=3foYyPDp0Ho and https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173",
+        }));
+
+        let urls = cleaned_urls(&message, &BotConfig::default(), None);
+        assert_eq!(urls, [Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?]);
+        assert_eq!(
+            reply_text(&urls, &ReplyFormat::default()).0,
+            "The link without tracking:\nhttps://youtu.be/FiwMTquj-rQ?t=173\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn denied_videos_are_left_out() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce https://www.youtube.com/watch?v=3foYyPDp0Ho&si=xyz https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+        }));
+
+        let config = BotConfig {
+            denied_video_ids: ["0FwBHrVuMJc".to_owned(), "3foYyPDp0Ho".to_owned()].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            cleaned_urls(&message, &config, None),
+            [Url::parse("https://youtu.be/FiwMTquj-rQ")?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ordering_links() -> anyhow::Result<()> {
+        let urls = [
+            Url::parse("https://open.spotify.com/track/first")?,
+            Url::parse("https://youtu.be/first")?,
+            Url::parse("https://yewtu.be/rewritten")?,
+            Url::parse("https://open.spotify.com/track/second")?,
+            Url::parse("https://www.youtube.com/watch?v=second")?,
+        ];
+
+        assert_eq!(order_links(&urls, LinkOrder::Document), urls);
+
+        let grouped: Vec<_> = order_links(&urls, LinkOrder::Grouped)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            grouped,
+            [
+                "https://youtu.be/first",
+                "https://www.youtube.com/watch?v=second",
+                "https://open.spotify.com/track/first",
+                "https://open.spotify.com/track/second",
+                "https://yewtu.be/rewritten",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dm_footer_counts_links_cleaned_for_the_user() {
+        let stats = StatsStore::default();
+        let private = message_with(json!({ "text": "hi" }));
+        let config = BotConfig {
+            dm_user_stats: true,
+            ..Default::default()
+        };
+
+        stats.record(private.chat.id, 2);
+        stats.record(private.chat.id, 1);
+
+        assert_eq!(
+            dm_footer(&config, &private, &stats).as_deref(),
+            Some("\nLinks I've cleaned for you: 3\n")
+        );
+        assert_eq!(dm_footer(&BotConfig::default(), &private, &stats), None);
+
+        let mut group = message_with(json!({ "text": "hi" }));
+        group.chat =
+            serde_json::from_value(json!({ "id": -100123, "type": "supergroup", "title": "Test" }))
+                .unwrap();
+        stats.record(group.chat.id, 5);
+        assert_eq!(dm_footer(&config, &group, &stats), None);
+    }
+
+    #[test]
+    fn only_the_first_link_is_cleaned_if_configured() -> anyhow::Result<()> {
+        let first = "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce";
+        let second = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        let message = message_with(json!({ "text": format!("{first} and {second}") }));
+
+        let config = BotConfig::default();
+        assert_eq!(cleaned_urls(&message, &config, None).len(), 2);
+
+        let config = BotConfig {
+            first_link_only: true,
+            ..Default::default()
+        };
+        let urls = cleaned_urls(&message, &config, None);
+        assert_eq!(urls, [Url::parse("https://youtu.be/0FwBHrVuMJc")?]);
+        assert_eq!(
+            reply_text(&urls, &ReplyFormat::default()).0,
+            "The link without tracking:\nhttps://youtu.be/0FwBHrVuMJc\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn links_ending_with_punctuation_are_linked_whole() -> anyhow::Result<()> {
+        let urls = [
+            Url::parse("https://www.youtube.com/watch?v=a_b_c&t=(1)")?,
+            Url::parse("https://www.youtube.com/watch?v=a_b_c")?,
+        ];
+        let (text, entities) = reply_text(&urls, &ReplyFormat::default());
+
+        // the link is on its own line, not merged with the header
+        let line = text.lines().nth(1).unwrap();
+        assert_eq!(line, urls[0].as_str());
+
+        let [entity] = entities.as_slice() else {
+            panic!("expected a single entity, got {entities:?}");
+        };
+        assert_eq!(entity.kind, MessageEntityKind::Url);
+        let start = text.find(line).unwrap();
+        assert_eq!(entity.offset, text[..start].encode_utf16().count());
+        assert_eq!(entity.length, line.encode_utf16().count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncating_for_display() {
+        assert_eq!(
+            truncate_for_display("https://youtu.be/abc", 20),
+            "https://youtu.be/abc"
+        );
+        assert_eq!(
+            truncate_for_display("https://youtu.be/abc", 10),
+            "https://y…"
+        );
+        assert_eq!(truncate_for_display("https://youtu.be/abc", 0), "…");
+    }
+
+    #[test]
+    fn truncated_links_keep_the_full_target() -> anyhow::Result<()> {
+        let short = Url::parse("https://youtu.be/FiwMTquj-rQ")?;
+        let long = Url::parse(
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho&list=PLabcdefghijklmnopqrstuvwxyz&index=12&t=173",
+        )?;
+        let urls = [short.clone(), long.clone()];
+
+        let (text, entities) = reply_text(
+            &urls,
+            &ReplyFormat {
+                max_displayed_len: Some(40),
+                ..Default::default()
+            },
+        );
+
+        let [entity] = entities.as_slice() else {
+            panic!("expected a single entity, got {entities:?}");
+        };
+        assert_eq!(
+            entity.kind,
+            MessageEntityKind::TextLink { url: long.clone() }
+        );
+
+        // the entity covers exactly the truncated link, the text is ASCII apart from the ellipsis
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        let covered = String::from_utf16(&utf16[entity.offset..entity.offset + entity.length])?;
+        assert_eq!(covered, truncate_for_display(long.as_str(), 40));
+        assert!(text.contains(&format!("{short}\n")));
+
+        let config = BotConfig {
+            max_displayed_url_len: Some(40),
+            ..Default::default()
+        };
+        let keyboard = reply_keyboard(&config, &urls).unwrap();
+        let [row] = keyboard.inline_keyboard.as_slice() else {
+            panic!("expected a single row, got {keyboard:?}");
+        };
+        assert_eq!(
+            row[0].kind,
+            InlineKeyboardButtonKind::CopyText(CopyTextButton {
+                text: long.to_string()
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn short_links_are_not_truncated() -> anyhow::Result<()> {
+        let urls = [Url::parse("https://youtu.be/FiwMTquj-rQ")?];
+
+        let (text, entities) = reply_text(
+            &urls,
+            &ReplyFormat {
+                max_displayed_len: Some(100),
+                ..Default::default()
+            },
+        );
+        assert_eq!(text, reply_text(&urls, &ReplyFormat::default()).0);
+        assert!(entities.is_empty());
+
+        let config = BotConfig {
+            max_displayed_url_len: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(reply_keyboard(&config, &urls), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn spoilered_links_are_cleaned() -> anyhow::Result<()> {
+        let link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        let text = format!("ending: {link}");
+        let offset = text.find(link).unwrap();
+        let message = message_with(json!({
+            "text": text,
+            "entities": [
+                { "type": "spoiler", "offset": offset, "length": link.len() },
+                url_entity(&text, link),
+            ],
+        }));
+
+        assert!(has_spoilered_links(&message));
+        assert_eq!(
+            cleaned_urls(&message, &BotConfig::default(), None),
+            [Url::parse("https://youtu.be/FiwMTquj-rQ")?]
+        );
+
+        // a spoiler elsewhere in the message doesn't hide the link
+        let message = message_with(json!({
+            "text": text,
+            "entities": [
+                { "type": "spoiler", "offset": 0, "length": 6 },
+                url_entity(&text, link),
+            ],
+        }));
+        assert!(!has_spoilered_links(&message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn spoilered_reply_hides_every_link() -> anyhow::Result<()> {
+        let urls = [
+            Url::parse("https://youtu.be/FiwMTquj-rQ")?,
+            Url::parse("https://youtu.be/0FwBHrVuMJc")?,
+        ];
+
+        let (text, entities) = reply_text(
+            &urls,
+            &ReplyFormat {
+                spoiler: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(text, reply_text(&urls, &ReplyFormat::default()).0);
+
+        let hidden: Vec<_> = entities
+            .iter()
+            .map(|entity| {
+                assert_eq!(entity.kind, MessageEntityKind::Spoiler);
+                &text[entity.offset..entity.offset + entity.length]
+            })
+            .collect();
+        assert_eq!(
+            hidden,
+            [
+                "https://youtu.be/FiwMTquj-rQ",
+                "https://youtu.be/0FwBHrVuMJc"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn chat_prefix_starts_the_reply() -> anyhow::Result<()> {
+        let urls = [Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?];
+        let format = ReplyFormat {
+            prefix: Some("🧹"),
+            max_displayed_len: Some(20),
+            ..Default::default()
+        };
+
+        let (text, entities) = reply_text(&urls, &format);
+        assert_eq!(
+            text,
+            "🧹 The link without tracking:\nhttps://youtu.be/Fi…\n"
+        );
+
+        // the entity offsets account for the prefix
+        let [entity] = entities.as_slice() else {
+            panic!("expected a single entity, got {entities:?}");
+        };
+        assert_eq!(
+            entity.offset,
+            "🧹 The link without tracking:\n".encode_utf16().count()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn many_links_are_numbered_and_deduplicated() -> anyhow::Result<()> {
+        let message = message_with(json!({
+            "text": "https://youtu.be/aaaaaaaaaaa?si=1 https://youtu.be/bbbbbbbbbbb?si=2 \
+                https://youtu.be/aaaaaaaaaaa?si=3 https://youtu.be/ccccccccccc?si=4 \
+                https://youtu.be/ddddddddddd?si=5",
+        }));
+
+        let urls = cleaned_urls(&message, &BotConfig::default(), None);
+        // the same video pasted twice only appears once, where it was first pasted
+        assert_eq!(
+            urls,
+            [
+                Url::parse("https://youtu.be/aaaaaaaaaaa")?,
+                Url::parse("https://youtu.be/bbbbbbbbbbb")?,
+                Url::parse("https://youtu.be/ccccccccccc")?,
+                Url::parse("https://youtu.be/ddddddddddd")?,
+            ]
+        );
+
+        let format = ReplyFormat {
+            numbered_from: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            reply_text(&urls, &format).0,
+            "The links without tracking:\n\
+            1. https://youtu.be/aaaaaaaaaaa\n\
+            2. https://youtu.be/bbbbbbbbbbb\n\
+            3. https://youtu.be/ccccccccccc\n\
+            4. https://youtu.be/ddddddddddd\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_continue_across_split_replies() -> anyhow::Result<()> {
+        let urls = (0..300)
+            .map(|i| Url::parse(&format!("https://www.youtube.com/watch?v=video{i:06}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let format = ReplyFormat {
+            numbered_from: Some(1),
+            ..Default::default()
+        };
+
+        let messages = reply_messages(&BotConfig::default(), &urls, &format, None);
+        assert!(messages.len() > 1);
+
+        let first_in_second = messages[0].text.matches("https://").count() + 1;
+        assert!(
+            messages[1]
+                .text
+                .contains(&format!("\n{first_in_second}. https://")),
+            "{}",
+            messages[1].text
+        );
+
+        for message in &messages {
+            assert!(message.text.encode_utf16().count() <= MAX_MESSAGE_LEN);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_replies_are_bounded() -> anyhow::Result<()> {
+        let urls = (0..1000)
+            .map(|i| Url::parse(&format!("https://www.youtube.com/watch?v=video{i:06}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let config = BotConfig {
+            max_reply_messages: 3,
+            ..Default::default()
+        };
+
+        let messages = reply_messages(&config, &urls, &ReplyFormat::default(), Some("footer\n"));
+        assert_eq!(messages.len(), 3);
+
+        for message in &messages {
+            assert!(message.text.encode_utf16().count() <= MAX_MESSAGE_LEN);
+        }
+
+        let listed: usize = messages
+            .iter()
+            .map(|message| message.text.matches("https://").count())
+            .sum();
+        let last = &messages[2].text;
+        assert!(
+            last.ends_with(&format!("…and {} more\nfooter\n", urls.len() - listed)),
+            "{last}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn short_replies_are_a_single_message() -> anyhow::Result<()> {
+        let urls = [Url::parse("https://youtu.be/FiwMTquj-rQ")?];
+        let format = ReplyFormat {
+            prefix: Some("🧹"),
+            ..Default::default()
+        };
+
+        let messages = reply_messages(&BotConfig::default(), &urls, &format, None);
+        assert_eq!(
+            messages,
+            [ReplyMessage {
+                text: "🧹 The link without tracking:\nhttps://youtu.be/FiwMTquj-rQ\n".to_owned(),
+                entities: Vec::new(),
+                keyboard: None,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn link_keyboard_opens_the_cleaned_url() -> anyhow::Result<()> {
+        let url = Url::parse("https://youtu.be/0FwBHrVuMJc?t=173")?;
+        let keyboard = link_keyboard(&url);
+
+        let [row] = keyboard.inline_keyboard.as_slice() else {
+            panic!("expected a single row, got {keyboard:?}");
+        };
+        let [button] = row.as_slice() else {
+            panic!("expected a single button, got {row:?}");
+        };
+
+        assert_eq!(button.text, LINK_BUTTON_TEXT);
+        assert_eq!(button.kind, InlineKeyboardButtonKind::Url(url));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cosmetic_changes_are_below_threshold() -> anyhow::Result<()> {
+        let config = BotConfig::default();
+        let cleaner = LinkCleaner::new(&config, None);
+        let clean = |link: &str| {
+            cleaner
+                .clean(Url::parse(link).unwrap())
+                .expect("the link has tracking")
+        };
+
+        let short = clean("https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce");
+        let short_len = short.cleaned.as_str().len();
+
+        assert!(is_cosmetic_change(&short, short_len + 1));
+        assert!(!is_cosmetic_change(&short, short_len));
+
+        // removing other tracking along with si is not cosmetic, even if the link ends up bare
+        let more_tracking = clean("https://youtu.be/0FwBHrVuMJc?si=abc&feature=shared");
+        assert_eq!(more_tracking.cleaned, short.cleaned);
+        assert!(!is_cosmetic_change(&more_tracking, 100));
+
+        // links keeping other params or in the long form are not cosmetic
+        let with_timestamp = clean("https://youtu.be/0FwBHrVuMJc?si=abc&t=173");
+        let long_form = clean("https://www.youtube.com/watch?v=3foYyPDp0Ho&si=abc");
+        assert!(!is_cosmetic_change(&with_timestamp, 100));
+        assert!(!is_cosmetic_change(&long_form, 100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reply_keeps_both_the_topic_and_the_business_connection() {
+        let message = message_with(json!({
+            "chat": { "id": -100123, "type": "supergroup", "title": "Test", "is_forum": true },
+            "message_thread_id": 7,
+            "is_topic_message": true,
+            "business_connection_id": "connection",
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+        }));
+
+        let context = ReplyContext::of(&message, message.id);
+        let mut request = SendMessage::new(message.chat.id, "The link without tracking:");
+        context.apply(&mut request);
+
+        assert_eq!(request.message_thread_id, Some(ThreadId(MessageId(7))));
+        assert_eq!(
+            request.business_connection_id,
+            Some(BusinessConnectionId("connection".to_owned()))
+        );
+        assert_eq!(
+            request.reply_parameters.map(|reply| reply.message_id),
+            Some(message.id)
+        );
+    }
+
+    #[test]
+    fn business_messages_are_answered_through_their_connection() {
+        let message = message_with(json!({
+            "business_connection_id": "connection",
+            "sender_business_bot": { "id": 2, "is_bot": true, "first_name": "Business" },
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+        }));
+
+        assert!(matches!(message.kind, MessageKind::Common(_)));
+        assert_eq!(
+            ReplyContext::of(&message, message.id).business_connection_id,
+            Some(BusinessConnectionId("connection".to_owned()))
+        );
+    }
+
+    #[test]
+    fn replies_outside_topics_have_no_thread() {
+        // replies to a message have the thread id of the replied message outside of forums
+        let message = message_with(json!({
+            "message_thread_id": 3,
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+        }));
+
+        let context = ReplyContext::of(&message, message.id);
+        assert_eq!(context.thread_id, None);
+        assert_eq!(context.business_connection_id, None);
+    }
+
+    #[test]
+    fn reposts_reply_to_what_the_original_replied_to() {
+        let message = message_with(json!({
+            "chat": { "id": -100123, "type": "supergroup", "title": "Test" },
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+        }));
+        assert_eq!(ReplyContext::repost_of(&message).reply_to, None);
+
+        let message = message_with(json!({
+            "chat": { "id": -100123, "type": "supergroup", "title": "Test" },
+            "text": "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+            "reply_to_message": {
+                "message_id": 5,
+                "date": 0,
+                "chat": { "id": -100123, "type": "supergroup", "title": "Test" },
+                "text": "what was that video?",
+            },
+        }));
+        assert_eq!(
+            ReplyContext::repost_of(&message).reply_to,
+            Some(MessageId(5))
+        );
+    }
+
+    /// The text of a repost and the links cleaned in it
+    fn reposted(message: &Message) -> Option<(String, Vec<MessageEntity>)> {
+        repost_text(
+            message,
+            &cleaned_links(message, &BotConfig::default(), None),
+        )
+    }
+
+    #[test]
+    fn reposts_are_attributed_to_the_author() {
+        let link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+
+        let message = message_with(json!({
+            "from": { "id": 1, "is_bot": false, "first_name": "Test", "username": "test_user" },
+            "text": link,
+            "entities": [url_entity(link, link)],
+        }));
+        assert_eq!(
+            reposted(&message),
+            Some((
+                "Shared by @test_user:\nhttps://youtu.be/FiwMTquj-rQ".to_owned(),
+                vec![MessageEntity::new(MessageEntityKind::Url, 22, 28)]
+            ))
+        );
+
+        // users without a username are mentioned by name
+        let message = message_with(json!({
+            "from": { "id": 1, "is_bot": false, "first_name": "Tëst", "last_name": "User" },
+            "text": link,
+            "entities": [url_entity(link, link)],
+        }));
+        let (text, entities) = reposted(&message).unwrap();
+        assert_eq!(text, "Shared by Tëst User:\nhttps://youtu.be/FiwMTquj-rQ");
+        assert_eq!(
+            entities,
+            [
+                MessageEntity::text_mention(message.from.clone().unwrap(), 10, 9),
+                MessageEntity::new(MessageEntityKind::Url, 21, 28),
+            ]
+        );
+
+        // anonymous admins post on behalf of the group
+        let message = message_with(json!({
+            "chat": { "id": -100123, "type": "supergroup", "title": "Cats" },
+            "sender_chat": { "id": -100123, "type": "supergroup", "title": "Cats" },
+            "text": link,
+            "entities": [url_entity(link, link)],
+        }));
+        assert_eq!(
+            reposted(&message).map(|(text, _)| text),
+            Some("Shared by Cats:\nhttps://youtu.be/FiwMTquj-rQ".to_owned())
+        );
+    }
+
+    #[test]
+    fn reposts_keep_the_text_with_the_links_cleaned() -> anyhow::Result<()> {
+        let link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        let hidden = "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r";
+        let text = format!("Watch {link} and this");
+        let message = message_with(json!({
+            "from": { "id": 1, "is_bot": false, "first_name": "Test", "username": "test_user" },
+            "text": text,
+            "entities": [
+                url_entity(&text, link),
+                { "type": "text_link", "offset": 59, "length": 4, "url": hidden },
+                { "type": "bold", "offset": 55, "length": 8 },
+            ],
+        }));
+
+        // the link is 20 characters shorter, the prefix is 22 characters long
+        assert_eq!(
+            reposted(&message),
+            Some((
+                "Shared by @test_user:\nWatch https://youtu.be/FiwMTquj-rQ and this".to_owned(),
+                vec![
+                    MessageEntity::new(MessageEntityKind::Url, 28, 28),
+                    MessageEntity::text_link(
+                        Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?,
+                        61,
+                        4
+                    ),
+                    MessageEntity::bold(57, 8),
+                ]
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_plain_text_with_link_entities_is_reposted() {
+        let link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+
+        let messages = [
+            // the media would be lost
+            json!({
+                "photo": [{ "file_id": "file", "file_unique_id": "unique", "width": 1, "height": 1 }],
+                "caption": link,
+                "caption_entities": [url_entity(link, link)],
+            }),
+            // the quoted link isn't in the text
+            json!({
+                "text": link,
+                "entities": [url_entity(link, link)],
+                "quote": {
+                    "text": link,
+                    "entities": [url_entity(link, link)],
+                    "position": 0,
+                    "is_manual": true,
+                },
+            }),
+            // the keyboard would be lost
+            json!({
+                "text": link,
+                "entities": [url_entity(link, link)],
+                "reply_markup": {
+                    "inline_keyboard": [[{ "text": "Like", "callback_data": "like" }]],
+                },
+            }),
+            // the link was found by scanning the text, there's no entity to replace
+            json!({ "text": link }),
+        ];
+
+        for message in messages {
+            assert_eq!(reposted(&message_with(message.clone())), None, "{message}");
+        }
+    }
+
+    /// A message with a tracked link in a group where the bot can delete messages
+    fn repostable(handlers: &Handlers) -> Message {
+        handlers.telegram.respond_once(
+            "getChatMember",
+            json!({
+                "ok": true,
+                "result": {
+                    "status": "administrator",
+                    "user": { "id": BOT_ID, "is_bot": true, "first_name": "Fake" },
+                    "can_be_edited": false,
+                    "is_anonymous": false,
+                    "can_manage_chat": true,
+                    "can_delete_messages": true,
+                    "can_manage_video_chats": false,
+                    "can_restrict_members": false,
+                    "can_promote_members": false,
+                    "can_change_info": false,
+                    "can_invite_users": true,
+                    "can_post_stories": false,
+                    "can_edit_stories": false,
+                    "can_delete_stories": false,
+                },
+            }),
+        );
+
+        let link = "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r";
+        message_with(json!({
+            "chat": { "id": -100123, "type": "supergroup", "title": "Test" },
+            "from": { "id": 1, "is_bot": false, "first_name": "Test", "username": "test_user" },
+            "text": link,
+            "entities": [url_entity(link, link)],
+        }))
+    }
+
+    #[tokio::test]
+    async fn the_original_is_deleted_after_the_repost_is_sent() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig {
+            repost_links: true,
+            ..BotConfig::default()
+        })
+        .await?;
+
+        handlers.message(repostable(&handlers)).await?;
+
+        let methods: Vec<_> = handlers
+            .telegram
+            .requests()
+            .into_iter()
+            .map(|request| request.method.to_ascii_lowercase())
+            .filter(|method| method != "getme")
+            .collect();
+        assert_eq!(methods, ["getchatmember", "sendmessage", "deletemessage"]);
+        assert_eq!(
+            handlers.telegram.requests_to("sendMessage")[0]["text"],
+            "Shared by @test_user:\nhttps://youtu.be/FiwMTquj-rQ"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_original_is_kept_if_the_repost_fails() -> anyhow::Result<()> {
+        let handlers = Handlers::new(BotConfig {
+            repost_links: true,
+            ..BotConfig::default()
+        })
+        .await?;
+        handlers.telegram.respond_once(
+            "sendMessage",
+            json!({ "ok": false, "error_code": 400, "description": "Bad Request: message is too long" }),
+        );
+
+        handlers.message(repostable(&handlers)).await?;
+
+        // the bot replies instead
+        assert!(handlers.telegram.requests_to("deleteMessage").is_empty());
+        let sent = handlers.telegram.requests_to("sendMessage");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1]["reply_parameters"]["message_id"], 1);
+
+        Ok(())
+    }
+
+    fn network_error() -> RequestError {
+        RequestError::Io(Arc::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        )))
+    }
+
+    #[tokio::test]
+    async fn network_errors_are_retried_with_growing_delays() -> anyhow::Result<()> {
+        let config = BotConfig::default();
+        let mut delays = Vec::new();
+        let mut attempts = 0;
+
+        let sent = retrying(
+            &config,
+            &UptimeMetrics::default(),
+            |delay| {
+                delays.push(delay);
+                std::future::ready(())
+            },
+            || {
+                attempts += 1;
+                std::future::ready(if attempts <= 4 {
+                    Err(network_error())
+                } else {
+                    Ok(MessageId(attempts))
+                })
+            },
+        )
+        .await?;
+
+        assert_eq!(sent, MessageId(5));
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_limit() {
+        let config = BotConfig {
+            send_retry_limit: 10,
+            ..BotConfig::default()
+        };
+        let metrics = UptimeMetrics::default();
+        let mut delays = Vec::new();
+        let mut attempts = 0;
+
+        let result = retrying(
+            &config,
+            &metrics,
+            |delay| {
+                delays.push(delay);
+                std::future::ready(())
+            },
+            || {
+                attempts += 1;
+                std::future::ready(Err::<MessageId, _>(network_error()))
+            },
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 10);
+        // the backoff stops growing at the cap
+        assert!(delays.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(delays.last(), Some(&MAX_NETWORK_BACKOFF));
+        // every error is counted, the last one isn't retried
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("youtube_no_si_send_retries_total 9\n"),
+            "{rendered}"
+        );
+        assert!(
+            rendered.contains("youtube_no_si_api_errors_total{kind=\"io\"} 10\n"),
+            "{rendered}"
+        );
+    }
+
+    #[tokio::test]
+    async fn immediate_success_is_not_retried() -> anyhow::Result<()> {
+        let config = BotConfig::default();
+        let mut delays = Vec::new();
+        let mut attempts = 0;
+
+        let sent = retrying(
+            &config,
+            &UptimeMetrics::default(),
+            |delay| {
+                delays.push(delay);
+                std::future::ready(())
+            },
+            || {
+                attempts += 1;
+                std::future::ready(Ok(MessageId(42)))
+            },
+        )
+        .await?;
+
+        assert_eq!(sent, MessageId(42));
+        assert_eq!(attempts, 1);
+        assert!(delays.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exhausted_retries_keep_the_last_error() {
+        // the config rejects a zero limit, but it would still mean one attempt
+        for (limit, expected_attempts) in [(3, 3), (0, 1)] {
+            let config = BotConfig {
+                send_retry_limit: limit,
+                ..BotConfig::default()
+            };
+
+            let result = retrying(
+                &config,
+                &UptimeMetrics::default(),
+                |_| std::future::ready(()),
+                || std::future::ready(Err::<MessageId, _>(network_error())),
+            )
+            .await;
+
+            assert!(
+                matches!(
+                    result,
+                    Err(SendError::Exhausted {
+                        attempts,
+                        last: RequestError::Io(_)
+                    }) if attempts == expected_attempts
+                ),
+                "{limit}: {result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_that_cant_be_retried_are_returned_right_away() {
+        let config = BotConfig::default();
+        let mut attempts = 0;
+
+        let result = retrying(
+            &config,
+            &UptimeMetrics::default(),
+            |_| std::future::ready(()),
+            || {
+                attempts += 1;
+                std::future::ready(Err::<MessageId, _>(RequestError::Api(
+                    ApiError::MessageNotModified,
+                )))
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(SendError::Request(_))), "{result:?}");
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn huge_retry_after_is_capped() {
+        let cap = Duration::from_secs(60);
+
+        assert_eq!(
+            honored_retry_after(Duration::from_secs(5), cap),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(honored_retry_after(cap, cap), Some(cap));
+        assert_eq!(
+            honored_retry_after(Duration::from_secs(60 * 60 * 24), cap),
+            None
+        );
     }
 }
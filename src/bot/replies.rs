@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use teloxide::types::{ChatId, MessageId};
+
+/// How many replies are remembered before the oldest ones are forgotten
+const TRACKED_REPLIES_LIMIT: usize = 4096;
+
+/// What to do with the bot's reply to a message
+///
+/// A reply split into several messages is edited or deleted as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplyAction {
+    /// Send a new reply
+    Send,
+    /// Edit the messages of the reply sent for an earlier version of the message
+    Edit(Vec<MessageId>),
+    /// Delete the messages of the reply sent for an earlier version of the message,
+    /// it's no longer needed
+    Delete(Vec<MessageId>),
+    /// Nothing to reply and nothing to clean up
+    Nothing,
+}
+
+/// Remembers which message the bot replied to with which reply,
+/// so the reply can be updated when the user edits their message
+#[derive(Debug, Clone, Default)]
+pub struct ReplyTracker {
+    inner: Arc<Mutex<TrackerInner>>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerInner {
+    /// The messages of every reply, in the order they were sent
+    replies: HashMap<(ChatId, MessageId), Vec<MessageId>>,
+    /// Insertion order of the keys, to forget the oldest replies first
+    order: VecDeque<(ChatId, MessageId)>,
+}
+
+impl ReplyTracker {
+    /// Decide what to do with the reply to the message,
+    /// given whether the bot has anything to say about its current version
+    ///
+    /// Forgets the reply if it should be deleted
+    pub fn action(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        has_response: bool,
+    ) -> ReplyAction {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (chat_id, message_id);
+
+        match (inner.replies.get(&key).cloned(), has_response) {
+            (None, true) => ReplyAction::Send,
+            (None, false) => ReplyAction::Nothing,
+            (Some(reply), true) => ReplyAction::Edit(reply),
+            (Some(reply), false) => {
+                inner.replies.remove(&key);
+                inner.order.retain(|tracked| *tracked != key);
+                ReplyAction::Delete(reply)
+            }
+        }
+    }
+
+    /// Remember the messages of the reply sent to the message, replacing the ones of its earlier version
+    pub fn track(&self, chat_id: ChatId, message_id: MessageId, reply: Vec<MessageId>) {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (chat_id, message_id);
+
+        if inner.replies.insert(key, reply).is_none() {
+            inner.order.push_back(key);
+        }
+
+        while inner.order.len() > TRACKED_REPLIES_LIMIT {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.replies.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: ChatId = ChatId(1);
+    const MESSAGE: MessageId = MessageId(10);
+    const REPLY: MessageId = MessageId(11);
+    const SECOND_REPLY: MessageId = MessageId(12);
+
+    #[test]
+    fn new_messages_get_a_reply() {
+        let replies = ReplyTracker::default();
+
+        assert_eq!(replies.action(CHAT, MESSAGE, true), ReplyAction::Send);
+        assert_eq!(replies.action(CHAT, MESSAGE, false), ReplyAction::Nothing);
+    }
+
+    #[test]
+    fn edited_message_with_links_edits_the_reply() {
+        let replies = ReplyTracker::default();
+        replies.track(CHAT, MESSAGE, vec![REPLY]);
+
+        assert_eq!(
+            replies.action(CHAT, MESSAGE, true),
+            ReplyAction::Edit(vec![REPLY])
+        );
+        // the reply is still tracked for further edits
+        assert_eq!(
+            replies.action(CHAT, MESSAGE, true),
+            ReplyAction::Edit(vec![REPLY])
+        );
+        // replies are tracked per chat
+        assert_eq!(replies.action(ChatId(2), MESSAGE, true), ReplyAction::Send);
+    }
+
+    #[test]
+    fn edited_message_without_links_deletes_the_reply() {
+        let replies = ReplyTracker::default();
+        replies.track(CHAT, MESSAGE, vec![REPLY]);
+
+        assert_eq!(
+            replies.action(CHAT, MESSAGE, false),
+            ReplyAction::Delete(vec![REPLY])
+        );
+        // the deleted reply is forgotten, adding links back sends a new one
+        assert_eq!(replies.action(CHAT, MESSAGE, false), ReplyAction::Nothing);
+        assert_eq!(replies.action(CHAT, MESSAGE, true), ReplyAction::Send);
+    }
+
+    #[test]
+    fn all_messages_of_a_split_reply_are_tracked() {
+        let replies = ReplyTracker::default();
+        replies.track(CHAT, MESSAGE, vec![REPLY, SECOND_REPLY]);
+
+        assert_eq!(
+            replies.action(CHAT, MESSAGE, true),
+            ReplyAction::Edit(vec![REPLY, SECOND_REPLY])
+        );
+
+        // the edited reply fits in one message now
+        replies.track(CHAT, MESSAGE, vec![REPLY]);
+        assert_eq!(
+            replies.action(CHAT, MESSAGE, false),
+            ReplyAction::Delete(vec![REPLY])
+        );
+    }
+
+    #[test]
+    fn oldest_replies_are_forgotten() {
+        let replies = ReplyTracker::default();
+
+        for id in 0..=TRACKED_REPLIES_LIMIT as i32 {
+            replies.track(CHAT, MessageId(id), vec![REPLY]);
+        }
+
+        assert_eq!(replies.action(CHAT, MessageId(0), true), ReplyAction::Send);
+        assert_eq!(
+            replies.action(CHAT, MessageId(TRACKED_REPLIES_LIMIT as i32), true),
+            ReplyAction::Edit(vec![REPLY])
+        );
+    }
+}
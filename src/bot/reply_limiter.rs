@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::types::ChatId;
+
+use crate::clock::SharedClock;
+
+/// The allowance of a chat is refilled over this period
+const REFILL_PERIOD: Duration = Duration::from_secs(60);
+/// Chats with a full allowance are forgotten once more than this many are tracked
+const MAX_TRACKED_CHATS: usize = 4096;
+
+/// Limits how many replies the bot sends to each chat per minute,
+/// so busy groups don't get it flood-banned by Telegram
+///
+/// A token bucket per chat: a burst of up to the limit is allowed,
+/// then replies are allowed as the allowance refills
+#[derive(Debug, Clone)]
+pub struct ReplyLimiter {
+    /// None if the replies are not limited
+    inner: Option<Arc<LimiterInner>>,
+}
+
+#[derive(Debug)]
+struct LimiterInner {
+    per_minute: u32,
+    buckets: Mutex<HashMap<ChatId, Bucket>>,
+    clock: SharedClock,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl ReplyLimiter {
+    pub fn new(per_minute: Option<u32>) -> Self {
+        Self::with_clock(per_minute, SharedClock::default())
+    }
+
+    pub fn with_clock(per_minute: Option<u32>, clock: SharedClock) -> Self {
+        Self {
+            inner: per_minute.map(|per_minute| {
+                Arc::new(LimiterInner {
+                    per_minute,
+                    buckets: Mutex::default(),
+                    clock,
+                })
+            }),
+        }
+    }
+
+    /// Take `messages` from the chat's allowance, all or none of them
+    ///
+    /// Returns false if there's not enough allowance left, the messages shouldn't be sent then.
+    /// More messages than the limit take the whole allowance, so they can still be sent
+    /// once it's full
+    pub fn try_acquire(&self, chat_id: ChatId, messages: usize) -> bool {
+        let Some(inner) = &self.inner else {
+            return true;
+        };
+
+        let now = inner.clock.now();
+        let capacity = f64::from(inner.per_minute);
+        let refill_rate = capacity / REFILL_PERIOD.as_secs_f64();
+        let mut buckets = inner.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_TRACKED_CHATS {
+            buckets.retain(|_, bucket| bucket.refilled(now, refill_rate, capacity) < capacity);
+        }
+
+        let bucket = buckets.entry(chat_id).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(now, refill_rate, capacity);
+        bucket.updated = now;
+
+        let cost = (messages as f64).min(capacity);
+        if bucket.tokens < cost {
+            return false;
+        }
+
+        bucket.tokens -= cost;
+        true
+    }
+}
+
+impl Bucket {
+    /// The tokens in the bucket at `now`, at most `capacity`
+    fn refilled(&self, now: Instant, refill_rate: f64, capacity: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * refill_rate).min(capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    const CHAT: ChatId = ChatId(1);
+
+    fn limiter(per_minute: u32) -> (ReplyLimiter, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::default());
+        let limiter = ReplyLimiter::with_clock(Some(per_minute), SharedClock::new(clock.clone()));
+        (limiter, clock)
+    }
+
+    #[test]
+    fn bursts_in_one_chat_are_throttled() {
+        let (limiter, clock) = limiter(20);
+
+        let sent = (0..50).filter(|_| limiter.try_acquire(CHAT, 1)).count();
+        assert_eq!(sent, 20);
+
+        // other chats have their own allowance, and clones share the state
+        assert!(limiter.clone().try_acquire(ChatId(2), 1));
+        assert!(!limiter.clone().try_acquire(CHAT, 1));
+
+        // one reply is allowed every 3 seconds as the allowance refills
+        clock.advance(Duration::from_secs(3));
+        assert!(limiter.try_acquire(CHAT, 1));
+        assert!(!limiter.try_acquire(CHAT, 1));
+
+        // the allowance refills fully after a minute, but not beyond
+        clock.advance(Duration::from_secs(10 * 60));
+        let sent = (0..50).filter(|_| limiter.try_acquire(CHAT, 1)).count();
+        assert_eq!(sent, 20);
+    }
+
+    #[test]
+    fn split_replies_are_sent_whole_or_not_at_all() {
+        let (limiter, _clock) = limiter(5);
+
+        assert!(limiter.try_acquire(CHAT, 3));
+        assert!(!limiter.try_acquire(CHAT, 3));
+        assert!(limiter.try_acquire(CHAT, 2));
+    }
+
+    #[test]
+    fn replies_over_the_limit_take_the_whole_allowance() {
+        let (limiter, clock) = limiter(5);
+
+        assert!(limiter.try_acquire(CHAT, 8));
+        assert!(!limiter.try_acquire(CHAT, 1));
+
+        // not before the allowance is full again
+        clock.advance(Duration::from_secs(30));
+        assert!(!limiter.try_acquire(CHAT, 8));
+        clock.advance(Duration::from_secs(30));
+        assert!(limiter.try_acquire(CHAT, 8));
+    }
+
+    #[test]
+    fn unlimited_without_a_limit() {
+        let limiter = ReplyLimiter::new(None);
+
+        assert!((0..1000).all(|_| limiter.try_acquire(CHAT, 1)));
+    }
+}
@@ -0,0 +1,49 @@
+use std::{
+    fmt::Display,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Starting the counter at a different value on every run,
+/// so the ids from different runs are unlikely to collide in the logs
+static NEXT_ID: LazyLock<AtomicU32> = LazyLock::new(|| {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+
+    AtomicU32::new(nanos)
+});
+
+/// A short id attached to the span of a handler, to correlate all logs of processing one update
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(u32);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_short_and_distinct() {
+        let first = RequestId::generate();
+        let second = RequestId::generate();
+
+        assert_ne!(first, second);
+        assert_eq!(first.to_string().len(), 8);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::ensure;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use super::BotRequester;
+use crate::rulesets::{DynamicRuleset, RulesetStripper, SharedRulesets, validate_rulesets};
+
+/// The largest rulesets response that is parsed, in bytes
+const MAX_RULESETS_LEN: usize = 1024 * 1024;
+/// Links the fetched rulesets must still clean, a blocklist without the YouTube rule
+/// would make the bot stop doing what it's for
+const REQUIRED_LINKS: &[&str] = &[
+    "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r",
+    "https://www.youtube.com/watch?v=FiwMTquj-rQ&si=KuczOyCr1s5_Ou0r",
+];
+
+/// Fetch the rulesets from `url` every `interval` starting right away, never returns
+///
+/// The rulesets in use are kept if fetching or validating the new ones fails
+#[instrument(skip_all, fields(%url))]
+pub async fn refresh_periodically(
+    rulesets: SharedRulesets,
+    bot: BotRequester,
+    url: Url,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        match apply_fetched(&rulesets, fetch(&bot, &url).await) {
+            Ok(count) => info!(count, "updated the rulesets"),
+            Err(e) => {
+                warn!(error = %e, "failed to update the rulesets, keeping the last good ones")
+            }
+        }
+    }
+}
+
+async fn fetch(bot: &BotRequester, url: &Url) -> anyhow::Result<String> {
+    let mut response = bot
+        .client()
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    // read in chunks, so a huge response is dropped before it's all in memory
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        ensure!(
+            body.len() + chunk.len() <= MAX_RULESETS_LEN,
+            "the rulesets are larger than {MAX_RULESETS_LEN} bytes"
+        );
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8(body)?)
+}
+
+/// Swap the fetched rulesets in if they are valid, returns how many there are
+fn apply_fetched(
+    rulesets: &SharedRulesets,
+    fetched: anyhow::Result<String>,
+) -> anyhow::Result<usize> {
+    let parsed = parse_rulesets(&fetched?)?;
+    let count = parsed.len();
+    rulesets.set(parsed);
+
+    Ok(count)
+}
+
+/// Parse a JSON array of rulesets, e.g.
+/// `[{"name": "youtube", "domains": ["youtu.be"], "params": ["si"]}]`
+fn parse_rulesets(json: &str) -> anyhow::Result<Vec<DynamicRuleset>> {
+    let rulesets: Vec<DynamicRuleset> = serde_json::from_str(json)?;
+    validate_rulesets(&rulesets)?;
+
+    let stripper = RulesetStripper(Arc::from(rulesets.as_slice()));
+    for link in REQUIRED_LINKS {
+        let removed = stripper
+            .strip(Url::parse(link)?)
+            .map(|cleaned| cleaned.removed_params);
+        ensure!(
+            removed.is_some_and(|removed| removed.iter().any(|param| param == "si")),
+            "the rulesets don't remove `si` from {link}"
+        );
+    }
+
+    Ok(rulesets)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    const RULESETS_JSON: &str = r#"[
+        { "name": "youtube", "domains": ["youtu.be", "www.youtube.com"], "params": ["si", "pp"] },
+        { "name": "example", "domains": ["example.org"], "params": ["ref"] }
+    ]"#;
+
+    #[test]
+    fn parsing_rulesets() -> anyhow::Result<()> {
+        let rulesets = parse_rulesets(RULESETS_JSON)?;
+
+        assert_eq!(rulesets.len(), 2);
+        assert_eq!(rulesets[0].name, "youtube");
+        assert_eq!(rulesets[0].domains, ["youtu.be", "www.youtube.com"]);
+        assert_eq!(rulesets[1].params, ["ref"]);
+
+        for invalid in [
+            "",
+            "{}",
+            "[]",
+            r#"[{ "name": "youtube", "domains": ["youtu.be"] }]"#,
+            r#"[{ "name": "youtube", "domains": ["youtu.be"], "params": [] }]"#,
+            // the YouTube rule is missing
+            r#"[{ "name": "example", "domains": ["example.org"], "params": ["ref"] }]"#,
+            r#"[{ "name": "youtube", "domains": ["youtu.be", "www.youtube.com"], "params": ["pp"] }]"#,
+            r#"[{ "name": "youtube", "domains": ["youtu.be"], "params": ["si"] }]"#,
+        ] {
+            assert!(parse_rulesets(invalid).is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_good_rulesets_are_kept_on_errors() -> anyhow::Result<()> {
+        let rulesets = SharedRulesets::default();
+
+        assert_eq!(apply_fetched(&rulesets, Ok(RULESETS_JSON.to_owned()))?, 2);
+        let good = rulesets.get();
+        assert_eq!(good.len(), 2);
+
+        assert!(apply_fetched(&rulesets, Err(anyhow!("connection refused"))).is_err());
+        assert!(apply_fetched(&rulesets, Ok("not json".to_owned())).is_err());
+        assert!(apply_fetched(&rulesets, Ok("[]".to_owned())).is_err());
+
+        assert_eq!(rulesets.get(), good);
+
+        Ok(())
+    }
+}
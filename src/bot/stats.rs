@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+use tracing::{error, instrument};
+
+use super::persistence::{load_json, save_json};
+use crate::utils::FullErrorDisplay;
+
+/// The most chats included in an export, the ones with the most cleaned links are kept
+const MAX_EXPORTED_CHATS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatStats {
+    pub messages_processed: u64,
+    pub urls_cleaned: u64,
+}
+
+impl ChatStats {
+    fn add(&mut self, other: &ChatStats) {
+        self.messages_processed += other.messages_processed;
+        self.urls_cleaned += other.urls_cleaned;
+    }
+}
+
+/// The stats of a single chat in an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedChat {
+    pub chat_id: i64,
+    #[serde(flatten)]
+    pub stats: ChatStats,
+}
+
+/// A JSON export of the stats, sent to operators by the `/export` command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsExport {
+    pub chats: Vec<ExportedChat>,
+    /// Whether some chats were left out to bound the size of the export
+    pub truncated: bool,
+}
+
+/// Per-chat usage counters, periodically flushed to a JSON file if a path is set
+#[derive(Debug, Clone, Default)]
+pub struct StatsStore {
+    stats: Arc<Mutex<HashMap<i64, ChatStats>>>,
+    dirty: Arc<AtomicBool>,
+    path: Option<PathBuf>,
+}
+
+impl StatsStore {
+    /// Load the stats from the JSON file at `path`, or start from zero if it doesn't exist yet
+    ///
+    /// If no path is given, the stats are only kept in memory
+    #[instrument]
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let stats = match &path {
+            Some(path) => load_json(path)?,
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            stats: Arc::new(Mutex::new(stats)),
+            dirty: Arc::default(),
+            path,
+        })
+    }
+
+    /// Count a processed message in which `urls_cleaned` links were cleaned
+    pub fn record(&self, chat_id: ChatId, urls_cleaned: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        let chat_stats = stats.entry(chat_id.0).or_default();
+        chat_stats.messages_processed += 1;
+        chat_stats.urls_cleaned += urls_cleaned as u64;
+
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn chat(&self, chat_id: ChatId) -> ChatStats {
+        self.stats
+            .lock()
+            .unwrap()
+            .get(&chat_id.0)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Stats summed over all chats
+    pub fn global(&self) -> ChatStats {
+        self.stats
+            .lock()
+            .unwrap()
+            .values()
+            .fold(ChatStats::default(), |mut total, chat_stats| {
+                total.add(chat_stats);
+                total
+            })
+    }
+
+    /// Export the stats of the chat, or of all chats if `chat_id` is None
+    ///
+    /// Returns None if there are no stats to export yet
+    pub fn export(&self, chat_id: Option<ChatId>) -> Option<StatsExport> {
+        let stats = self.stats.lock().unwrap();
+
+        let mut chats: Vec<_> = stats
+            .iter()
+            .filter(|(id, _)| chat_id.is_none_or(|chat_id| chat_id.0 == **id))
+            .map(|(&chat_id, &stats)| ExportedChat { chat_id, stats })
+            .collect();
+
+        if chats.is_empty() {
+            return None;
+        }
+
+        chats.sort_by_key(|chat| (std::cmp::Reverse(chat.stats.urls_cleaned), chat.chat_id));
+        let truncated = chats.len() > MAX_EXPORTED_CHATS;
+        chats.truncate(MAX_EXPORTED_CHATS);
+
+        Some(StatsExport { chats, truncated })
+    }
+
+    /// Save the stats to the file if they changed since the last flush
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let snapshot = self.stats.lock().unwrap().clone();
+        save_json(path, &snapshot).await.inspect_err(|_| {
+            // trying again on the next flush
+            self.dirty.store(true, Ordering::Release);
+        })
+    }
+
+    /// Flush the stats every `interval`, never returns
+    pub async fn flush_periodically(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.flush().await {
+                error!(error = %FullErrorDisplay(&*e), "failed to flush stats");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_per_chat() {
+        let stats = StatsStore::default();
+        let chat = ChatId(1);
+        let other_chat = ChatId(2);
+
+        stats.record(chat, 0);
+        stats.record(chat, 2);
+        stats.record(other_chat, 1);
+
+        assert_eq!(
+            stats.chat(chat),
+            ChatStats {
+                messages_processed: 2,
+                urls_cleaned: 2
+            }
+        );
+        assert_eq!(
+            stats.chat(other_chat),
+            ChatStats {
+                messages_processed: 1,
+                urls_cleaned: 1
+            }
+        );
+        assert_eq!(stats.chat(ChatId(3)), ChatStats::default());
+        assert_eq!(
+            stats.global(),
+            ChatStats {
+                messages_processed: 3,
+                urls_cleaned: 3
+            }
+        );
+    }
+
+    #[test]
+    fn exporting_stats() -> anyhow::Result<()> {
+        let stats = StatsStore::default();
+        assert_eq!(stats.export(None), None);
+
+        stats.record(ChatId(1), 1);
+        stats.record(ChatId(2), 3);
+
+        assert_eq!(stats.export(Some(ChatId(3))), None);
+
+        let chat_export = stats.export(Some(ChatId(1))).unwrap();
+        assert_eq!(
+            serde_json::to_value(&chat_export)?,
+            serde_json::json!({
+                "chats": [{ "chat_id": 1, "messages_processed": 1, "urls_cleaned": 1 }],
+                "truncated": false,
+            })
+        );
+
+        // the busiest chats come first
+        let global_export = stats.export(None).unwrap();
+        let chat_ids: Vec<_> = global_export
+            .chats
+            .iter()
+            .map(|chat| chat.chat_id)
+            .collect();
+        assert_eq!(chat_ids, [2, 1]);
+        assert!(!global_export.truncated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_is_bounded() {
+        let stats = StatsStore::default();
+
+        for id in 0..=MAX_EXPORTED_CHATS as i64 {
+            stats.record(ChatId(id), 1);
+        }
+
+        let export = stats.export(None).unwrap();
+        assert_eq!(export.chats.len(), MAX_EXPORTED_CHATS);
+        assert!(export.truncated);
+    }
+
+    #[tokio::test]
+    async fn stats_persistence_round_trip() -> anyhow::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("youtube_no_si_{}_stats.json", std::process::id()));
+        let chat = ChatId(-100123);
+
+        let stats = StatsStore::load(Some(path.clone()))?;
+        stats.record(chat, 3);
+        stats.flush().await?;
+
+        let reloaded = StatsStore::load(Some(path.clone()));
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            reloaded?.chat(chat),
+            ChatStats {
+                messages_processed: 1,
+                urls_cleaned: 3
+            }
+        );
+
+        Ok(())
+    }
+}
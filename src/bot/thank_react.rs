@@ -1,32 +1,226 @@
-use super::BotRequester;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{BotRequester, chat_settings::ChatSettingsStore, me::SharedMe};
+use crate::clock::SharedClock;
 use anyhow::anyhow;
 use teloxide::{
     dispatching::dialogue::GetChatId,
     prelude::*,
-    types::{Me, ReactionType},
+    types::{MessageEntityKind, ReactionType, UserId},
 };
-use tracing::{info, instrument};
-
-pub fn thank_react_filter(me: Me, message: Message) -> bool {
-    message.reply_to_message().is_some_and(|origin| {
-        origin
-            .from
-            .as_ref()
-            .is_some_and(|from_user| from_user.id == me.id)
-    })
+use tracing::{debug, info, instrument};
+
+/// The reaction to replies to the bot's messages
+pub const THANK_EMOJI: &str = "💘";
+/// A user's replies are reacted to at most once per this interval
+const THANK_COOLDOWN: Duration = Duration::from_secs(60);
+/// Users whose cooldown is over are forgotten once more than this many are tracked
+const MAX_TRACKED_USERS: usize = 4096;
+
+/// Whether the message is a reply to the bot from someone else, and not a command
+pub fn thank_react_filter(me: SharedMe, message: Message) -> bool {
+    let me = me.get();
+
+    let from_me = message.from.as_ref().is_some_and(|from| from.id == me.id);
+    let is_command = message.entities().is_some_and(|entities| {
+        entities
+            .iter()
+            .any(|entity| entity.kind == MessageEntityKind::BotCommand && entity.offset == 0)
+    });
+
+    !from_me
+        && !is_command
+        && message.reply_to_message().is_some_and(|origin| {
+            origin
+                .from
+                .as_ref()
+                .is_some_and(|from_user| from_user.id == me.id)
+        })
+}
+
+/// When each user's reply was last reacted to, so replying repeatedly doesn't spam reactions
+#[derive(Debug, Clone, Default)]
+pub struct ThankCooldown {
+    inner: Arc<CooldownInner>,
+}
+
+#[derive(Debug, Default)]
+struct CooldownInner {
+    last_reactions: Mutex<HashMap<UserId, Instant>>,
+    clock: SharedClock,
+}
+
+impl ThankCooldown {
+    #[cfg(test)]
+    fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            inner: Arc::new(CooldownInner {
+                last_reactions: Mutex::default(),
+                clock,
+            }),
+        }
+    }
+
+    /// Whether to react to the user now, starting the cooldown if so
+    pub fn try_react(&self, user_id: UserId) -> bool {
+        let now = self.inner.clock.now();
+        let mut last_reactions = self.inner.last_reactions.lock().unwrap();
+        let cooling_down = |last: &Instant| now.saturating_duration_since(*last) < THANK_COOLDOWN;
+
+        if last_reactions.get(&user_id).is_some_and(cooling_down) {
+            return false;
+        }
+
+        if last_reactions.len() >= MAX_TRACKED_USERS {
+            last_reactions.retain(|_, last| cooling_down(last));
+        }
+
+        last_reactions.insert(user_id, now);
+        true
+    }
 }
 
 #[instrument(skip_all, err)]
-pub async fn thank_react(bot: BotRequester, message: Message) -> anyhow::Result<()> {
+pub async fn thank_react(
+    bot: BotRequester,
+    message: Message,
+    settings: ChatSettingsStore,
+    cooldown: ThankCooldown,
+) -> anyhow::Result<()> {
+    let chat_id = message.chat_id().ok_or(anyhow!("No chat id for message"))?;
+
+    if settings.is_muted(chat_id).await {
+        debug!("disabled, paused or in quiet hours in this chat");
+        return Ok(());
+    }
+
+    if let Some(user) = &message.from
+        && !cooldown.try_react(user.id)
+    {
+        debug!("reacted to this user recently");
+        return Ok(());
+    }
+
     info!("Reacting to a reply");
-    let mut react = bot.set_message_reaction(
-        message.chat_id().ok_or(anyhow!("No chat id for message"))?,
-        message.id,
-    );
+    let mut react = bot.set_message_reaction(chat_id, message.id);
     react.reaction = Some(vec![ReactionType::Emoji {
-        emoji: "💘".to_owned(),
+        emoji: THANK_EMOJI.to_owned(),
     }]);
     react.await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use serde_json::json;
+    use teloxide::types::Me;
+
+    fn me(id: u64, username: &str) -> Me {
+        serde_json::from_value(json!({
+            "id": id,
+            "is_bot": true,
+            "first_name": "Bot",
+            "username": username,
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+            "can_connect_to_business": false,
+            "has_main_web_app": false,
+        }))
+        .unwrap()
+    }
+
+    fn reply_to(user_id: u64) -> Message {
+        reply_with(user_id, 1, json!({ "text": "thanks!" }))
+    }
+
+    /// A reply from `from_id` to a message of `user_id`, with the content
+    fn reply_with(user_id: u64, from_id: u64, content: serde_json::Value) -> Message {
+        let mut message = json!({
+            "message_id": 2,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "Test" },
+            "from": { "id": from_id, "is_bot": from_id == user_id, "first_name": "Test" },
+            "reply_to_message": {
+                "message_id": 1,
+                "date": 0,
+                "chat": { "id": 1, "type": "private", "first_name": "Test" },
+                "from": { "id": user_id, "is_bot": true, "first_name": "Bot" },
+                "text": "The link without tracking:",
+            },
+        });
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(content.as_object().unwrap().clone());
+
+        serde_json::from_value(message).unwrap()
+    }
+
+    #[test]
+    fn filter_uses_the_refreshed_user() {
+        let shared_me = SharedMe::new(me(100, "old_bot"));
+        assert!(thank_react_filter(shared_me.clone(), reply_to(100)));
+        assert!(!thank_react_filter(shared_me.clone(), reply_to(200)));
+
+        shared_me.set(me(200, "new_bot"));
+        assert!(!thank_react_filter(shared_me.clone(), reply_to(100)));
+        assert!(thank_react_filter(shared_me, reply_to(200)));
+    }
+
+    #[test]
+    fn commands_are_not_reacted_to() {
+        let shared_me = SharedMe::new(me(100, "bot"));
+        let command = reply_with(
+            100,
+            1,
+            json!({
+                "text": "/stats",
+                "entities": [{ "type": "bot_command", "offset": 0, "length": 6 }],
+            }),
+        );
+        assert!(!thank_react_filter(shared_me.clone(), command));
+
+        // mentioning a command later in the text is still a reply
+        let mention = reply_with(
+            100,
+            1,
+            json!({
+                "text": "thanks, /stats is neat",
+                "entities": [{ "type": "bot_command", "offset": 8, "length": 6 }],
+            }),
+        );
+        assert!(thank_react_filter(shared_me, mention));
+    }
+
+    #[test]
+    fn the_bots_own_replies_are_not_reacted_to() {
+        let shared_me = SharedMe::new(me(100, "bot"));
+        let own_reply = reply_with(100, 100, json!({ "text": "The link without tracking:" }));
+
+        assert!(!thank_react_filter(shared_me, own_reply));
+    }
+
+    #[test]
+    fn reactions_to_a_user_cool_down() {
+        let clock = Arc::new(FakeClock::default());
+        let cooldown = ThankCooldown::with_clock(SharedClock::new(clock.clone()));
+
+        assert!(cooldown.try_react(UserId(1)));
+        assert!(!cooldown.clone().try_react(UserId(1)));
+        assert!(cooldown.try_react(UserId(2)));
+
+        clock.advance(THANK_COOLDOWN - Duration::from_secs(1));
+        assert!(!cooldown.try_react(UserId(1)));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(cooldown.try_react(UserId(1)));
+    }
+}
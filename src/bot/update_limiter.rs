@@ -0,0 +1,112 @@
+use std::{
+    future,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// Counts the updates going through the dispatcher, to stop it after a set number of them
+#[derive(Debug, Clone, Default)]
+pub struct UpdateLimiter {
+    inner: Option<Arc<LimiterInner>>,
+}
+
+#[derive(Debug)]
+struct LimiterInner {
+    remaining: AtomicUsize,
+    exhausted: Notify,
+}
+
+impl UpdateLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            inner: limit.map(|limit| {
+                Arc::new(LimiterInner {
+                    remaining: AtomicUsize::new(limit),
+                    exhausted: Notify::new(),
+                })
+            }),
+        }
+    }
+
+    /// Count an incoming update
+    pub fn register(&self) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+
+        let previous =
+            inner
+                .remaining
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |remaining| {
+                    remaining.checked_sub(1)
+                });
+
+        if previous == Ok(1) {
+            debug!("update limit exhausted");
+            inner.exhausted.notify_one();
+        }
+    }
+
+    /// Resolves once the limit is exhausted, never resolves for unlimited limiters
+    pub async fn exhausted(&self) {
+        let Some(inner) = &self.inner else {
+            return future::pending().await;
+        };
+
+        if inner.remaining.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        inner.exhausted.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn limiter_is_exhausted_after_one_update() {
+        let limiter = UpdateLimiter::new(Some(1));
+        assert!(timeout(TIMEOUT, limiter.exhausted()).await.is_err());
+
+        limiter.register();
+        assert!(timeout(TIMEOUT, limiter.exhausted()).await.is_ok());
+
+        // further updates don't underflow the counter
+        limiter.register();
+        assert!(timeout(TIMEOUT, limiter.exhausted()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn waiting_before_the_update_arrives() {
+        let limiter = UpdateLimiter::new(Some(1));
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.exhausted().await }
+        });
+
+        limiter.register();
+        assert!(timeout(TIMEOUT, waiter).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unlimited_limiter_is_never_exhausted() {
+        let limiter = UpdateLimiter::new(None);
+
+        for _ in 0..10 {
+            limiter.register();
+        }
+
+        assert!(timeout(TIMEOUT, limiter.exhausted()).await.is_err());
+    }
+}
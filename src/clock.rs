@@ -0,0 +1,145 @@
+//! The source of time for time-based components, so tests can control it
+
+use std::{
+    fmt::Debug,
+    ops::Deref,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for measuring intervals
+    fn now(&self) -> Instant;
+    /// Wall-clock time, for timestamps and times of day
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock shared between clones of a component, the real time by default
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self(clock)
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(SystemClock))
+    }
+}
+
+impl Debug for SharedClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SharedClock").finish_non_exhaustive()
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeClock;
+
+#[cfg(test)]
+mod fake {
+    use std::{
+        sync::Mutex,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use super::Clock;
+
+    /// A clock that only moves when advanced
+    #[derive(Debug)]
+    pub struct FakeClock {
+        start: Instant,
+        system_start: SystemTime,
+        elapsed: Mutex<Duration>,
+    }
+
+    impl FakeClock {
+        /// A clock stopped at `system_start` wall-clock time
+        pub fn new(system_start: SystemTime) -> Self {
+            Self {
+                start: Instant::now(),
+                system_start,
+                elapsed: Mutex::default(),
+            }
+        }
+
+        pub fn advance(&self, by: Duration) {
+            *self.elapsed.lock().unwrap() += by;
+        }
+    }
+
+    impl Default for FakeClock {
+        fn default() -> Self {
+            Self::new(SystemTime::UNIX_EPOCH)
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + *self.elapsed.lock().unwrap()
+        }
+
+        fn system_now(&self) -> SystemTime {
+            self.system_start + *self.elapsed.lock().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn fake_clock_only_moves_when_advanced() {
+        let clock = FakeClock::default();
+        let start = clock.now();
+
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.system_now(), SystemTime::UNIX_EPOCH);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert_eq!(
+            clock.system_now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn shared_clock_is_shared_between_clones() {
+        let fake = Arc::new(FakeClock::default());
+        let clock = SharedClock::new(fake.clone());
+        let clone = clock.clone();
+        let start = clock.now();
+
+        fake.advance(Duration::from_secs(5));
+        assert_eq!(clone.now() - start, Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,743 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    num::{NonZeroU32, NonZeroU64},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::{rulesets::SharedRulesets, url_kind::UrlKind};
+
+const CONFIRMATION_MODE_KEY: &str = "CONFIRMATION_MODE";
+const REACTION_EMOJIS_KEY: &str = "REACTION_EMOJIS";
+const DEFAULT_REACTION_EMOJI_KEY: &str = "DEFAULT_REACTION_EMOJI";
+const MAX_RETRY_AFTER_SECS_KEY: &str = "MAX_RETRY_AFTER_SECS";
+const SEND_RETRY_LIMIT_KEY: &str = "SEND_RETRY_LIMIT";
+const CHAT_SETTINGS_PATH_KEY: &str = "CHAT_SETTINGS_PATH";
+const LINK_BUTTON_KEY: &str = "LINK_BUTTON";
+const MAX_DOCUMENT_SIZE_KEY: &str = "MAX_DOCUMENT_SIZE";
+const STATS_PATH_KEY: &str = "STATS_PATH";
+const STATS_FLUSH_INTERVAL_SECS_KEY: &str = "STATS_FLUSH_INTERVAL_SECS";
+const ADMIN_USER_IDS_KEY: &str = "ADMIN_USER_IDS";
+const ME_REFRESH_INTERVAL_SECS_KEY: &str = "ME_REFRESH_INTERVAL_SECS";
+const COSMETIC_LINK_THRESHOLD_KEY: &str = "COSMETIC_LINK_THRESHOLD";
+const SCAN_KEYBOARD_URLS_KEY: &str = "SCAN_KEYBOARD_URLS";
+const FRONTEND_HOST_KEY: &str = "FRONTEND_HOST";
+const ACTIVE_CHATS_PATH_KEY: &str = "ACTIVE_CHATS_PATH";
+const FIRST_LINK_ONLY_KEY: &str = "FIRST_LINK_ONLY";
+const THREAD_REPLIES_KEY: &str = "THREAD_REPLIES";
+const MAINTENANCE_KEY: &str = "MAINTENANCE";
+const DENIED_VIDEO_IDS_KEY: &str = "DENIED_VIDEO_IDS";
+const LINK_ORDER_KEY: &str = "LINK_ORDER";
+const DM_USER_STATS_KEY: &str = "DM_USER_STATS";
+const MAX_DISPLAYED_URL_LENGTH_KEY: &str = "MAX_DISPLAYED_URL_LENGTH";
+const MAX_RESTARTS_KEY: &str = "MAX_RESTARTS";
+const SPOILER_LINKS_KEY: &str = "SPOILER_LINKS";
+const MAX_REPLY_MESSAGES_KEY: &str = "MAX_REPLY_MESSAGES";
+const EXPAND_SHORT_LINKS_KEY: &str = "EXPAND_SHORT_LINKS";
+const REPOST_LINKS_KEY: &str = "REPOST_LINKS";
+const UNWRAP_SHARE_LINKS_KEY: &str = "UNWRAP_SHARE_LINKS";
+const RULESET_URL_KEY: &str = "RULESET_URL";
+const RULESET_REFRESH_INTERVAL_SECS_KEY: &str = "RULESET_REFRESH_INTERVAL_SECS";
+const FOLLOW_REDIRECTS_KEY: &str = "FOLLOW_REDIRECTS";
+const REDIRECT_TIMEOUT_SECS_KEY: &str = "REDIRECT_TIMEOUT_SECS";
+const MAINTENANCE_NOTICE_KEY: &str = "MAINTENANCE_NOTICE";
+const MAINTENANCE_NOTICE_INTERVAL_SECS_KEY: &str = "MAINTENANCE_NOTICE_INTERVAL_SECS";
+const REPLIES_PER_MINUTE_KEY: &str = "REPLIES_PER_MINUTE";
+/// All the keys, the config file can only set these
+const KEYS: &[&str] = &[
+    CONFIRMATION_MODE_KEY,
+    REACTION_EMOJIS_KEY,
+    DEFAULT_REACTION_EMOJI_KEY,
+    MAX_RETRY_AFTER_SECS_KEY,
+    SEND_RETRY_LIMIT_KEY,
+    CHAT_SETTINGS_PATH_KEY,
+    LINK_BUTTON_KEY,
+    MAX_DOCUMENT_SIZE_KEY,
+    STATS_PATH_KEY,
+    STATS_FLUSH_INTERVAL_SECS_KEY,
+    ADMIN_USER_IDS_KEY,
+    ME_REFRESH_INTERVAL_SECS_KEY,
+    COSMETIC_LINK_THRESHOLD_KEY,
+    SCAN_KEYBOARD_URLS_KEY,
+    FRONTEND_HOST_KEY,
+    ACTIVE_CHATS_PATH_KEY,
+    FIRST_LINK_ONLY_KEY,
+    THREAD_REPLIES_KEY,
+    MAINTENANCE_KEY,
+    DENIED_VIDEO_IDS_KEY,
+    LINK_ORDER_KEY,
+    DM_USER_STATS_KEY,
+    MAX_DISPLAYED_URL_LENGTH_KEY,
+    MAX_RESTARTS_KEY,
+    SPOILER_LINKS_KEY,
+    MAX_REPLY_MESSAGES_KEY,
+    EXPAND_SHORT_LINKS_KEY,
+    REPOST_LINKS_KEY,
+    UNWRAP_SHARE_LINKS_KEY,
+    RULESET_URL_KEY,
+    RULESET_REFRESH_INTERVAL_SECS_KEY,
+    FOLLOW_REDIRECTS_KEY,
+    REDIRECT_TIMEOUT_SECS_KEY,
+    MAINTENANCE_NOTICE_KEY,
+    MAINTENANCE_NOTICE_INTERVAL_SECS_KEY,
+    REPLIES_PER_MINUTE_KEY,
+];
+
+const DEFAULT_REACTION_EMOJI: &str = "👍";
+const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+const DEFAULT_SEND_RETRY_LIMIT: u32 = 20;
+const DEFAULT_MAX_DOCUMENT_SIZE: u32 = 256 * 1024;
+const DEFAULT_STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_ME_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_MAINTENANCE_NOTICE: &str =
+    "The bot is temporarily in maintenance, links are not cleaned right now";
+const DEFAULT_MAINTENANCE_NOTICE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_MAX_REPLY_MESSAGES: usize = 3;
+const DEFAULT_RULESET_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_REDIRECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum LoadConfigError {
+    #[error("Invalid value for {key}: {reason}")]
+    InvalidValue { key: &'static str, reason: String },
+    #[error("Failed to read the config file {}: {reason}", path.display())]
+    ReadFile { path: PathBuf, reason: String },
+    #[error("Invalid config file: {reason}")]
+    InvalidFile { reason: String },
+    #[error("Unknown key in the config file: {key}")]
+    UnknownKey { key: String },
+}
+
+/// How the bot acknowledges a message with tracked links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationMode {
+    /// Reply with the links without tracking
+    #[default]
+    Reply,
+    /// React to the original message with an emoji depending on the kind of the link
+    Reaction,
+    /// Don't send anything
+    Silent,
+}
+
+impl FromStr for ConfirmationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reply" => Ok(Self::Reply),
+            "reaction" => Ok(Self::Reaction),
+            "silent" => Ok(Self::Silent),
+            other => Err(format!("unknown confirmation mode `{other}`")),
+        }
+    }
+}
+
+/// The order of the cleaned links in replies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkOrder {
+    /// The order in which the links appear in the message
+    #[default]
+    Document,
+    /// Grouped by service, e.g. all YouTube links first, then all Spotify ones
+    Grouped,
+}
+
+impl FromStr for LinkOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "document" => Ok(Self::Document),
+            "grouped" => Ok(Self::Grouped),
+            other => Err(format!("unknown link order `{other}`")),
+        }
+    }
+}
+
+/// Emojis used for reactions in the [`ConfirmationMode::Reaction`] mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactionEmojis {
+    pub default: String,
+    pub by_kind: HashMap<UrlKind, String>,
+}
+
+impl Default for ReactionEmojis {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_REACTION_EMOJI.to_owned(),
+            by_kind: HashMap::new(),
+        }
+    }
+}
+
+impl ReactionEmojis {
+    /// Get the emoji for the kind, falling back to the default one for unmapped kinds
+    pub fn emoji_for(&self, kind: UrlKind) -> &str {
+        self.by_kind.get(&kind).unwrap_or(&self.default)
+    }
+
+    /// Parse a mapping in the form of `short=🔥,live=🔴`
+    pub fn parse_mapping(s: &str) -> Result<HashMap<UrlKind, String>, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (kind, emoji) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected `kind=emoji`, got `{pair}`"))?;
+                let kind = kind.parse::<UrlKind>().map_err(|e| e.to_string())?;
+
+                Ok((kind, emoji.trim().to_owned()))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    pub confirmation_mode: ConfirmationMode,
+    pub reaction_emojis: ReactionEmojis,
+    /// The longest `RetryAfter` delay the bot is willing to wait for before giving up on a message
+    pub max_retry_after: Duration,
+    /// How many times sending a message is attempted on network errors and `RetryAfter`,
+    /// must not be zero
+    pub send_retry_limit: u32,
+    /// Where per-chat settings are persisted, kept only in memory if not set
+    pub chat_settings_path: Option<PathBuf>,
+    /// Attach an inline button opening the first cleaned link to replies
+    pub link_button: bool,
+    /// The largest text document in bytes the bot will download for bulk cleaning
+    pub max_document_size: u32,
+    /// Stop the bot after processing this many updates, set by the `--once` flag
+    pub update_limit: Option<usize>,
+    /// Where usage stats are persisted, kept only in memory if not set
+    pub stats_path: Option<PathBuf>,
+    /// How often the stats are written to the file, must not be zero
+    pub stats_flush_interval: Duration,
+    /// Telegram ids of the bot operators, who can see stats across all chats
+    pub admin_user_ids: HashSet<u64>,
+    /// How often the bot's own user is fetched again, in case it was renamed, must not be zero
+    pub me_refresh_interval: Duration,
+    /// Don't reply if all cleaned links are bare `youtu.be` links shorter than this many characters
+    pub cosmetic_link_threshold: Option<usize>,
+    /// Also clean links from inline keyboard buttons, e.g. in messages forwarded from other bots
+    pub scan_keyboard_urls: bool,
+    /// Rewrite cleaned links to this privacy frontend host, e.g. an Invidious instance
+    pub frontend_host: Option<String>,
+    /// Where the set of chats the bot is a member of is persisted, kept only in memory if not set
+    pub active_chats_path: Option<PathBuf>,
+    /// Only clean the first tracked link of a message, ignoring the rest
+    pub first_link_only: bool,
+    /// Thread all replies in a chat under a single pinned message of the bot
+    pub thread_replies: bool,
+    /// Start in maintenance, replying with a notice instead of cleaning links
+    pub maintenance: bool,
+    pub maintenance_notice: String,
+    /// The notice is sent to a chat at most once per this interval
+    pub maintenance_notice_interval: Duration,
+    /// Ids of videos the bot never posts, even after cleaning
+    pub denied_video_ids: HashSet<String>,
+    pub link_order: LinkOrder,
+    /// Tell users in private chats how many links the bot has cleaned for them
+    pub dm_user_stats: bool,
+    /// Show longer cleaned links truncated in replies, with a button copying the full link
+    pub max_displayed_url_len: Option<usize>,
+    /// How many times the dispatcher is restarted after panics before the bot exits with an error,
+    /// unlimited if not set
+    pub max_restarts: Option<usize>,
+    /// Hide the cleaned links under a spoiler if the original links were hidden under one
+    pub spoiler_links: bool,
+    /// Replies too long for one message are split into at most this many messages,
+    /// the links that don't fit are only counted
+    pub max_reply_messages: usize,
+    /// Expand cleaned `youtu.be/<id>` links to the `youtube.com/watch?v=<id>` form
+    pub expand_short_links: bool,
+    /// In groups where the bot can delete messages, repost text messages with tracked links
+    /// with the links cleaned, attributed to the author, and delete them instead of replying
+    pub repost_links: bool,
+    /// Clean the YouTube links embedded in Telegram share links (`t.me/share/url?url=...`)
+    pub unwrap_share_links: bool,
+    /// The rulesets links are cleaned with, the built-in ones unless fetched from `ruleset_url`
+    pub rulesets: SharedRulesets,
+    /// Where the rulesets are periodically fetched from as JSON, if set
+    pub ruleset_url: Option<Url>,
+    /// How often the rulesets are fetched, must not be zero
+    pub ruleset_refresh_interval: Duration,
+    /// Follow the redirects of links to other sites and clean the links they lead to
+    pub follow_redirects: bool,
+    /// How long following the redirects of a link may take in total
+    pub redirect_timeout: Duration,
+    /// The most replies and edits of replies sent to a chat per minute, the ones over it
+    /// are dropped, unlimited if not set, must not be zero
+    pub replies_per_minute: Option<u32>,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            confirmation_mode: ConfirmationMode::default(),
+            reaction_emojis: ReactionEmojis::default(),
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            send_retry_limit: DEFAULT_SEND_RETRY_LIMIT,
+            chat_settings_path: None,
+            link_button: false,
+            max_document_size: DEFAULT_MAX_DOCUMENT_SIZE,
+            update_limit: None,
+            stats_path: None,
+            stats_flush_interval: DEFAULT_STATS_FLUSH_INTERVAL,
+            admin_user_ids: HashSet::new(),
+            me_refresh_interval: DEFAULT_ME_REFRESH_INTERVAL,
+            cosmetic_link_threshold: None,
+            scan_keyboard_urls: false,
+            frontend_host: None,
+            active_chats_path: None,
+            first_link_only: false,
+            thread_replies: false,
+            maintenance: false,
+            maintenance_notice: DEFAULT_MAINTENANCE_NOTICE.to_owned(),
+            maintenance_notice_interval: DEFAULT_MAINTENANCE_NOTICE_INTERVAL,
+            denied_video_ids: HashSet::new(),
+            link_order: LinkOrder::default(),
+            dm_user_stats: false,
+            max_displayed_url_len: None,
+            max_restarts: None,
+            spoiler_links: true,
+            max_reply_messages: DEFAULT_MAX_REPLY_MESSAGES,
+            expand_short_links: false,
+            repost_links: false,
+            unwrap_share_links: false,
+            rulesets: SharedRulesets::default(),
+            ruleset_url: None,
+            ruleset_refresh_interval: DEFAULT_RULESET_REFRESH_INTERVAL,
+            follow_redirects: false,
+            redirect_timeout: DEFAULT_REDIRECT_TIMEOUT,
+            replies_per_minute: None,
+        }
+    }
+}
+
+impl BotConfig {
+    /// Load the config from environment variables, using defaults for missing ones
+    pub fn from_env() -> Result<Self, LoadConfigError> {
+        Self::from_source(env_var)
+    }
+
+    /// Load the config from a TOML file, with environment variables overriding its values
+    ///
+    /// The keys of the file are the names of the environment variables in lowercase,
+    /// lists (e.g. of admin user ids) are TOML arrays
+    pub fn from_file_and_env(path: &Path) -> Result<Self, LoadConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| LoadConfigError::ReadFile {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })?;
+        let file = parse_file(&contents)?;
+
+        Self::from_layers(&file, env_var)
+    }
+
+    /// Load the config from the file values, overridden by the values `env` returns
+    fn from_layers(
+        file: &HashMap<&'static str, String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, LoadConfigError> {
+        Self::from_source(|key| {
+            env(key).or_else(|| {
+                file.get(key)
+                    .filter(|value| !value.trim().is_empty())
+                    .cloned()
+            })
+        })
+    }
+
+    /// Load the config from the values `var` returns for the keys, using defaults for missing ones
+    fn from_source(var: impl Fn(&'static str) -> Option<String>) -> Result<Self, LoadConfigError> {
+        let mut config = Self::default();
+
+        if let Some(mode) = var(CONFIRMATION_MODE_KEY) {
+            config.confirmation_mode = parse_value(CONFIRMATION_MODE_KEY, &mode)?;
+        }
+
+        if let Some(emoji) = var(DEFAULT_REACTION_EMOJI_KEY) {
+            config.reaction_emojis.default = emoji;
+        }
+
+        if let Some(mapping) = var(REACTION_EMOJIS_KEY) {
+            config.reaction_emojis.by_kind =
+                ReactionEmojis::parse_mapping(&mapping).map_err(|reason| {
+                    LoadConfigError::InvalidValue {
+                        key: REACTION_EMOJIS_KEY,
+                        reason,
+                    }
+                })?;
+        }
+
+        if let Some(secs) = var(MAX_RETRY_AFTER_SECS_KEY) {
+            config.max_retry_after =
+                Duration::from_secs(parse_value(MAX_RETRY_AFTER_SECS_KEY, &secs)?);
+        }
+
+        if let Some(limit) = var(SEND_RETRY_LIMIT_KEY) {
+            // a message must be attempted at least once
+            let limit: NonZeroU32 = parse_value(SEND_RETRY_LIMIT_KEY, &limit)?;
+            config.send_retry_limit = limit.get();
+        }
+
+        config.chat_settings_path = var(CHAT_SETTINGS_PATH_KEY).map(PathBuf::from);
+
+        if let Some(link_button) = var(LINK_BUTTON_KEY) {
+            config.link_button = parse_value(LINK_BUTTON_KEY, &link_button)?;
+        }
+
+        if let Some(size) = var(MAX_DOCUMENT_SIZE_KEY) {
+            config.max_document_size = parse_value(MAX_DOCUMENT_SIZE_KEY, &size)?;
+        }
+
+        config.stats_path = var(STATS_PATH_KEY).map(PathBuf::from);
+
+        if let Some(secs) = var(STATS_FLUSH_INTERVAL_SECS_KEY) {
+            // the flushing task panics on a zero period
+            let secs: NonZeroU64 = parse_value(STATS_FLUSH_INTERVAL_SECS_KEY, &secs)?;
+            config.stats_flush_interval = Duration::from_secs(secs.get());
+        }
+
+        if let Some(ids) = var(ADMIN_USER_IDS_KEY) {
+            config.admin_user_ids = parse_list(ADMIN_USER_IDS_KEY, &ids)?;
+        }
+
+        if let Some(secs) = var(ME_REFRESH_INTERVAL_SECS_KEY) {
+            let secs: NonZeroU64 = parse_value(ME_REFRESH_INTERVAL_SECS_KEY, &secs)?;
+            config.me_refresh_interval = Duration::from_secs(secs.get());
+        }
+
+        if let Some(threshold) = var(COSMETIC_LINK_THRESHOLD_KEY) {
+            config.cosmetic_link_threshold =
+                Some(parse_value(COSMETIC_LINK_THRESHOLD_KEY, &threshold)?);
+        }
+
+        if let Some(scan) = var(SCAN_KEYBOARD_URLS_KEY) {
+            config.scan_keyboard_urls = parse_value(SCAN_KEYBOARD_URLS_KEY, &scan)?;
+        }
+
+        config.frontend_host = var(FRONTEND_HOST_KEY).map(|host| host.trim().to_owned());
+        config.active_chats_path = var(ACTIVE_CHATS_PATH_KEY).map(PathBuf::from);
+
+        if let Some(first_only) = var(FIRST_LINK_ONLY_KEY) {
+            config.first_link_only = parse_value(FIRST_LINK_ONLY_KEY, &first_only)?;
+        }
+
+        if let Some(thread) = var(THREAD_REPLIES_KEY) {
+            config.thread_replies = parse_value(THREAD_REPLIES_KEY, &thread)?;
+        }
+
+        if let Some(maintenance) = var(MAINTENANCE_KEY) {
+            config.maintenance = parse_value(MAINTENANCE_KEY, &maintenance)?;
+        }
+
+        if let Some(notice) = var(MAINTENANCE_NOTICE_KEY) {
+            config.maintenance_notice = notice;
+        }
+
+        if let Some(secs) = var(MAINTENANCE_NOTICE_INTERVAL_SECS_KEY) {
+            config.maintenance_notice_interval =
+                Duration::from_secs(parse_value(MAINTENANCE_NOTICE_INTERVAL_SECS_KEY, &secs)?);
+        }
+
+        if let Some(ids) = var(DENIED_VIDEO_IDS_KEY) {
+            config.denied_video_ids = parse_list(DENIED_VIDEO_IDS_KEY, &ids)?;
+        }
+
+        if let Some(order) = var(LINK_ORDER_KEY) {
+            config.link_order = parse_value(LINK_ORDER_KEY, &order)?;
+        }
+
+        if let Some(dm_stats) = var(DM_USER_STATS_KEY) {
+            config.dm_user_stats = parse_value(DM_USER_STATS_KEY, &dm_stats)?;
+        }
+
+        if let Some(len) = var(MAX_DISPLAYED_URL_LENGTH_KEY) {
+            config.max_displayed_url_len = Some(parse_value(MAX_DISPLAYED_URL_LENGTH_KEY, &len)?);
+        }
+
+        if let Some(max_restarts) = var(MAX_RESTARTS_KEY) {
+            config.max_restarts = Some(parse_value(MAX_RESTARTS_KEY, &max_restarts)?);
+        }
+
+        if let Some(spoiler_links) = var(SPOILER_LINKS_KEY) {
+            config.spoiler_links = parse_value(SPOILER_LINKS_KEY, &spoiler_links)?;
+        }
+
+        if let Some(max_messages) = var(MAX_REPLY_MESSAGES_KEY) {
+            config.max_reply_messages = parse_value(MAX_REPLY_MESSAGES_KEY, &max_messages)?;
+        }
+
+        if let Some(expand) = var(EXPAND_SHORT_LINKS_KEY) {
+            config.expand_short_links = parse_value(EXPAND_SHORT_LINKS_KEY, &expand)?;
+        }
+
+        if let Some(repost) = var(REPOST_LINKS_KEY) {
+            config.repost_links = parse_value(REPOST_LINKS_KEY, &repost)?;
+        }
+
+        if let Some(unwrap) = var(UNWRAP_SHARE_LINKS_KEY) {
+            config.unwrap_share_links = parse_value(UNWRAP_SHARE_LINKS_KEY, &unwrap)?;
+        }
+
+        if let Some(url) = var(RULESET_URL_KEY) {
+            config.ruleset_url = Some(parse_value(RULESET_URL_KEY, url.trim())?);
+        }
+
+        if let Some(secs) = var(RULESET_REFRESH_INTERVAL_SECS_KEY) {
+            let secs: NonZeroU64 = parse_value(RULESET_REFRESH_INTERVAL_SECS_KEY, &secs)?;
+            config.ruleset_refresh_interval = Duration::from_secs(secs.get());
+        }
+
+        if let Some(follow) = var(FOLLOW_REDIRECTS_KEY) {
+            config.follow_redirects = parse_value(FOLLOW_REDIRECTS_KEY, &follow)?;
+        }
+
+        if let Some(secs) = var(REDIRECT_TIMEOUT_SECS_KEY) {
+            config.redirect_timeout =
+                Duration::from_secs(parse_value(REDIRECT_TIMEOUT_SECS_KEY, &secs)?);
+        }
+
+        if let Some(limit) = var(REPLIES_PER_MINUTE_KEY) {
+            // a zero limit would silently drop every reply
+            let limit: NonZeroU32 = parse_value(REPLIES_PER_MINUTE_KEY, &limit)?;
+            config.replies_per_minute = Some(limit.get());
+        }
+
+        Ok(config)
+    }
+}
+
+fn env_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// The values of a TOML config file by the keys they override, as if set in the environment
+fn parse_file(contents: &str) -> Result<HashMap<&'static str, String>, LoadConfigError> {
+    let table: toml::Table =
+        contents
+            .parse()
+            .map_err(|e: toml::de::Error| LoadConfigError::InvalidFile {
+                reason: e.to_string(),
+            })?;
+
+    table
+        .into_iter()
+        .map(|(name, value)| {
+            let key = KEYS
+                .iter()
+                .find(|key| key.eq_ignore_ascii_case(&name))
+                .ok_or(LoadConfigError::UnknownKey { key: name })?;
+
+            let value = match value {
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| file_value(key, item))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+                value => file_value(key, value)?,
+            };
+
+            Ok((*key, value))
+        })
+        .collect()
+}
+
+/// A scalar value of the config file as a string, as it would be in the environment
+fn file_value(key: &'static str, value: toml::Value) -> Result<String, LoadConfigError> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => Err(LoadConfigError::InvalidValue {
+            key,
+            reason: format!("unsupported value type: {}", other.type_str()),
+        }),
+    }
+}
+
+fn parse_value<T>(key: &'static str, value: &str) -> Result<T, LoadConfigError>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    value
+        .parse()
+        .map_err(|e: T::Err| LoadConfigError::InvalidValue {
+            key,
+            reason: e.to_string(),
+        })
+}
+
+/// Parse a comma-separated list of values
+fn parse_list<T, C>(key: &'static str, value: &str) -> Result<C, LoadConfigError>
+where
+    T: FromStr,
+    T::Err: ToString,
+    C: FromIterator<T>,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse_value(key, item))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmapped_kinds_use_default_emoji() {
+        let emojis = ReactionEmojis {
+            default: "👌".to_owned(),
+            by_kind: HashMap::from([(UrlKind::Short, "🔥".to_owned())]),
+        };
+
+        assert_eq!(emojis.emoji_for(UrlKind::Short), "🔥");
+        assert_eq!(emojis.emoji_for(UrlKind::Video), "👌");
+        assert_eq!(emojis.emoji_for(UrlKind::Other), "👌");
+        assert_eq!(
+            ReactionEmojis::default().emoji_for(UrlKind::Live),
+            DEFAULT_REACTION_EMOJI
+        );
+    }
+
+    #[test]
+    fn parsing_confirmation_mode() {
+        assert_eq!("reply".parse(), Ok(ConfirmationMode::Reply));
+        assert_eq!(" Reaction ".parse(), Ok(ConfirmationMode::Reaction));
+        assert_eq!("SILENT".parse(), Ok(ConfirmationMode::Silent));
+        assert!("loud".parse::<ConfirmationMode>().is_err());
+    }
+
+    #[test]
+    fn parsing_link_order() {
+        assert_eq!("document".parse(), Ok(LinkOrder::Document));
+        assert_eq!(" Grouped".parse(), Ok(LinkOrder::Grouped));
+        assert!("sorted".parse::<LinkOrder>().is_err());
+    }
+
+    #[test]
+    fn parsing_lists() {
+        assert_eq!(
+            parse_list::<u64, Vec<_>>("KEY", "1, 2,,3 "),
+            Ok(vec![1, 2, 3])
+        );
+        assert!(parse_list::<u64, Vec<_>>("KEY", "1,two").is_err());
+    }
+
+    #[test]
+    fn zero_intervals_and_limits_are_rejected() {
+        for key in [
+            STATS_FLUSH_INTERVAL_SECS_KEY,
+            ME_REFRESH_INTERVAL_SECS_KEY,
+            RULESET_REFRESH_INTERVAL_SECS_KEY,
+            SEND_RETRY_LIMIT_KEY,
+            REPLIES_PER_MINUTE_KEY,
+        ] {
+            let config = BotConfig::from_source(|k| (k == key).then(|| "0".to_owned()));
+
+            assert!(
+                matches!(config, Err(LoadConfigError::InvalidValue { key: k, .. }) if k == key),
+                "{key}: {config:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parsing_emoji_mapping() {
+        assert_eq!(
+            ReactionEmojis::parse_mapping("shorts=🔥, live = 🔴,"),
+            Ok(HashMap::from([
+                (UrlKind::Short, "🔥".to_owned()),
+                (UrlKind::Live, "🔴".to_owned()),
+            ]))
+        );
+
+        assert!(ReactionEmojis::parse_mapping("shorts").is_err());
+        assert!(ReactionEmojis::parse_mapping("movie=🎬").is_err());
+    }
+
+    const SAMPLE_FILE: &str = r#"
+        confirmation_mode = "reaction"
+        admin_user_ids = [1, 2]
+        link_button = true
+        max_restarts = 3
+        frontend_host = "yewtu.be"
+    "#;
+
+    #[test]
+    fn loading_a_config_file() -> anyhow::Result<()> {
+        let file = parse_file(SAMPLE_FILE)?;
+        let config = BotConfig::from_layers(&file, |_| None)?;
+
+        assert_eq!(config.confirmation_mode, ConfirmationMode::Reaction);
+        assert_eq!(config.admin_user_ids, HashSet::from([1, 2]));
+        assert!(config.link_button);
+        assert_eq!(config.max_restarts, Some(3));
+        assert_eq!(config.frontend_host.as_deref(), Some("yewtu.be"));
+        // missing keys keep their defaults
+        assert_eq!(config.max_retry_after, DEFAULT_MAX_RETRY_AFTER);
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_overrides_the_config_file() -> anyhow::Result<()> {
+        let file = parse_file(SAMPLE_FILE)?;
+        let env = |key: &str| (key == CONFIRMATION_MODE_KEY).then(|| "silent".to_owned());
+        let config = BotConfig::from_layers(&file, env)?;
+
+        assert_eq!(config.confirmation_mode, ConfirmationMode::Silent);
+        assert_eq!(config.max_restarts, Some(3));
+        assert_eq!(config.frontend_host.as_deref(), Some("yewtu.be"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_file_errors_name_the_key() {
+        assert_eq!(
+            parse_file("confirmation_mod = \"reply\"").err(),
+            Some(LoadConfigError::UnknownKey {
+                key: "confirmation_mod".to_owned()
+            })
+        );
+
+        assert!(matches!(
+            parse_file("[link_button]\nvalue = true").err(),
+            Some(LoadConfigError::InvalidValue {
+                key: LINK_BUTTON_KEY,
+                ..
+            })
+        ));
+
+        let file = parse_file("max_restarts = \"many\"").unwrap();
+        assert!(matches!(
+            BotConfig::from_layers(&file, |_| None).err(),
+            Some(LoadConfigError::InvalidValue {
+                key: MAX_RESTARTS_KEY,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            parse_file("link_button = ").err(),
+            Some(LoadConfigError::InvalidFile { .. })
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+//! Whether the bot is working, for the systemd watchdog and the HTTP healthcheck and metrics
+
+use std::{
+    borrow::Cow,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{metrics::UptimeMetrics, utils::FullErrorDisplay};
+
+/// The path of the healthcheck endpoint
+const HEALTHZ_PATH: &str = "/healthz";
+/// The path of the Prometheus metrics
+const METRICS_PATH: &str = "/metrics";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+/// Only the request line is needed, longer requests are cut off
+const MAX_REQUEST_LEN: usize = 1024;
+/// Slow clients are dropped so they don't pile up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the dispatcher is running, shared between clones
+#[derive(Debug, Clone)]
+pub struct Health(Arc<AtomicBool>);
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl Health {
+    pub fn new(healthy: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(healthy)))
+    }
+
+    pub fn set(&self, healthy: bool) {
+        self.0.store(healthy, Ordering::Release);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Answer `GET /healthz` with 200 while healthy and 503 otherwise,
+/// and `GET /metrics` with the metrics, never returns
+#[instrument(skip_all)]
+pub async fn serve_status(listener: TcpListener, health: Health, metrics: UptimeMetrics) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(error = %FullErrorDisplay(e), "failed to accept a healthcheck connection");
+                continue;
+            }
+        };
+
+        let (health, metrics) = (health.clone(), metrics.clone());
+        tokio::spawn(async move {
+            let response = respond(stream, &health, &metrics);
+            match tokio::time::timeout(REQUEST_TIMEOUT, response).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    debug!(error = %FullErrorDisplay(e), "failed to answer the healthcheck")
+                }
+                Err(_) => debug!("healthcheck request timed out"),
+            }
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    health: &Health,
+    metrics: &UptimeMetrics,
+) -> std::io::Result<()> {
+    let mut request = vec![0; MAX_REQUEST_LEN];
+    let len = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..len]);
+    let request_line = request.lines().next().unwrap_or_default();
+
+    let (status, content_type, body) = match response_for(request_line, health.is_healthy()) {
+        Response::Metrics => ("200 OK", METRICS_CONTENT_TYPE, metrics.render().into()),
+        Response::Plain(status, body) => (status, "text/plain", Cow::Borrowed(body)),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Response {
+    Metrics,
+    /// The status line and the body
+    Plain(&'static str, &'static str),
+}
+
+/// What to answer the request with
+fn response_for(request_line: &str, healthy: bool) -> Response {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    match (method, path) {
+        (Some("GET" | "HEAD"), Some(HEALTHZ_PATH)) if healthy => Response::Plain("200 OK", "ok\n"),
+        (Some("GET" | "HEAD"), Some(HEALTHZ_PATH)) => {
+            Response::Plain("503 Service Unavailable", "unhealthy\n")
+        }
+        (Some("GET" | "HEAD"), Some(METRICS_PATH)) => Response::Metrics,
+        (Some(_), Some(HEALTHZ_PATH | METRICS_PATH)) => {
+            Response::Plain("405 Method Not Allowed", "method not allowed\n")
+        }
+        _ => Response::Plain("404 Not Found", "not found\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(request_line: &str, healthy: bool) -> &'static str {
+        match response_for(request_line, healthy) {
+            Response::Metrics => "metrics",
+            Response::Plain(status, _) => status,
+        }
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> anyhow::Result<String> {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[test]
+    fn healthz_reflects_the_health() {
+        assert_eq!(status("GET /healthz HTTP/1.1", true), "200 OK");
+        assert_eq!(
+            status("GET /healthz HTTP/1.1", false),
+            "503 Service Unavailable"
+        );
+        assert_eq!(status("HEAD /healthz HTTP/1.0", true), "200 OK");
+        assert_eq!(
+            status("POST /healthz HTTP/1.1", true),
+            "405 Method Not Allowed"
+        );
+        assert_eq!(status("GET / HTTP/1.1", true), "404 Not Found");
+        assert_eq!(status("", true), "404 Not Found");
+    }
+
+    #[test]
+    fn metrics_are_served_regardless_of_the_health() {
+        assert_eq!(status("GET /metrics HTTP/1.1", true), "metrics");
+        assert_eq!(status("GET /metrics HTTP/1.1", false), "metrics");
+        assert_eq!(
+            status("DELETE /metrics HTTP/1.1", true),
+            "405 Method Not Allowed"
+        );
+    }
+
+    #[tokio::test]
+    async fn serving_the_healthcheck() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let health = Health::default();
+        let server = tokio::spawn(serve_status(
+            listener,
+            health.clone(),
+            UptimeMetrics::default(),
+        ));
+
+        let response = get(addr, HEALTHZ_PATH).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nok\n"), "{response}");
+
+        // e.g. while the dispatcher restarts after a panic
+        health.set(false);
+        let response = get(addr, HEALTHZ_PATH).await?;
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{response}"
+        );
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scraping_the_metrics() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let metrics = UptimeMetrics::default();
+        metrics.record(1);
+        metrics.record_removed_params(["si"]);
+        let server = tokio::spawn(serve_status(listener, Health::default(), metrics));
+
+        let response = get(addr, METRICS_PATH).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(
+            response.contains(&format!("Content-Type: {METRICS_CONTENT_TYPE}\r\n")),
+            "{response}"
+        );
+
+        for name in [
+            "youtube_no_si_messages_seen_total",
+            "youtube_no_si_links_cleaned_total",
+            "youtube_no_si_tracking_params_removed_total",
+            "youtube_no_si_send_retries_total",
+            "youtube_no_si_api_errors_total",
+        ] {
+            assert!(
+                response.contains(&format!("# TYPE {name} counter\n")),
+                "{name}\n{response}"
+            );
+        }
+        assert!(
+            response.contains("youtube_no_si_tracking_params_removed_total{param=\"si\"} 1\n"),
+            "{response}"
+        );
+
+        server.abort();
+        Ok(())
+    }
+}
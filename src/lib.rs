@@ -1,5 +1,28 @@
+#[cfg(feature = "bot")]
 mod bot;
+pub mod clock;
+pub mod config;
+#[cfg(feature = "bot")]
+pub mod health;
+#[cfg(feature = "bot")]
+pub mod metrics;
+pub mod remove_si;
+pub mod rulesets;
+#[cfg(feature = "bot")]
+pub mod tasks;
+pub mod timestamp;
+#[cfg(feature = "bot")]
+pub mod title_cache;
+#[cfg(feature = "bot")]
 pub mod token;
+pub mod transform;
+pub mod url_kind;
+#[cfg(feature = "bot")]
 pub(crate) mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "systemd")]
+pub mod watchdog;
 
-pub use bot::run_bot;
+#[cfg(feature = "bot")]
+pub use bot::{run_bot, run_bot_webhook};
@@ -1,34 +1,227 @@
-use std::time::Duration;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    time::Duration,
+};
 
+use anyhow::{Context, bail};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, instrument, warn};
 use tracing_subscriber::EnvFilter;
-use youtube_no_si_redux::{run_bot, token::load_token};
+use url::Url;
+use youtube_no_si_redux::{
+    config::BotConfig,
+    health::{Health, serve_status},
+    metrics::UptimeMetrics,
+    remove_si::StripResult,
+    run_bot, run_bot_webhook,
+    tasks::TaskAccounting,
+    token::{load_token, load_token_from_stdin},
+};
 
 const FORCED_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Process a single update and exit, useful for end-to-end tests against a test bot
+const ONCE_FLAG: &str = "--once";
+/// Read the token from the standard input instead of the environment or the .env file
+const TOKEN_STDIN_FLAG: &str = "--token-stdin";
+/// Load the config from a TOML file, environment variables override its values
+const CONFIG_FLAG: &str = "--config";
+/// Clean the links given as arguments, or read line by line from the standard input,
+/// and exit, without running the bot
+const CLEAN_SUBCOMMAND: &str = "clean";
+/// Same as the clean subcommand
+const CLEAN_FLAG: &str = "--clean";
+/// Print the results of the clean subcommand as JSON, one object per line
+const JSON_FLAG: &str = "--json";
+/// Printed by the clean subcommand if it's given no links and the standard input is a terminal
+const CLEAN_USAGE: &str = "\
+usage: youtube_no_si_redux clean [--json] [URL]...
+
+Prints the URLs without tracking, reading them line by line from the standard input
+if none are given. With --json, prints what was removed as JSON, one object per line";
+
+/// `polling` (the default) or `webhook`
+const BOT_MODE_KEY: &str = "BOT_MODE";
+/// The address the webhook server listens on, e.g. `0.0.0.0:8443`
+const WEBHOOK_ADDR_KEY: &str = "WEBHOOK_ADDR";
+/// The public url Telegram sends the updates to
+const WEBHOOK_URL_KEY: &str = "WEBHOOK_URL";
+/// The address to serve `/healthz` and `/metrics` on, e.g. `0.0.0.0:8080`, not served if unset
+const HEALTHCHECK_ADDR_KEY: &str = "HEALTHCHECK_ADDR";
+
+/// How the bot receives updates
+#[derive(Debug)]
+enum BotMode {
+    Polling,
+    Webhook { addr: SocketAddr, url: Url },
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // before the logs are set up, so they don't mix with the output
+    if matches!(
+        env::args().nth(1).as_deref(),
+        Some(CLEAN_SUBCOMMAND | CLEAN_FLAG)
+    ) {
+        return clean(env::args().skip(2).collect());
+    }
+
     tracing_subscriber::FmtSubscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
+    let token = if env::args().skip(1).any(|arg| arg == TOKEN_STDIN_FLAG) {
+        info!("reading the token from the standard input");
+        load_token_from_stdin()?
+    } else {
+        load_token()?
+    };
+    let mut config = match config_path() {
+        Some(path) => {
+            info!(path = %path.display(), "loading the config file");
+            BotConfig::from_file_and_env(&path)?
+        }
+        None => BotConfig::from_env()?,
+    };
+
+    if env::args().skip(1).any(|arg| arg == ONCE_FLAG) {
+        info!("running in the single update mode");
+        config.update_limit = Some(1);
+    }
+
+    let mode = bot_mode()?;
+    info!(?mode, "receiving updates");
+
+    let tasks = TaskAccounting::default();
+    let shutdown = CancellationToken::new();
+    // unhealthy until the dispatcher starts
+    let health = Health::new(false);
+    let metrics = UptimeMetrics::default();
+    if let Some(addr) = healthcheck_addr()? {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind the healthcheck server to {addr}"))?;
+        info!(%addr, "serving the healthcheck and metrics");
+        // not tracked by the task accounting so it doesn't hold up the shutdown,
+        // it's dropped with the runtime
+        tokio::spawn(serve_status(listener, health.clone(), metrics.clone()));
+    }
+    let bot = {
+        let tasks = tasks.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            match mode {
+                BotMode::Polling => run_bot(token, config, tasks, shutdown, health, metrics).await,
+                BotMode::Webhook { addr, url } => {
+                    run_bot_webhook(token, config, tasks, shutdown, health, metrics, addr, url)
+                        .await
+                }
+            }
+        }
+    };
+
     tokio::select! {
         // spawn the bot in a separate task so it does not interfere with the forced shutdown
-        _ = tokio::spawn(run_bot(load_token()?)) => {},
-        // forcibly shutdown everything after some time after receiving a Ctrl-C
-        _ = forced_shutdown() => {}
+        res = tokio::spawn(bot) => res??,
+        // the first Ctrl-C shuts down gracefully,
+        // forcibly shutdown everything after some time or after a second Ctrl-C
+        _ = forced_shutdown(shutdown) => {
+            warn!(summary = %tasks.summary(false), "bot did not shut down in time");
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the cleaned links, or the original ones if there's nothing to clean
+///
+/// The links are read from the standard input, one per line, if none are given as arguments,
+/// unless it's a terminal, the usage is printed then
+fn clean(args: Vec<String>) -> anyhow::Result<()> {
+    let json = args.iter().any(|arg| arg == JSON_FLAG);
+    let mut inputs: Vec<_> = args.into_iter().filter(|arg| arg != JSON_FLAG).collect();
+
+    if inputs.is_empty() {
+        // waiting for links to be typed in would look like the command hangs
+        if io::stdin().is_terminal() {
+            eprintln!("{CLEAN_USAGE}");
+            process::exit(2);
+        }
+
+        for line in io::stdin().lines() {
+            let line = line.context("failed to read the standard input")?;
+            if !line.trim().is_empty() {
+                inputs.push(line);
+            }
+        }
+    }
+
+    for input in &inputs {
+        let result = StripResult::of(input).with_context(|| format!("invalid url `{input}`"))?;
+
+        if json {
+            println!("{}", serde_json::to_string(&result)?);
+        } else {
+            println!("{}", result.cleaned);
+        }
     }
 
     Ok(())
 }
 
-#[instrument]
-async fn forced_shutdown() {
+fn bot_mode() -> anyhow::Result<BotMode> {
+    let mode = env::var(BOT_MODE_KEY).unwrap_or_default();
+
+    match mode.trim().to_ascii_lowercase().as_str() {
+        "" | "polling" => Ok(BotMode::Polling),
+        "webhook" => {
+            let addr = env::var(WEBHOOK_ADDR_KEY)
+                .with_context(|| format!("{WEBHOOK_ADDR_KEY} is required in the webhook mode"))?
+                .parse()
+                .with_context(|| format!("invalid {WEBHOOK_ADDR_KEY}"))?;
+            let url = env::var(WEBHOOK_URL_KEY)
+                .with_context(|| format!("{WEBHOOK_URL_KEY} is required in the webhook mode"))?
+                .parse()
+                .with_context(|| format!("invalid {WEBHOOK_URL_KEY}"))?;
+
+            Ok(BotMode::Webhook { addr, url })
+        }
+        other => bail!("invalid {BOT_MODE_KEY}: expected `polling` or `webhook`, got `{other}`"),
+    }
+}
+
+fn healthcheck_addr() -> anyhow::Result<Option<SocketAddr>> {
+    match env::var(HEALTHCHECK_ADDR_KEY) {
+        Ok(addr) if !addr.trim().is_empty() => addr
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("invalid {HEALTHCHECK_ADDR_KEY}")),
+        _ => Ok(None),
+    }
+}
+
+/// The path following the config flag, if given
+fn config_path() -> Option<PathBuf> {
+    env::args()
+        .skip(1)
+        .skip_while(|arg| arg != CONFIG_FLAG)
+        .nth(1)
+        .map(PathBuf::from)
+}
+
+#[instrument(skip_all)]
+async fn forced_shutdown(shutdown: CancellationToken) {
     tokio::signal::ctrl_c()
         .await
         .expect("failed to listen for the Ctrl-C event");
 
-    info!("^C received, press again for forced shutdown");
+    info!("^C received, shutting down gracefully, press again for forced shutdown");
+    shutdown.cancel();
 
     tokio::select! {
         res = tokio::signal::ctrl_c() => {
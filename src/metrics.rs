@@ -0,0 +1,229 @@
+//! Counters since the bot started, rendered in the Prometheus text format
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use teloxide::RequestError;
+
+use crate::bot::stats::ChatStats;
+
+/// The most distinct parameter names counted separately, the rest are counted as [`OTHER_PARAMS_LABEL`]
+///
+/// The names come from the links users send, so they are bounded not to blow up the label cardinality
+const MAX_PARAM_LABELS: usize = 64;
+const OTHER_PARAMS_LABEL: &str = "_other";
+
+/// Counts since the bot started, unlike the persisted stats, shared between clones
+#[derive(Debug, Clone, Default)]
+pub struct UptimeMetrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    messages_processed: AtomicU64,
+    urls_cleaned: AtomicU64,
+    send_retries: AtomicU64,
+    removed_params: Mutex<BTreeMap<String, u64>>,
+    api_errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl UptimeMetrics {
+    /// Count a processed message in which `urls_cleaned` links were cleaned
+    pub fn record(&self, urls_cleaned: usize) {
+        self.0.messages_processed.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .urls_cleaned
+            .fetch_add(urls_cleaned as u64, Ordering::Relaxed);
+    }
+
+    /// Count the tracking parameters removed from the cleaned links, by name
+    pub fn record_removed_params<S: AsRef<str>>(&self, params: impl IntoIterator<Item = S>) {
+        let mut removed = self.0.removed_params.lock().unwrap();
+
+        for param in params {
+            let param = param.as_ref();
+            let label = if removed.contains_key(param) || removed.len() < MAX_PARAM_LABELS {
+                param
+            } else {
+                OTHER_PARAMS_LABEL
+            };
+
+            *removed.entry(label.to_owned()).or_default() += 1;
+        }
+    }
+
+    /// Count a message send that is retried after an error
+    pub fn record_send_retry(&self) {
+        self.0.send_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an error returned by the Telegram API, by its kind
+    pub fn record_api_error(&self, error: &RequestError) {
+        let mut errors = self.0.api_errors.lock().unwrap();
+        *errors.entry(api_error_kind(error)).or_default() += 1;
+    }
+
+    pub fn messages_processed(&self) -> u64 {
+        self.0.messages_processed.load(Ordering::Relaxed)
+    }
+
+    pub fn urls_cleaned(&self) -> u64 {
+        self.0.urls_cleaned.load(Ordering::Relaxed)
+    }
+
+    /// The counts in the form of the persisted stats, for `/stats`
+    pub(crate) fn get(&self) -> ChatStats {
+        ChatStats {
+            messages_processed: self.messages_processed(),
+            urls_cleaned: self.urls_cleaned(),
+        }
+    }
+
+    /// The counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "youtube_no_si_messages_seen_total",
+            "Messages checked for links",
+        );
+        let _ = writeln!(
+            out,
+            "youtube_no_si_messages_seen_total {}",
+            self.messages_processed()
+        );
+
+        counter(
+            &mut out,
+            "youtube_no_si_links_cleaned_total",
+            "Links replied with without tracking",
+        );
+        let _ = writeln!(
+            out,
+            "youtube_no_si_links_cleaned_total {}",
+            self.urls_cleaned()
+        );
+
+        counter(
+            &mut out,
+            "youtube_no_si_tracking_params_removed_total",
+            "Tracking parameters removed from the cleaned links, by name",
+        );
+        for (param, count) in self.0.removed_params.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "youtube_no_si_tracking_params_removed_total{{param=\"{}\"}} {count}",
+                escape_label(param)
+            );
+        }
+
+        counter(
+            &mut out,
+            "youtube_no_si_send_retries_total",
+            "Message sends retried after an error",
+        );
+        let _ = writeln!(
+            out,
+            "youtube_no_si_send_retries_total {}",
+            self.0.send_retries.load(Ordering::Relaxed)
+        );
+
+        counter(
+            &mut out,
+            "youtube_no_si_api_errors_total",
+            "Errors returned when sending messages, by kind",
+        );
+        for (kind, count) in self.0.api_errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "youtube_no_si_api_errors_total{{kind=\"{kind}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+}
+
+fn api_error_kind(error: &RequestError) -> &'static str {
+    match error {
+        RequestError::Api(_) => "api",
+        RequestError::MigrateToChatId(_) => "migrate_to_chat_id",
+        RequestError::RetryAfter(_) => "retry_after",
+        RequestError::Network(_) => "network",
+        RequestError::InvalidJson { .. } => "invalid_json",
+        RequestError::Io(_) => "io",
+    }
+}
+
+/// Escape the label value as the text format requires
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::ApiError;
+
+    use super::*;
+
+    #[test]
+    fn rendering_the_counters() {
+        let metrics = UptimeMetrics::default();
+        metrics.record(2);
+        metrics.record(0);
+        metrics.record_removed_params(["si", "pp", "si"]);
+        metrics.record_send_retry();
+        metrics.record_api_error(&RequestError::Api(ApiError::BotBlocked));
+        metrics.record_api_error(&RequestError::Api(ApiError::BotBlocked));
+
+        let rendered = metrics.render();
+
+        for line in [
+            "# TYPE youtube_no_si_messages_seen_total counter",
+            "youtube_no_si_messages_seen_total 2",
+            "youtube_no_si_links_cleaned_total 2",
+            "youtube_no_si_tracking_params_removed_total{param=\"pp\"} 1",
+            "youtube_no_si_tracking_params_removed_total{param=\"si\"} 2",
+            "youtube_no_si_send_retries_total 1",
+            "youtube_no_si_api_errors_total{kind=\"api\"} 2",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "{line}\n{rendered}");
+        }
+    }
+
+    #[test]
+    fn param_labels_are_bounded() {
+        let metrics = UptimeMetrics::default();
+        let params: Vec<_> = (0..MAX_PARAM_LABELS + 10)
+            .map(|i| format!("p{i}"))
+            .collect();
+        metrics.record_removed_params(&params);
+        // already counted names keep their label
+        metrics.record_removed_params(["p0"]);
+
+        let removed = metrics.0.removed_params.lock().unwrap();
+        assert_eq!(removed.len(), MAX_PARAM_LABELS + 1);
+        assert_eq!(removed[OTHER_PARAMS_LABEL], 10);
+        assert_eq!(removed["p0"], 2);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+    }
+}
@@ -0,0 +1,1355 @@
+use std::borrow::Cow;
+
+use serde::Serialize;
+use tracing::debug;
+use url::{Url, form_urlencoded};
+
+use crate::timestamp::{format_seconds, parse_timestamp};
+
+pub const YOUTUBE_DOMAINS: &[&str] = &[
+    "youtube.com",
+    "www.youtube.com",
+    "youtu.be",
+    "music.youtube.com",
+    "m.youtube.com",
+    "youtube-nocookie.com",
+    CONSENT_DOMAIN,
+];
+
+/// The cookie consent page shown to EU users,
+/// which carries the page to return to in the `continue` parameter
+const CONSENT_DOMAIN: &str = "consent.youtube.com";
+
+/// The domain of short `youtu.be/<id>` links, also one of the [`YOUTUBE_DOMAINS`]
+pub const SHORT_LINK_DOMAIN: &str = "youtu.be";
+
+const TELEGRAM_DOMAINS: &[&str] = &["t.me", "telegram.me"];
+/// Paths of Telegram's share links, which carry the shared link in the `url` parameter
+const SHARE_PATHS: &[&str] = &["/share", "/share/url"];
+
+/// Tracking parameters only stripped from YouTube links
+const YOUTUBE_TRACKING_PARAMS: &[&str] = &["si", "pp", "feature"];
+/// Tracking parameters stripped from links on any host
+const COMMON_TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "mc_eid"];
+/// Prefixes of tracking parameters stripped from links on any host
+const COMMON_TRACKING_PREFIXES: &[&str] = &["utm_"];
+
+/// Tracking parameters [`url_without_si`] and [`YOUTUBE_RULESET`] strip from YouTube links
+///
+/// `t` (the timestamp) and `v` (the video id) are not tracking and always kept
+pub const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "si",
+    "pp",
+    "feature",
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "gclid",
+];
+
+/// Tracking parameters stripped from the links of a set of domains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ruleset {
+    pub name: &'static str,
+    pub domains: &'static [&'static str],
+    pub params: &'static [&'static str],
+}
+
+impl Ruleset {
+    pub fn matches(&self, url: &Url) -> bool {
+        host_is_one_of(url, self.domains)
+    }
+
+    /// If the url matches the ruleset and contains any of its tracking parameters,
+    /// returns a copy of that url without them, along with the names of the removed ones
+    pub fn clean(&self, url: Url) -> Option<CleanedUrl> {
+        if !self.matches(&url) {
+            return None;
+        }
+
+        clean_params(url, self.name, self.params)
+    }
+}
+
+/// Removes the `params` from the url, returns None if it has none of them
+///
+/// Consent page links are replaced with the cleaned link they return to,
+/// even if it has no tracking, see [`unwrap_consent_link`]
+pub(crate) fn clean_params(url: Url, ruleset: &str, params: &[&str]) -> Option<CleanedUrl> {
+    if let Some(target) = unwrap_consent_link(&url) {
+        return Some(
+            clean_params(target.clone(), ruleset, params).unwrap_or(CleanedUrl {
+                url: target,
+                removed_params: Vec::new(),
+            }),
+        );
+    }
+
+    let removed_params = params_present(&url, params);
+    if removed_params.is_empty() {
+        return None;
+    }
+
+    debug!(%url, ruleset, ?removed_params, "removing tracking from URL");
+    Some(CleanedUrl {
+        url: remove_query_params(url, |key| params.contains(&key)),
+        removed_params,
+    })
+}
+
+/// A url without tracking and what was removed from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanedUrl {
+    pub url: Url,
+    /// The names of the removed tracking parameters, in the order they first appeared
+    pub removed_params: Vec<String>,
+}
+
+pub const YOUTUBE_RULESET: Ruleset = Ruleset {
+    name: "youtube",
+    domains: YOUTUBE_DOMAINS,
+    params: DEFAULT_TRACKING_PARAMS,
+};
+
+pub const SPOTIFY_RULESET: Ruleset = Ruleset {
+    name: "spotify",
+    domains: &["open.spotify.com"],
+    params: &["si"],
+};
+
+/// The rulesets [`clean_url`] picks from
+pub const RULESETS: &[Ruleset] = &[YOUTUBE_RULESET, SPOTIFY_RULESET];
+
+/// If the url matches one of the [`RULESETS`] and contains any of its tracking parameters,
+/// returns a copy of that url without them
+pub fn clean_url(url: Url) -> Option<Url> {
+    clean_url_detailed(url).map(|cleaned| cleaned.url)
+}
+
+/// Same as [`clean_url`], but also tells which parameters were removed
+pub fn clean_url_detailed(url: Url) -> Option<CleanedUrl> {
+    let ruleset = RULESETS.iter().find(|ruleset| ruleset.matches(&url))?;
+    ruleset.clean(url)
+}
+
+/// Same as [`clean_url`], but for a url that is not parsed yet
+///
+/// Returns None if the input is not a url or has nothing to clean
+pub fn clean_url_str(input: &str) -> Option<String> {
+    let url = Url::parse(input.trim()).ok()?;
+    clean_url(url).map(String::from)
+}
+
+/// If the url belongs to YouTube and contains any of the [`DEFAULT_TRACKING_PARAMS`],
+/// returns a copy of that url without them
+pub fn url_without_si(url: Url) -> Option<Url> {
+    YOUTUBE_RULESET.clean(url).map(|cleaned| cleaned.url)
+}
+
+/// Returns whether the url belongs to YouTube, along with the cleaned url if it had tracking
+///
+/// Same as calling [`url_belongs_to_youtube`] and [`url_without_si`]
+pub fn classify_and_clean(url: Url) -> (bool, Option<Url>) {
+    let is_youtube = url_belongs_to_youtube(&url);
+    (is_youtube, url_without_si(url))
+}
+
+/// What cleaning a link with [`url_without_si`] did, for scripts and other tools
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StripResult {
+    /// The link as given
+    pub input: String,
+    pub is_youtube: bool,
+    pub had_tracking: bool,
+    /// The link without tracking, the parsed input if there was nothing to remove
+    pub cleaned: String,
+    /// The removed tracking parameters, in the order they first appeared
+    pub removed_params: Vec<String>,
+}
+
+impl StripResult {
+    pub fn of(input: &str) -> Result<Self, url::ParseError> {
+        let url = Url::parse(input.trim())?;
+        let is_youtube = url_belongs_to_youtube(&url);
+        let cleaned = YOUTUBE_RULESET.clean(url.clone());
+
+        Ok(Self {
+            input: input.to_owned(),
+            is_youtube,
+            had_tracking: cleaned.is_some(),
+            removed_params: cleaned
+                .as_ref()
+                .map(|cleaned| cleaned.removed_params.clone())
+                .unwrap_or_default(),
+            cleaned: cleaned.map_or(url, |cleaned| cleaned.url).into(),
+        })
+    }
+}
+
+/// The names of the `params` in the query or the query in the fragment, without duplicates
+fn params_present(url: &Url, params: &[&str]) -> Vec<String> {
+    let mut present = Vec::new();
+    let keys = url
+        .query()
+        .into_iter()
+        .chain(fragment_query(url))
+        .flat_map(|query| query.split('&'))
+        .map(decoded_key);
+
+    for key in keys {
+        if params.contains(&key.as_str()) && !present.contains(&key) {
+            present.push(key);
+        }
+    }
+
+    present
+}
+
+/// Removes all known tracking parameters from the url
+///
+/// YouTube-specific parameters (`si`, `pp`, `feature`) are only removed from YouTube links,
+/// common cross-site ones (`utm_*`, `fbclid`, `gclid`, `mc_eid`) are removed from any link.
+/// Unlike [`url_without_si`], always returns a url, which is unchanged if nothing matched
+pub fn strip_all_tracking(url: Url) -> Url {
+    let is_youtube = url_belongs_to_youtube(&url);
+    let is_tracking = |key: &str| {
+        (is_youtube && YOUTUBE_TRACKING_PARAMS.contains(&key))
+            || COMMON_TRACKING_PARAMS.contains(&key)
+            || COMMON_TRACKING_PREFIXES
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+    };
+
+    if !has_param(&url, is_tracking) {
+        return url;
+    }
+
+    remove_query_params(url, is_tracking)
+}
+
+/// Rewrites the host of a YouTube url to a privacy frontend (e.g. an Invidious or Piped instance),
+/// keeping the path and the query
+///
+/// Returns None if `host` is not a valid host
+pub fn rewrite_to_frontend(mut url: Url, host: &str) -> Option<Url> {
+    url.set_host(Some(host)).ok()?;
+    url.set_scheme("https").ok()?;
+    url.set_port(None).ok()?;
+
+    debug!(%url, "rewrote URL to the frontend");
+    Some(url)
+}
+
+/// Whether the string looks like a hostname a frontend could be served at, e.g. `yewtu.be`
+///
+/// Requires at least two dot-separated labels of ASCII letters, digits and inner hyphens
+pub fn is_plausible_host(host: &str) -> bool {
+    let labels: Vec<_> = host.split('.').collect();
+
+    host.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+/// Expands a short `youtu.be/<id>` link to the `https://www.youtube.com/watch?v=<id>` form,
+/// keeping the timestamp and any other query parameters after the video id
+///
+/// The timestamp is converted to plain seconds, see [`canonical_timestamp`]
+///
+/// Other urls, including short links with extra path segments, are returned unchanged
+pub fn expand_short_link(url: Url) -> Url {
+    if !is_short_link(&url) {
+        return url;
+    }
+
+    let Some(id) = url
+        .path_segments()
+        .and_then(|mut segments| segments.next().filter(|_| segments.next().is_none()))
+        .filter(|id| !id.is_empty())
+    else {
+        return url;
+    };
+
+    let mut query = format!("v={id}");
+    if let Some(params) = url.query().filter(|params| !params.is_empty()) {
+        for param in params.split('&') {
+            query.push('&');
+            query.push_str(&canonical_timestamp(param));
+        }
+    }
+
+    let mut expanded = Url::parse("https://www.youtube.com/watch").expect("the base url is valid");
+    expanded.set_query(Some(&query));
+    expanded.set_fragment(url.fragment());
+
+    debug!(%url, %expanded, "expanded the short link");
+    expanded
+}
+
+/// The `t` query parameter with the timestamp in plain seconds, e.g. `t=173` for `t=2m53s`,
+/// which both the short and the long links understand
+///
+/// Other parameters and timestamps that don't parse are returned unchanged
+fn canonical_timestamp(param: &str) -> Cow<'_, str> {
+    match param.split_once('=') {
+        Some(("t", t)) => match parse_timestamp(t) {
+            Some(secs) => Cow::Owned(format!("t={}", format_seconds(secs))),
+            None => Cow::Borrowed(param),
+        },
+        _ => Cow::Borrowed(param),
+    }
+}
+
+/// The YouTube url embedded in a Telegram share link,
+/// e.g. `https://t.me/share/url?url=https%3A%2F%2Fyoutu.be%2Fabc%3Fsi%3Dxyz`
+///
+/// Returns None for any other link, including ordinary `t.me` links
+/// and share links embedding something other than a YouTube url
+pub fn unwrap_share_link(url: &Url) -> Option<Url> {
+    if !host_is_one_of(url, TELEGRAM_DOMAINS) || !SHARE_PATHS.contains(&url.path()) {
+        return None;
+    }
+
+    let (_, embedded) = url.query_pairs().find(|(key, _)| key == "url")?;
+    let embedded = Url::parse(embedded.trim()).ok()?;
+
+    if !url_belongs_to_youtube(&embedded) {
+        return None;
+    }
+
+    debug!(%url, %embedded, "unwrapped the share link");
+    Some(embedded)
+}
+
+/// The YouTube url a consent page link returns to, e.g. the video in
+/// `https://consent.youtube.com/m?continue=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3Dabc%26si%3Dxyz`
+///
+/// Returns None for other links and for consent links returning to anything but a YouTube page
+pub fn unwrap_consent_link(url: &Url) -> Option<Url> {
+    if !host_is_one_of(url, &[CONSENT_DOMAIN]) {
+        return None;
+    }
+
+    let (_, target) = url.query_pairs().find(|(key, _)| key == "continue")?;
+    let target = Url::parse(target.trim()).ok()?;
+
+    // a consent link returning to another one would be unwrapped forever
+    if !url_belongs_to_youtube(&target) || host_is_one_of(&target, &[CONSENT_DOMAIN]) {
+        return None;
+    }
+
+    debug!(%url, %target, "unwrapped the consent link");
+    Some(target)
+}
+
+/// Removes the query parameters with the given keys, keeping the rest in their order
+pub fn remove_tracking_params(url: Url, params: &[&str]) -> Url {
+    debug!(%url, ?params, "removing tracking params from URL");
+
+    remove_query_params(url, |key| params.contains(&key))
+}
+
+/// Removes every query parameter for which `should_remove` returns true
+///
+/// The kept parameters are copied as is, without decoding and encoding them again,
+/// so values with encoded reserved characters (e.g. `%26` in a search query) stay intact
+///
+/// The query of a single-page app style fragment (see [`fragment_query`]) is cleaned the same way
+fn remove_query_params(mut url: Url, should_remove: impl Fn(&str) -> bool) -> Url {
+    if let Some((route, query)) = split_fragment_query(&url) {
+        let kept_pairs = kept_query_pairs(query, &should_remove);
+        let new_fragment = if kept_pairs.is_empty() {
+            route.to_owned()
+        } else {
+            format!("{route}?{kept_pairs}")
+        };
+
+        url.set_fragment(Some(&new_fragment));
+        debug!(%url, "cleaned the query in the fragment");
+    }
+
+    let kept_pairs = kept_query_pairs(url.query().unwrap_or_default(), &should_remove);
+
+    if kept_pairs.is_empty() {
+        url.set_query(None);
+        debug!(%url, "URL has no other query params, cleared the query");
+        return url;
+    }
+
+    url.set_query(Some(&kept_pairs));
+    debug!(%url, "restored other query params");
+    url
+}
+
+/// The raw pairs of the query for which `should_remove` returns false, joined back with `&`
+fn kept_query_pairs(query: &str, should_remove: impl Fn(&str) -> bool) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| !should_remove(&decoded_key(pair)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Whether the query, or the query in the fragment, has a parameter matching `is_param`
+fn has_param(url: &Url, is_param: impl Fn(&str) -> bool) -> bool {
+    url.query()
+        .into_iter()
+        .chain(fragment_query(url))
+        .flat_map(|query| query.split('&'))
+        .any(|pair| is_param(&decoded_key(pair)))
+}
+
+/// The query of a single-page app style route in the fragment,
+/// e.g. `v=abc&si=xyz` for `https://www.youtube.com/#/watch?v=abc&si=xyz`
+fn fragment_query(url: &Url) -> Option<&str> {
+    split_fragment_query(url).map(|(_route, query)| query)
+}
+
+fn split_fragment_query(url: &Url) -> Option<(&str, &str)> {
+    url.fragment()?.split_once('?')
+}
+
+/// The percent-decoded key of a raw `key=value` query pair
+fn decoded_key(pair: &str) -> String {
+    form_urlencoded::parse(pair.as_bytes())
+        .next()
+        .map(|(key, _value)| key.into_owned())
+        .unwrap_or_default()
+}
+
+/// Whether the url has any of the [`DEFAULT_TRACKING_PARAMS`], so it needs cleaning
+pub fn url_has_tracking(url: &Url) -> bool {
+    debug!(%url, "checking if the URL contains tracking params");
+
+    has_param(url, |key| DEFAULT_TRACKING_PARAMS.contains(&key))
+}
+
+#[deprecated(note = "checks all of the tracking params now, use `url_has_tracking`")]
+pub fn url_has_si(url: &Url) -> bool {
+    url_has_tracking(url)
+}
+
+pub fn url_belongs_to_youtube(url: &Url) -> bool {
+    debug!(%url, "checking if URL belongs to YouTube");
+
+    host_is_one_of(url, YOUTUBE_DOMAINS)
+}
+
+/// Whether the url is a short `youtu.be` link
+pub fn is_short_link(url: &Url) -> bool {
+    host_is_one_of(url, &[SHORT_LINK_DOMAIN])
+}
+
+/// Whether the host of the url is one of the domains, ignoring case
+///
+/// The url crate lowercases the hosts of http(s) urls, but not of every scheme
+pub(crate) fn host_is_one_of(url: &Url, domains: &[&str]) -> bool {
+    let Some(url::Host::Domain(host)) = url.host() else {
+        return false;
+    };
+
+    domains
+        .iter()
+        .any(|domain| domain.eq_ignore_ascii_case(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS: &str = include_str!("../tests/fixtures/url_corpus.txt");
+
+    /// Parse the `input -> expected` pairs of the corpus, `expected` is None for `none`
+    fn corpus_cases(corpus: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+        corpus
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (input, expected) = line
+                    .split_once(" -> ")
+                    .unwrap_or_else(|| panic!("malformed corpus line: {line}"));
+                let expected = expected.trim();
+
+                (input.trim(), (expected != "none").then_some(expected))
+            })
+    }
+
+    #[test]
+    fn cleaning_matches_the_corpus() -> anyhow::Result<()> {
+        let mut cases = 0;
+
+        for (input, expected) in corpus_cases(CORPUS) {
+            let expected = expected.map(Url::parse).transpose()?;
+            assert_eq!(url_without_si(Url::parse(input)?), expected, "{input}");
+            cases += 1;
+        }
+
+        assert!(cases > 0, "the corpus is empty");
+
+        Ok(())
+    }
+
+    #[test]
+    fn rewriting_to_frontend_keeps_path_and_query() -> anyhow::Result<()> {
+        let cleaned = url_without_si(Url::parse(
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up&t=10",
+        )?)
+        .unwrap();
+
+        assert_eq!(
+            rewrite_to_frontend(cleaned, "yewtu.be"),
+            Some(Url::parse("https://yewtu.be/watch?v=3foYyPDp0Ho&t=10")?)
+        );
+
+        assert_eq!(
+            rewrite_to_frontend(
+                Url::parse("https://youtu.be/0FwBHrVuMJc")?,
+                "piped.example.org"
+            ),
+            Some(Url::parse("https://piped.example.org/0FwBHrVuMJc")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rewriting_to_invalid_frontend_fails() -> anyhow::Result<()> {
+        let url = Url::parse("https://youtu.be/0FwBHrVuMJc")?;
+
+        assert_eq!(rewrite_to_frontend(url.clone(), ""), None);
+        assert_eq!(rewrite_to_frontend(url, "bad host"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_query_survives_removing_si() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/results?search_query=never+gonna%20give%26take&si=xyz"
+            )?),
+            Some(Url::parse(
+                "https://www.youtube.com/results?search_query=never+gonna%20give%26take"
+            )?)
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/results?si=xyz&search_query=caf%C3%A9%3Dbar&sp=EgIQAQ%253D%253D"
+            )?),
+            Some(Url::parse(
+                "https://www.youtube.com/results?search_query=caf%C3%A9%3Dbar&sp=EgIQAQ%253D%253D"
+            )?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keyless_si_is_removed() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "https://youtu.be/0FwBHrVuMJc?si",
+                "https://youtu.be/0FwBHrVuMJc",
+            ),
+            (
+                "https://www.youtube.com/watch?x=1&si",
+                "https://www.youtube.com/watch?x=1",
+            ),
+            (
+                "https://www.youtube.com/watch?feature=share&si&v=3foYyPDp0Ho",
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+            ),
+            (
+                "https://www.youtube.com/watch?app=desktop&si=xyz&v=3foYyPDp0Ho",
+                "https://www.youtube.com/watch?app=desktop&v=3foYyPDp0Ho",
+            ),
+        ];
+
+        for (input, cleaned) in cases {
+            let url = Url::parse(input)?;
+            assert!(url_has_tracking(&url), "{input}");
+            #[allow(deprecated)]
+            let has_si = url_has_si(&url);
+            assert!(has_si, "{input}");
+            assert_eq!(url_without_si(url), Some(Url::parse(cleaned)?), "{input}");
+        }
+
+        // keys only starting with si are kept
+        assert!(!url_has_tracking(&Url::parse(
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho&sit&size=1"
+        )?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn removing_si_from_hashtag_urls() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse("https://www.youtube.com/hashtag/rust?si=xyz")?),
+            Some(Url::parse("https://www.youtube.com/hashtag/rust")?)
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/hashtag/%D0%BA%D0%BE%D1%82?si=xyz&app=desktop"
+            )?),
+            Some(Url::parse(
+                "https://www.youtube.com/hashtag/%D0%BA%D0%BE%D1%82?app=desktop"
+            )?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn removing_si_from_fragment_routes() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse("https://www.youtube.com/#/watch?v=abc&si=xyz")?),
+            Some(Url::parse("https://www.youtube.com/#/watch?v=abc")?)
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse("https://m.youtube.com/#/watch?si=xyz")?),
+            Some(Url::parse("https://m.youtube.com/#/watch")?)
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse("https://example.org/#/watch?si=xyz")?),
+            None,
+            "not a YouTube domain"
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse("https://www.youtube.com/#/watch?si=xyz")?),
+            Some(Url::parse("https://www.youtube.com/#/watch")?)
+        );
+
+        assert_eq!(
+            clean_url(Url::parse(
+                "https://www.youtube.com/?app=m#/watch?si=xyz&v=abc"
+            )?),
+            Some(Url::parse("https://www.youtube.com/?app=m#/watch?v=abc")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fragments_without_queries_are_ignored() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse("https://www.youtube.com/watch?v=abc#si=xyz")?),
+            None
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/watch?v=abc&si=xyz#comments"
+            )?),
+            Some(Url::parse("https://www.youtube.com/watch?v=abc#comments")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_fragments_are_preserved() -> anyhow::Result<()> {
+        let url = Url::parse("https://www.youtube.com/watch?v=x&si=y#t=30")?;
+        let expected = Url::parse("https://www.youtube.com/watch?v=x#t=30")?;
+
+        assert_eq!(url_without_si(url.clone()), Some(expected.clone()));
+        assert_eq!(clean_url(url.clone()), Some(expected.clone()));
+        assert_eq!(strip_all_tracking(url), expected);
+
+        assert_eq!(
+            url_without_si(Url::parse("https://youtu.be/x?si=y&t=5#t=30")?).map(expand_short_link),
+            Some(Url::parse("https://www.youtube.com/watch?v=x&t=5#t=30")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cleaning_unparsed_urls() {
+        assert_eq!(
+            clean_url_str(" https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173\n").as_deref(),
+            Some("https://youtu.be/FiwMTquj-rQ?t=173")
+        );
+        assert_eq!(clean_url_str("https://youtu.be/FiwMTquj-rQ"), None);
+        assert_eq!(clean_url_str("not a url"), None);
+    }
+
+    #[test]
+    fn classifying_and_cleaning() -> anyhow::Result<()> {
+        assert_eq!(
+            classify_and_clean(Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?),
+            (true, None)
+        );
+
+        assert_eq!(
+            classify_and_clean(Url::parse(
+                "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173"
+            )?),
+            (
+                true,
+                Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+            )
+        );
+
+        assert_eq!(
+            classify_and_clean(Url::parse("https://example.org/meow?si=23")?),
+            (false, None)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_is_preserved_byte_for_byte() -> anyhow::Result<()> {
+        let paths = [
+            "/watch",
+            "/shorts/a-B_9c-D_0e",
+            "/clip/Ugkx-3_oDkE_lHu0mJ4Zq-8_yx9_d2e1LwXA",
+            "/post/Ugkx_9-8bYrQ1-2_3cD4",
+            "/FiwMTquj-rQ",
+            "/_-_-_123",
+        ];
+
+        for path in paths {
+            let url = Url::parse(&format!("https://www.youtube.com{path}?si=xyz&t=5"))?;
+            let cleaned = url_without_si(url).unwrap();
+
+            assert_eq!(cleaned.path(), path);
+            assert_eq!(
+                cleaned.as_str(),
+                format!("https://www.youtube.com{path}?t=5")
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn all_default_tracking_params_are_removed() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&utm_source=app&t=173"
+            )?),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+        );
+
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&feature=shared&utm_medium=x&utm_campaign=y&gclid=z&t=10"
+            )?),
+            Some(Url::parse(
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&t=10"
+            )?)
+        );
+
+        // tracking other than si is enough to clean the link
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&utm_source=app"
+            )?),
+            Some(Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn removing_custom_tracking_params() -> anyhow::Result<()> {
+        assert_eq!(
+            remove_tracking_params(
+                Url::parse("https://youtu.be/FiwMTquj-rQ?si=abc&pp=def&t=173")?,
+                &["pp"]
+            ),
+            Url::parse("https://youtu.be/FiwMTquj-rQ?si=abc&t=173")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn music_mobile_and_nocookie_links_are_cleaned() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "https://music.youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r",
+                "https://music.youtube.com/watch?v=3foYyPDp0Ho",
+            ),
+            (
+                "https://m.youtube.com/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r&t=10",
+                "https://m.youtube.com/watch?v=3foYyPDp0Ho&t=10",
+            ),
+            (
+                "https://youtube-nocookie.com/embed/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r",
+                "https://youtube-nocookie.com/embed/3foYyPDp0Ho",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let url = Url::parse(input)?;
+            assert!(url_belongs_to_youtube(&url), "{input}");
+
+            let expected = Some(Url::parse(expected)?);
+            assert_eq!(url_without_si(url.clone()), expected, "{input}");
+            assert_eq!(clean_url(url), expected, "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn shorts_live_and_embed_links_are_cleaned() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "https://www.youtube.com/shorts/a-B_9c-D_0e?si=KuczOyCr1s5_Ou0r",
+                "https://www.youtube.com/shorts/a-B_9c-D_0e",
+            ),
+            (
+                // `feature` is tracking as well, see DEFAULT_TRACKING_PARAMS
+                "https://youtube.com/shorts/a-B_9c-D_0e?feature=share&si=KuczOyCr1s5_Ou0r",
+                "https://youtube.com/shorts/a-B_9c-D_0e",
+            ),
+            (
+                "https://www.youtube.com/live/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r&t=120",
+                "https://www.youtube.com/live/3foYyPDp0Ho?t=120",
+            ),
+            (
+                "https://www.youtube.com/embed/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r&start=30",
+                "https://www.youtube.com/embed/3foYyPDp0Ho?start=30",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let url = Url::parse(input)?;
+            assert!(url_belongs_to_youtube(&url), "{input}");
+
+            let cleaned = url_without_si(url.clone()).unwrap();
+            assert_eq!(cleaned, Url::parse(expected)?, "{input}");
+            // the path with the video id is left as is
+            assert_eq!(cleaned.path(), url.path(), "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn short_links_are_recognized() -> anyhow::Result<()> {
+        assert!(is_short_link(&Url::parse("https://youtu.be/0FwBHrVuMJc")?));
+        assert!(is_short_link(&Url::parse(
+            "youtube://Youtu.Be/0FwBHrVuMJc"
+        )?));
+        assert!(!is_short_link(&Url::parse(
+            "https://www.youtube.com/watch?v=0FwBHrVuMJc"
+        )?));
+        assert!(!is_short_link(&Url::parse(
+            "https://notyoutu.be/0FwBHrVuMJc"
+        )?));
+        assert!(YOUTUBE_DOMAINS.contains(&SHORT_LINK_DOMAIN));
+
+        Ok(())
+    }
+
+    #[test]
+    fn consent_links_are_unwrapped_and_cleaned() -> anyhow::Result<()> {
+        let consent = Url::parse(
+            "https://consent.youtube.com/m?continue=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3D3foYyPDp0Ho%26si%3DKuczOyCr1s5_Ou0r%26cbrd%3D1&gl=DE&m=0&pc=yt&cm=2&hl=de&src=1",
+        )?;
+        assert!(url_belongs_to_youtube(&consent));
+
+        let expected = Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho&cbrd=1")?;
+        assert_eq!(url_without_si(consent.clone()), Some(expected.clone()));
+        assert_eq!(
+            clean_url_detailed(consent),
+            Some(CleanedUrl {
+                url: expected,
+                removed_params: vec!["si".to_owned()],
+            })
+        );
+
+        // the link is unwrapped even without tracking in it
+        let consent = Url::parse(
+            "https://consent.youtube.com/m?continue=https%3A%2F%2Fyoutu.be%2F0FwBHrVuMJc",
+        )?;
+        assert_eq!(
+            url_without_si(consent),
+            Some(Url::parse("https://youtu.be/0FwBHrVuMJc")?)
+        );
+
+        // only links back to YouTube are unwrapped
+        for input in [
+            "https://consent.youtube.com/m?continue=https%3A%2F%2Fexample.org%2F%3Fsi%3Dabc",
+            "https://consent.youtube.com/m?continue=https%3A%2F%2Fconsent.youtube.com%2Fm",
+            "https://consent.youtube.com/m?gl=DE",
+        ] {
+            assert_eq!(unwrap_consent_link(&Url::parse(input)?), None, "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_v_links_are_cleaned() -> anyhow::Result<()> {
+        let url = Url::parse("https://www.youtube.com/v/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r&hl=en")?;
+
+        assert_eq!(
+            url_without_si(url),
+            Some(Url::parse("https://www.youtube.com/v/3foYyPDp0Ho?hl=en")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn domains_are_matched_ignoring_case() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "https://WWW.YOUTUBE.COM/watch?v=3foYyPDp0Ho&si=KuczOyCr1s5_Ou0r",
+                Some("v=3foYyPDp0Ho"),
+            ),
+            ("https://Youtu.Be/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r", None),
+            // not lowercased by the url crate, as the scheme isn't special
+            ("youtube://Youtu.Be/3foYyPDp0Ho?si=KuczOyCr1s5_Ou0r", None),
+        ];
+
+        for (input, query) in cases {
+            let url = Url::parse(input)?;
+            assert!(url_belongs_to_youtube(&url), "{input}");
+            assert!(YOUTUBE_RULESET.matches(&url), "{input}");
+
+            let cleaned = url_without_si(url).unwrap();
+            assert_eq!(cleaned.query(), query, "{input}");
+            assert!(
+                !cleaned.query_pairs().any(|(key, _)| key == "si"),
+                "{input}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn any_tracking_param_alone_triggers_cleaning() -> anyhow::Result<()> {
+        let cases = [
+            (
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&pp=ygUFbWVvdw%3D%3D",
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+            ),
+            (
+                "https://youtu.be/3foYyPDp0Ho?feature=shared",
+                "https://youtu.be/3foYyPDp0Ho",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let url = Url::parse(input)?;
+            assert!(url_has_tracking(&url), "{input}");
+
+            let expected = Some(Url::parse(expected)?);
+            assert_eq!(url_without_si(url.clone()), expected, "{input}");
+            assert_eq!(clean_url(url), expected, "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn channel_handles_are_preserved() -> anyhow::Result<()> {
+        let handles = [
+            "/@SomeChannel",
+            "/@some.channel-name_1",
+            "/@SomeChannel/videos",
+        ];
+
+        for handle in handles {
+            let url = Url::parse(&format!("https://www.youtube.com{handle}?si=xyz"))?;
+
+            let cleaned = url_without_si(url.clone()).unwrap();
+            assert_eq!(cleaned.path(), handle);
+            assert_eq!(cleaned.as_str(), format!("https://www.youtube.com{handle}"));
+
+            assert_eq!(clean_url(url), Some(cleaned));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn clean_url_picks_the_matching_ruleset() -> anyhow::Result<()> {
+        assert_eq!(
+            clean_url(Url::parse(
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123"
+            )?),
+            Some(Url::parse(
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+            )?)
+        );
+
+        assert_eq!(
+            clean_url(Url::parse(
+                "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173"
+            )?),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+        );
+
+        assert_eq!(
+            clean_url(Url::parse("https://example.org/meow?si=23")?),
+            None
+        );
+        assert_eq!(
+            clean_url(Url::parse(
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+            )?),
+            None
+        );
+
+        Ok(())
+    }
+
+    /// Cleaned links are compared as strings for deduplication,
+    /// the url crate normalizes the host and the port of http(s) links when parsing them
+    #[test]
+    fn cleaned_links_have_normalized_hosts_and_ports() -> anyhow::Result<()> {
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://WWW.YouTube.com:443/watch?v=3foYyPDp0Ho&si=xyz"
+            )?)
+            .map(String::from),
+            Some("https://www.youtube.com/watch?v=3foYyPDp0Ho".to_owned())
+        );
+        // the path and the query keep their case
+        assert_eq!(
+            url_without_si(Url::parse("http://YOUTU.BE:80/FiwMTquj-rQ?si=xyz&t=173")?)
+                .map(String::from),
+            Some("http://youtu.be/FiwMTquj-rQ?t=173".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_all_tracking_cleans_youtube_links() -> anyhow::Result<()> {
+        assert_eq!(
+            strip_all_tracking(Url::parse(
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=abc&pp=def&feature=shared&t=10"
+            )?),
+            Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho&t=10")?
+        );
+
+        assert_eq!(
+            strip_all_tracking(Url::parse(
+                "https://youtu.be/0FwBHrVuMJc?utm_source=newsletter&si=drdl-LZXYJzZPIce"
+            )?),
+            Url::parse("https://youtu.be/0FwBHrVuMJc")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_all_tracking_cleans_common_trackers_on_any_host() -> anyhow::Result<()> {
+        assert_eq!(
+            strip_all_tracking(Url::parse(
+                "https://example.org/article?id=5&utm_source=x&utm_medium=y&fbclid=z"
+            )?),
+            Url::parse("https://example.org/article?id=5")?
+        );
+
+        assert_eq!(
+            strip_all_tracking(Url::parse(
+                "https://shop.example.com/?gclid=abc&mc_eid=def"
+            )?),
+            Url::parse("https://shop.example.com/")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_all_tracking_keeps_youtube_params_on_other_hosts() -> anyhow::Result<()> {
+        let urls = [
+            "https://example.org/meow?si=23&feature=cats",
+            "https://google.com/hii",
+            "https://www.youtube.com/watch?v=nFuAJl46w_w&t=229s",
+        ];
+
+        for url in urls {
+            let url = Url::parse(url)?;
+            assert_eq!(strip_all_tracking(url.clone()), url);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn expanding_short_links() -> anyhow::Result<()> {
+        assert_eq!(
+            expand_short_link(Url::parse("https://youtu.be/FiwMTquj-rQ")?),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn expanding_short_links_keeps_the_timestamp_and_other_params() -> anyhow::Result<()> {
+        assert_eq!(
+            expand_short_link(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ&t=173")?
+        );
+
+        let cleaned = url_without_si(Url::parse(
+            "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&list=PL123&t=173",
+        )?)
+        .unwrap();
+        assert_eq!(
+            expand_short_link(cleaned),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ&list=PL123&t=173")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn expanding_short_links_converts_the_timestamp_to_seconds() -> anyhow::Result<()> {
+        assert_eq!(
+            expand_short_link(Url::parse(
+                "https://youtu.be/FiwMTquj-rQ?t=2m53s&list=PL123"
+            )?),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ&t=173&list=PL123")?
+        );
+        assert_eq!(
+            expand_short_link(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173s")?),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ&t=173")?
+        );
+        // timestamps that don't parse are kept as the user wrote them
+        assert_eq!(
+            expand_short_link(Url::parse("https://youtu.be/FiwMTquj-rQ?t=soon")?),
+            Url::parse("https://www.youtube.com/watch?v=FiwMTquj-rQ&t=soon")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_short_video_links_are_expanded() -> anyhow::Result<()> {
+        let urls = [
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho&t=10",
+            "https://youtu.be/",
+            "https://youtu.be/abc/def?t=5",
+            "https://example.org/FiwMTquj-rQ",
+        ];
+
+        for url in urls {
+            let url = Url::parse(url)?;
+            assert_eq!(expand_short_link(url.clone()), url);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unwrapping_share_links() -> anyhow::Result<()> {
+        let shared = Url::parse(
+            "https://t.me/share/url?url=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ%3Fsi%3DKuczOyCr1s5_Ou0r%26t%3D173&text=look",
+        )?;
+
+        let embedded = unwrap_share_link(&shared);
+        assert_eq!(
+            embedded,
+            Some(Url::parse(
+                "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173"
+            )?)
+        );
+        assert_eq!(
+            embedded.and_then(url_without_si),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+        );
+
+        assert!(
+            unwrap_share_link(&Url::parse(
+                "https://telegram.me/share?url=https://www.youtube.com/watch?v=3foYyPDp0Ho%26si=abc"
+            )?)
+            .is_some()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn plain_telegram_links_are_not_unwrapped() -> anyhow::Result<()> {
+        let urls = [
+            "https://t.me/durov",
+            "https://t.me/some_channel/123?url=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ",
+            "https://t.me/share/url?text=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ",
+            "https://t.me/share/url?url=https%3A%2F%2Fexample.org%2F%3Fsi%3Dabc",
+            "https://t.me/share/url?url=not%20a%20url",
+            "https://example.org/share/url?url=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ",
+        ];
+
+        for url in urls {
+            assert_eq!(unwrap_share_link(&Url::parse(url)?), None, "{url}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_result_of_a_tracked_youtube_link() -> anyhow::Result<()> {
+        let result = StripResult::of("https://youtu.be/FiwMTquj-rQ?si=abc&t=173&feature=shared")?;
+
+        assert_eq!(
+            serde_json::to_value(&result)?,
+            serde_json::json!({
+                "input": "https://youtu.be/FiwMTquj-rQ?si=abc&t=173&feature=shared",
+                "is_youtube": true,
+                "had_tracking": true,
+                "cleaned": "https://youtu.be/FiwMTquj-rQ?t=173",
+                "removed_params": ["si", "feature"],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_result_of_a_clean_youtube_link() -> anyhow::Result<()> {
+        let result = StripResult::of("https://www.youtube.com/watch?v=3foYyPDp0Ho")?;
+
+        assert_eq!(
+            serde_json::to_value(&result)?,
+            serde_json::json!({
+                "input": "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+                "is_youtube": true,
+                "had_tracking": false,
+                "cleaned": "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+                "removed_params": [],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_result_of_other_links() -> anyhow::Result<()> {
+        let result = StripResult::of("https://example.org/meow?si=23")?;
+
+        assert_eq!(
+            serde_json::to_value(&result)?,
+            serde_json::json!({
+                "input": "https://example.org/meow?si=23",
+                "is_youtube": false,
+                "had_tracking": false,
+                "cleaned": "https://example.org/meow?si=23",
+                "removed_params": [],
+            })
+        );
+
+        assert!(StripResult::of("not a url").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn every_si_param_is_removed() -> anyhow::Result<()> {
+        let url = Url::parse("https://youtu.be/FiwMTquj-rQ?si=abc&t=173&si=def")?;
+
+        assert!(url_has_tracking(&url));
+        assert_eq!(
+            url_without_si(url),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn percent_encoded_keys_are_decoded() -> anyhow::Result<()> {
+        let url = Url::parse("https://youtu.be/FiwMTquj-rQ?%73i=abc&t=173")?;
+
+        assert!(url_has_tracking(&url));
+        assert_eq!(
+            url_without_si(url),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn si_inside_values_is_not_a_param() -> anyhow::Result<()> {
+        // the value is `never&si=gonna` once decoded
+        let url = Url::parse("https://www.youtube.com/results?search_query=never%26si%3Dgonna")?;
+        assert!(!url_has_tracking(&url));
+        assert_eq!(url_without_si(url), None);
+
+        assert_eq!(
+            url_without_si(Url::parse(
+                "https://www.youtube.com/results?search_query=never%26si%3Dgonna&si=abc"
+            )?),
+            Some(Url::parse(
+                "https://www.youtube.com/results?search_query=never%26si%3Dgonna"
+            )?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cleaning_tells_the_removed_params() -> anyhow::Result<()> {
+        let cleaned = clean_url_detailed(Url::parse(
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=abc&utm_source=tg&t=10&si=def#/x?pp=1",
+        )?)
+        .unwrap();
+
+        assert_eq!(
+            cleaned.url,
+            Url::parse("https://www.youtube.com/watch?v=3foYyPDp0Ho&t=10#/x")?
+        );
+        assert_eq!(cleaned.removed_params, ["si", "utm_source", "pp"]);
+
+        // only the params of the matching ruleset
+        let cleaned = clean_url_detailed(Url::parse(
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc&utm_source=tg",
+        )?)
+        .unwrap();
+        assert_eq!(cleaned.removed_params, ["si"]);
+
+        assert_eq!(
+            clean_url_detailed(Url::parse("https://youtu.be/FiwMTquj-rQ?t=173")?),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validating_frontend_hosts() {
+        for host in ["yewtu.be", "piped.example.org", "invidious-1.example.co.uk"] {
+            assert!(is_plausible_host(host), "{host}");
+        }
+
+        for host in [
+            "",
+            "localhost",
+            "yewtu..be",
+            "-yewtu.be",
+            "yewtu-.be",
+            "yewtu.be/watch",
+            "https://yewtu.be",
+            "yewtu.be:8080",
+            "bad host.org",
+        ] {
+            assert!(!is_plausible_host(host), "{host}");
+        }
+    }
+}
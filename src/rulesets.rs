@@ -0,0 +1,227 @@
+//! Rulesets that can change at runtime, e.g. when fetched from a centrally maintained blocklist
+
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    remove_si::{CleanedUrl, RULESETS, Ruleset, clean_params, host_is_one_of, is_plausible_host},
+    transform::UrlTransform,
+};
+
+/// Same as [`Ruleset`], but owning its data so it can be loaded at runtime
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DynamicRuleset {
+    pub name: String,
+    pub domains: Vec<String>,
+    pub params: Vec<String>,
+}
+
+impl DynamicRuleset {
+    pub fn matches(&self, url: &Url) -> bool {
+        let domains: Vec<_> = self.domains.iter().map(String::as_str).collect();
+        host_is_one_of(url, &domains)
+    }
+
+    /// If the url matches the ruleset and contains any of its tracking parameters,
+    /// returns a copy of that url without them, along with the names of the removed ones
+    pub fn clean(&self, url: Url) -> Option<CleanedUrl> {
+        if !self.matches(&url) {
+            return None;
+        }
+
+        let params: Vec<_> = self.params.iter().map(String::as_str).collect();
+        clean_params(url, &self.name, &params)
+    }
+}
+
+impl From<&Ruleset> for DynamicRuleset {
+    fn from(ruleset: &Ruleset) -> Self {
+        Self {
+            name: ruleset.name.to_owned(),
+            domains: ruleset
+                .domains
+                .iter()
+                .map(|&domain| domain.to_owned())
+                .collect(),
+            params: ruleset
+                .params
+                .iter()
+                .map(|&param| param.to_owned())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum RulesetsError {
+    #[error("no rulesets")]
+    Empty,
+    #[error("invalid ruleset `{name}`: {reason}")]
+    InvalidRuleset { name: String, reason: String },
+}
+
+/// Check that every ruleset has a name, valid domains and valid parameter names
+///
+/// Catches blocklists that would make the bot stop cleaning links, or clean everything
+pub fn validate_rulesets(rulesets: &[DynamicRuleset]) -> Result<(), RulesetsError> {
+    if rulesets.is_empty() {
+        return Err(RulesetsError::Empty);
+    }
+
+    for ruleset in rulesets {
+        let invalid = |reason: String| RulesetsError::InvalidRuleset {
+            name: ruleset.name.clone(),
+            reason,
+        };
+
+        if ruleset.name.trim().is_empty() {
+            return Err(invalid("the name is empty".to_owned()));
+        }
+
+        if ruleset.domains.is_empty() {
+            return Err(invalid("no domains".to_owned()));
+        }
+
+        if let Some(domain) = ruleset
+            .domains
+            .iter()
+            .find(|domain| !is_plausible_host(domain))
+        {
+            return Err(invalid(format!("`{domain}` is not a valid domain")));
+        }
+
+        if ruleset.params.is_empty() {
+            return Err(invalid("no params".to_owned()));
+        }
+
+        if let Some(param) = ruleset
+            .params
+            .iter()
+            .find(|param| param.is_empty() || param.contains(['&', '=', '#', '?']))
+        {
+            return Err(invalid(format!("`{param}` is not a valid param name")));
+        }
+    }
+
+    Ok(())
+}
+
+/// The rulesets in use, shared between clones and swapped as a whole,
+/// the built-in [`RULESETS`] by default
+#[derive(Debug, Clone)]
+pub struct SharedRulesets(Arc<RwLock<Arc<[DynamicRuleset]>>>);
+
+impl SharedRulesets {
+    pub fn get(&self) -> Arc<[DynamicRuleset]> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Replace the rulesets, cleaning already in progress keeps using the previous ones
+    pub fn set(&self, rulesets: Vec<DynamicRuleset>) {
+        *self.0.write().unwrap() = rulesets.into();
+    }
+
+    /// A transform cleaning links with the current rulesets
+    pub fn stripper(&self) -> RulesetStripper {
+        RulesetStripper(self.get())
+    }
+}
+
+impl Default for SharedRulesets {
+    fn default() -> Self {
+        let rulesets: Arc<[DynamicRuleset]> = RULESETS.iter().map(DynamicRuleset::from).collect();
+        Self(Arc::new(RwLock::new(rulesets)))
+    }
+}
+
+/// Same as the [`SiStripper`](crate::transform::SiStripper), but with dynamic rulesets
+#[derive(Debug, Clone)]
+pub struct RulesetStripper(pub Arc<[DynamicRuleset]>);
+
+impl RulesetStripper {
+    /// Clean the url with the first ruleset matching it, telling which parameters were removed
+    pub fn strip(&self, url: Url) -> Option<CleanedUrl> {
+        let ruleset = self.0.iter().find(|ruleset| ruleset.matches(&url))?;
+        ruleset.clean(url)
+    }
+}
+
+impl UrlTransform for RulesetStripper {
+    fn apply(&self, url: Url) -> Option<Url> {
+        self.strip(url).map(|cleaned| cleaned.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::SiStripper;
+
+    fn ruleset(name: &str, domains: &[&str], params: &[&str]) -> DynamicRuleset {
+        DynamicRuleset {
+            name: name.to_owned(),
+            domains: domains.iter().map(|&domain| domain.to_owned()).collect(),
+            params: params.iter().map(|&param| param.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn default_rulesets_clean_like_the_built_in_ones() -> anyhow::Result<()> {
+        let stripper = SharedRulesets::default().stripper();
+
+        for url in [
+            "https://youtu.be/FiwMTquj-rQ?si=KuczOyCr1s5_Ou0r&t=173",
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123",
+            "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+            "https://example.org/meow?si=23",
+        ] {
+            let url = Url::parse(url)?;
+            assert_eq!(stripper.apply(url.clone()), SiStripper.apply(url));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn swapped_rulesets_are_used_for_new_strippers() -> anyhow::Result<()> {
+        let rulesets = SharedRulesets::default();
+        let old_stripper = rulesets.stripper();
+
+        rulesets.set(vec![ruleset("example", &["example.org"], &["ref"])]);
+        let url = Url::parse("https://example.org/meow?ref=abc&page=2")?;
+
+        assert_eq!(old_stripper.apply(url.clone()), None);
+        assert_eq!(
+            rulesets.stripper().apply(url),
+            Some(Url::parse("https://example.org/meow?page=2")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validating_rulesets() {
+        assert_eq!(validate_rulesets(&[]), Err(RulesetsError::Empty));
+        assert_eq!(
+            validate_rulesets(&[ruleset("youtube", &["youtu.be"], &["si", "pp"])]),
+            Ok(())
+        );
+
+        for invalid in [
+            ruleset(" ", &["youtu.be"], &["si"]),
+            ruleset("youtube", &[], &["si"]),
+            ruleset("youtube", &["not a domain"], &["si"]),
+            ruleset("youtube", &["youtu.be"], &[]),
+            ruleset("youtube", &["youtu.be"], &[""]),
+            ruleset("youtube", &["youtu.be"], &["si=abc"]),
+        ] {
+            assert!(
+                validate_rulesets(std::slice::from_ref(&invalid)).is_err(),
+                "{invalid:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fmt::Display,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use tokio::task::JoinHandle;
+
+/// Keeps count of running handlers and background tasks to report them on shutdown
+#[derive(Debug, Clone, Default)]
+pub struct TaskAccounting {
+    handlers: Arc<AtomicUsize>,
+    background: Arc<AtomicUsize>,
+}
+
+/// Counts a task as running until the last clone of the guard is dropped
+#[derive(Debug, Clone)]
+pub struct TaskGuard {
+    // only held for its `Drop`
+    _inner: Arc<GuardInner>,
+}
+
+#[derive(Debug)]
+struct GuardInner(Arc<AtomicUsize>);
+
+impl Drop for GuardInner {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl TaskGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::AcqRel);
+        Self {
+            _inner: Arc::new(GuardInner(counter.clone())),
+        }
+    }
+}
+
+impl TaskAccounting {
+    /// Count an update handler as running while the guard is alive
+    pub fn track_handler(&self) -> TaskGuard {
+        TaskGuard::new(&self.handlers)
+    }
+
+    /// Spawn a background task, counted until it finishes or is aborted
+    pub fn spawn_background<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let guard = TaskGuard::new(&self.background);
+
+        tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        })
+    }
+
+    pub fn summary(&self, graceful: bool) -> ShutdownSummary {
+        ShutdownSummary {
+            in_flight_handlers: self.handlers.load(Ordering::Acquire),
+            background_tasks: self.background.load(Ordering::Acquire),
+            graceful,
+        }
+    }
+}
+
+/// What was still running when the bot shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    pub in_flight_handlers: usize,
+    pub background_tasks: usize,
+    pub graceful: bool,
+}
+
+impl Display for ShutdownSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} shutdown with {} handler(s) in flight and {} background task(s) running",
+            if self.graceful { "graceful" } else { "forced" },
+            self.in_flight_handlers,
+            self.background_tasks
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_counts_live_handlers() {
+        let tasks = TaskAccounting::default();
+        let first = tasks.track_handler();
+        let second = tasks.track_handler();
+        let second_clone = second.clone();
+
+        assert_eq!(tasks.summary(true).in_flight_handlers, 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(tasks.summary(true).in_flight_handlers, 1);
+
+        drop(second_clone);
+        assert_eq!(tasks.summary(true).in_flight_handlers, 0);
+    }
+
+    #[tokio::test]
+    async fn summary_counts_background_tasks() {
+        let tasks = TaskAccounting::default();
+        let pending = tasks.spawn_background(std::future::pending::<()>());
+        let finished = tasks.spawn_background(async {});
+        finished.await.unwrap();
+
+        assert_eq!(
+            tasks.summary(false),
+            ShutdownSummary {
+                in_flight_handlers: 0,
+                background_tasks: 1,
+                graceful: false,
+            }
+        );
+
+        pending.abort();
+        let _ = pending.await;
+        assert_eq!(tasks.summary(true).background_tasks, 0);
+    }
+
+    #[test]
+    fn summary_display() {
+        let summary = ShutdownSummary {
+            in_flight_handlers: 3,
+            background_tasks: 2,
+            graceful: true,
+        };
+
+        assert_eq!(
+            summary.to_string(),
+            "graceful shutdown with 3 handler(s) in flight and 2 background task(s) running"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+/// Parse a YouTube `t` parameter into seconds
+///
+/// Accepts plain seconds (`173`, `173s`) and the `2m53s`/`1h2m3s` form
+pub fn parse_timestamp(t: &str) -> Option<u64> {
+    let t = t.trim();
+    if t.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = t.parse() {
+        return Some(secs);
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    let mut last_unit = None;
+
+    for c in t.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c {
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+
+        // units have to go in order and each needs a number before it
+        if number.is_empty() || last_unit.is_some_and(|last| last <= multiplier) {
+            return None;
+        }
+
+        // absurdly long timestamps are invalid rather than wrapping around
+        let secs = number.parse::<u64>().ok()?.checked_mul(multiplier)?;
+        total = total.checked_add(secs)?;
+        number.clear();
+        last_unit = Some(multiplier);
+    }
+
+    number.is_empty().then_some(total)
+}
+
+/// Format seconds the way `youtu.be` links do, e.g. `173`
+pub fn format_seconds(secs: u64) -> String {
+    secs.to_string()
+}
+
+/// Format seconds the way `watch` links sometimes do, e.g. `2m53s`
+pub fn format_hms(secs: u64) -> String {
+    let hours = secs / (60 * 60);
+    let minutes = secs % (60 * 60) / 60;
+    let seconds = secs % 60;
+
+    let mut formatted = String::new();
+    if hours > 0 {
+        formatted.push_str(&format!("{hours}h"));
+    }
+    if minutes > 0 {
+        formatted.push_str(&format!("{minutes}m"));
+    }
+    if seconds > 0 || formatted.is_empty() {
+        formatted.push_str(&format!("{seconds}s"));
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_to_hms() {
+        assert_eq!(format_hms(173), "2m53s");
+        assert_eq!(format_hms(3723), "1h2m3s");
+        assert_eq!(format_hms(3600), "1h");
+        assert_eq!(format_hms(45), "45s");
+        assert_eq!(format_hms(0), "0s");
+    }
+
+    #[test]
+    fn hms_to_seconds() {
+        assert_eq!(parse_timestamp("2m53s"), Some(173));
+        assert_eq!(parse_timestamp("1h2m3s"), Some(3723));
+        assert_eq!(parse_timestamp("1h"), Some(3600));
+        assert_eq!(parse_timestamp("173"), Some(173));
+        assert_eq!(parse_timestamp("173s"), Some(173));
+        assert_eq!(format_seconds(parse_timestamp("2m53s").unwrap()), "173");
+    }
+
+    #[test]
+    fn invalid_timestamps() {
+        for t in ["", "abc", "2x", "m5s", "5s2m", "2m5", "-5"] {
+            assert_eq!(parse_timestamp(t), None, "{t}");
+        }
+    }
+
+    #[test]
+    fn overflowing_timestamps_are_invalid() {
+        assert_eq!(parse_timestamp("6000000000000000000h"), None);
+        assert_eq!(parse_timestamp("1h18446744073709551615s"), None);
+        assert_eq!(parse_timestamp("18446744073709551615s"), Some(u64::MAX));
+    }
+
+    #[test]
+    fn round_trips() {
+        for secs in [0, 1, 59, 60, 61, 173, 3599, 3600, 3723, 86400] {
+            assert_eq!(parse_timestamp(&format_hms(secs)), Some(secs));
+            assert_eq!(parse_timestamp(&format_seconds(secs)), Some(secs));
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::clock::SharedClock;
+
+/// Video titles fetched from oEmbed, keyed by video id
+///
+/// Entries expire after the TTL, and the oldest ones are evicted once the capacity is reached.
+/// Clones share the same cache, so it can be handed to every handler
+#[derive(Debug, Clone)]
+pub struct TitleCache {
+    inner: Arc<Mutex<CacheInner>>,
+    capacity: usize,
+    ttl: Duration,
+    clock: SharedClock,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<String, CachedTitle>,
+    /// Insertion order of the video ids, oldest first
+    order: VecDeque<String>,
+}
+
+#[derive(Debug)]
+struct CachedTitle {
+    title: String,
+    fetched_at: Instant,
+}
+
+impl TitleCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self::with_clock(capacity, ttl, SharedClock::default())
+    }
+
+    pub fn with_clock(capacity: usize, ttl: Duration, clock: SharedClock) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity,
+            ttl,
+            clock,
+        }
+    }
+
+    /// The cached title of the video, if it hasn't expired yet
+    pub fn get(&self, video_id: &str) -> Option<String> {
+        self.get_at(video_id, self.clock.now())
+    }
+
+    pub fn insert(&self, video_id: &str, title: String) {
+        self.insert_at(video_id, title, self.clock.now());
+    }
+
+    /// The cached title of the video, or the one returned by `fetch`, which is then cached
+    ///
+    /// Fetching errors are returned as is and nothing is cached
+    pub async fn get_or_fetch<F, E>(&self, video_id: &str, fetch: F) -> Result<String, E>
+    where
+        F: AsyncFnOnce(&str) -> Result<String, E>,
+    {
+        if let Some(title) = self.get(video_id) {
+            debug!(video_id, "title cache hit");
+            return Ok(title);
+        }
+
+        debug!(video_id, "title cache miss, fetching");
+        // the lock is not held while fetching, concurrent misses may fetch the same title twice
+        let title = fetch(video_id).await?;
+        self.insert(video_id, title.clone());
+
+        Ok(title)
+    }
+
+    fn get_at(&self, video_id: &str, now: Instant) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        let cached = inner.entries.get(video_id)?;
+
+        (now.duration_since(cached.fetched_at) < self.ttl).then(|| cached.title.clone())
+    }
+
+    fn insert_at(&self, video_id: &str, title: String, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        let CacheInner { entries, order } = &mut *inner;
+
+        if entries.contains_key(video_id) {
+            order.retain(|id| id != video_id);
+        }
+
+        entries.insert(
+            video_id.to_owned(),
+            CachedTitle {
+                title,
+                fetched_at: now,
+            },
+        );
+        order.push_back(video_id.to_owned());
+
+        // dropping the expired entries first, then the oldest ones while over capacity
+        order.retain(|id| {
+            let expired = entries
+                .get(id)
+                .is_none_or(|cached| now.duration_since(cached.fetched_at) >= self.ttl);
+
+            if expired {
+                entries.remove(id);
+            }
+
+            !expired
+        });
+
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn cache_hit_and_miss() {
+        let cache = TitleCache::new(10, TTL);
+
+        assert_eq!(cache.get("3foYyPDp0Ho"), None);
+
+        cache.insert("3foYyPDp0Ho", "A video".to_owned());
+        assert_eq!(cache.get("3foYyPDp0Ho").as_deref(), Some("A video"));
+        assert_eq!(cache.get("0FwBHrVuMJc"), None);
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = TitleCache::new(10, TTL);
+        let now = Instant::now();
+
+        cache.insert_at("3foYyPDp0Ho", "A video".to_owned(), now);
+
+        assert!(cache.get_at("3foYyPDp0Ho", now + TTL / 2).is_some());
+        assert!(cache.get_at("3foYyPDp0Ho", now + TTL).is_none());
+
+        // expired entries are evicted on the next insert
+        cache.insert_at("0FwBHrVuMJc", "Another video".to_owned(), now + TTL);
+        assert!(
+            !cache
+                .inner
+                .lock()
+                .unwrap()
+                .entries
+                .contains_key("3foYyPDp0Ho")
+        );
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted_over_capacity() {
+        let cache = TitleCache::new(2, TTL);
+
+        cache.insert("first", "1".to_owned());
+        cache.insert("second", "2".to_owned());
+        // refreshing an entry makes it the newest one
+        cache.insert("first", "1".to_owned());
+        cache.insert("third", "3".to_owned());
+
+        assert_eq!(cache.get("second"), None);
+        assert!(cache.get("first").is_some());
+        assert!(cache.get("third").is_some());
+    }
+
+    #[tokio::test]
+    async fn cache_hit_avoids_fetching() -> anyhow::Result<()> {
+        let cache = TitleCache::new(10, TTL);
+        let fetches = AtomicUsize::new(0);
+        let fetch = async |_: &str| -> anyhow::Result<String> {
+            fetches.fetch_add(1, Ordering::Relaxed);
+            Ok("A video".to_owned())
+        };
+
+        assert_eq!(cache.get_or_fetch("3foYyPDp0Ho", &fetch).await?, "A video");
+        assert_eq!(cache.get_or_fetch("3foYyPDp0Ho", &fetch).await?, "A video");
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // a clone shares the entries
+        assert_eq!(
+            cache.clone().get_or_fetch("3foYyPDp0Ho", &fetch).await?,
+            "A video"
+        );
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fake_clock_drives_expiry() -> anyhow::Result<()> {
+        let clock = Arc::new(FakeClock::default());
+        let cache = TitleCache::with_clock(10, TTL, SharedClock::new(clock.clone()));
+        let fetches = AtomicUsize::new(0);
+        let fetch = async |_: &str| -> anyhow::Result<String> {
+            fetches.fetch_add(1, Ordering::Relaxed);
+            Ok("A video".to_owned())
+        };
+
+        cache.get_or_fetch("3foYyPDp0Ho", &fetch).await?;
+
+        clock.advance(TTL - Duration::from_secs(1));
+        assert!(cache.get("3foYyPDp0Ho").is_some());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get("3foYyPDp0Ho"), None);
+
+        cache.get_or_fetch("3foYyPDp0Ho", &fetch).await?;
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_not_cached() {
+        let cache = TitleCache::new(10, TTL);
+
+        let result = cache
+            .get_or_fetch("3foYyPDp0Ho", async |_: &str| -> anyhow::Result<String> {
+                Err(anyhow::anyhow!("offline"))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(cache.get("3foYyPDp0Ho"), None);
+    }
+}
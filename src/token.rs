@@ -1,14 +1,38 @@
-use std::env;
+use std::{
+    env, fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
 const TOKEN_KEY: &str = "TELEGRAM_BOT_TOKEN";
+/// A file containing the token, e.g. a Docker secret or a systemd credential
+const TOKEN_FILE_KEY: &str = "TELEGRAM_BOT_TOKEN_FILE";
+const DOTENV_PATH_KEY: &str = "DOTENV_PATH";
 
 #[derive(Debug, Error)]
 pub enum LoadTokenError {
     #[error("Failed to parse the .env file")]
     DotEnv(dotenvy::Error),
+    #[error("The .env file at {0} does not exist")]
+    DotEnvPathNotFound(PathBuf),
     #[error("Failed to find the bot token in environment variables or the .env file")]
     NotFound,
+    #[error("Failed to read the bot token from the standard input")]
+    Stdin(#[source] io::Error),
+    #[error("The standard input did not contain a bot token")]
+    EmptyStdin,
+    #[error("Failed to read the bot token from {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("The file {0} did not contain a bot token")]
+    EmptyFile(PathBuf),
+    /// The reason doesn't include the token, so it can be logged
+    #[error("The bot token is malformed: {0}, expected `<bot id>:<secret>`")]
+    Malformed(&'static str),
 }
 
 impl From<dotenvy::Error> for LoadTokenError {
@@ -21,17 +45,267 @@ impl From<dotenvy::Error> for LoadTokenError {
     }
 }
 
+/// Load the bot token from the environment, the file at `TELEGRAM_BOT_TOKEN_FILE`
+/// or the .env file, in that order
+///
+/// If `DOTENV_PATH` is set, the .env file is loaded from that path,
+/// otherwise it is searched for starting from the current directory
 pub fn load_token() -> Result<String, LoadTokenError> {
     let maybe_token = env::vars().find_map(|(key, value)| (key == TOKEN_KEY).then_some(value));
     if let Some(token) = maybe_token {
-        return Ok(token);
+        return validated(token);
     }
 
-    let mut dotenv_file = dotenvy::dotenv_iter()?;
+    if let Some(path) = env::var_os(TOKEN_FILE_KEY) {
+        return load_token_from_file(path);
+    }
+
+    match env::var_os(DOTENV_PATH_KEY) {
+        Some(path) => load_token_from_dotenv_path(path),
+        None => find_token(dotenvy::dotenv_iter()?),
+    }
+}
+
+/// Check that the token has the shape of a bot token, `<digits>:<secret>`,
+/// catching placeholders before the first request fails with a confusing error
+///
+/// The lengths are not checked, as Telegram doesn't guarantee them
+pub fn validate_token(token: &str) -> Result<(), LoadTokenError> {
+    let Some((id, secret)) = token.split_once(':') else {
+        return Err(LoadTokenError::Malformed("no `:` separating the bot id"));
+    };
+
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(LoadTokenError::Malformed("the bot id is not a number"));
+    }
+
+    if secret.is_empty() {
+        return Err(LoadTokenError::Malformed("the secret is empty"));
+    }
+
+    if !secret
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(LoadTokenError::Malformed(
+            "the secret contains characters other than letters, digits, `-` and `_`",
+        ));
+    }
+
+    Ok(())
+}
+
+fn validated(token: String) -> Result<String, LoadTokenError> {
+    validate_token(&token)?;
+    Ok(token)
+}
+
+/// Load the bot token from the .env file at the specified path
+pub fn load_token_from_dotenv_path(path: impl AsRef<Path>) -> Result<String, LoadTokenError> {
+    let path = path.as_ref();
+    let dotenv_file = dotenvy::from_path_iter(path).map_err(|e| {
+        if e.not_found() {
+            LoadTokenError::DotEnvPathNotFound(path.to_owned())
+        } else {
+            e.into()
+        }
+    })?;
+
+    find_token(dotenv_file)
+}
+
+/// Load the bot token from the whole contents of the file, trimming the surrounding whitespace
+pub fn load_token_from_file(path: impl AsRef<Path>) -> Result<String, LoadTokenError> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path).map_err(|source| LoadTokenError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    let token = contents.trim();
+    if token.is_empty() {
+        return Err(LoadTokenError::EmptyFile(path.to_owned()));
+    }
+
+    validated(token.to_owned())
+}
+
+/// Load the bot token from the first line of the standard input
+///
+/// Keeps the token out of the process environment and off the disk
+pub fn load_token_from_stdin() -> Result<String, LoadTokenError> {
+    read_token(io::stdin().lock())
+}
+
+/// Read the token from the first line of the reader, trimming the surrounding whitespace
+fn read_token(mut reader: impl BufRead) -> Result<String, LoadTokenError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(LoadTokenError::Stdin)?;
+
+    let token = line.trim();
+    if token.is_empty() {
+        return Err(LoadTokenError::EmptyStdin);
+    }
+
+    validated(token.to_owned())
+}
+
+fn find_token(
+    mut dotenv_file: impl Iterator<Item = dotenvy::Result<(String, String)>>,
+) -> Result<String, LoadTokenError> {
     let maybe_token = dotenv_file.find_map(|kv_pair| match kv_pair {
         Err(e) => Some(Err(e.into())),
-        Ok((key, value)) => (key == TOKEN_KEY).then_some(Ok(value)),
+        Ok((key, value)) => (key == TOKEN_KEY).then(|| validated(value)),
     });
 
     maybe_token.unwrap_or(Err(LoadTokenError::NotFound))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("youtube_no_si_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn loading_from_explicit_dotenv_path() -> anyhow::Result<()> {
+        let path = temp_path("explicit.env");
+        fs::write(&path, "OTHER=value\nTELEGRAM_BOT_TOKEN=123456:abcdef\n")?;
+
+        let token = load_token_from_dotenv_path(&path);
+        fs::remove_file(&path)?;
+
+        assert_eq!(token?, "123456:abcdef");
+
+        Ok(())
+    }
+
+    #[test]
+    fn explicit_dotenv_path_without_token() -> anyhow::Result<()> {
+        let path = temp_path("no_token.env");
+        fs::write(&path, "OTHER=value\n")?;
+
+        let token = load_token_from_dotenv_path(&path);
+        fs::remove_file(&path)?;
+
+        assert!(matches!(token, Err(LoadTokenError::NotFound)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn loading_from_token_file() -> anyhow::Result<()> {
+        let path = temp_path("token_file");
+        fs::write(&path, "123456:abcdef\n")?;
+
+        let token = load_token_from_file(&path);
+        fs::remove_file(&path)?;
+
+        assert_eq!(token?, "123456:abcdef");
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_token_file() -> anyhow::Result<()> {
+        let path = temp_path("empty_token_file");
+        fs::write(&path, " \n")?;
+
+        let token = load_token_from_file(&path);
+        fs::remove_file(&path)?;
+
+        assert!(matches!(token, Err(LoadTokenError::EmptyFile(p)) if p == path));
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_token_file() {
+        let path = temp_path("missing_token_file");
+
+        assert!(matches!(
+            load_token_from_file(&path),
+            Err(LoadTokenError::Io { path: p, source }) if p == path && source.kind() == io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn reading_token_trims_whitespace() -> anyhow::Result<()> {
+        assert_eq!(read_token(&b"123456:abcdef\n"[..])?, "123456:abcdef");
+        assert_eq!(read_token(&b"  123456:abcdef \r\n"[..])?, "123456:abcdef");
+        // only the first line is read
+        assert_eq!(read_token(&b"123456:abcdef\nrest\n"[..])?, "123456:abcdef");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reading_empty_token() {
+        assert!(matches!(
+            read_token(&b""[..]),
+            Err(LoadTokenError::EmptyStdin)
+        ));
+        assert!(matches!(
+            read_token(&b"  \n"[..]),
+            Err(LoadTokenError::EmptyStdin)
+        ));
+    }
+
+    #[test]
+    fn validating_tokens() {
+        for valid in [
+            "123456:abcdef",
+            "110201543:AAHdqTcvCH1vGWJxfSeofSAs0K5PALDsaw",
+            "1:A",
+            "7123456789:AAF-x_y-Z0123456789012345678901234567890",
+        ] {
+            assert!(validate_token(valid).is_ok(), "{valid}");
+        }
+
+        for invalid in [
+            "",
+            "your_token_here",
+            "<TELEGRAM_BOT_TOKEN>",
+            ":abcdef",
+            "bot123456:abcdef",
+            "123456:",
+            "123456:abc def",
+            "123456:abc:def",
+            "\"123456:abcdef\"",
+        ] {
+            assert!(
+                matches!(validate_token(invalid), Err(LoadTokenError::Malformed(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected_when_loading() -> anyhow::Result<()> {
+        let path = temp_path("placeholder.env");
+        fs::write(&path, "TELEGRAM_BOT_TOKEN=changeme\n")?;
+
+        let token = load_token_from_dotenv_path(&path);
+        fs::remove_file(&path)?;
+
+        assert!(matches!(token, Err(LoadTokenError::Malformed(_))));
+        assert!(matches!(
+            read_token(&b"changeme\n"[..]),
+            Err(LoadTokenError::Malformed(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_explicit_dotenv_path() {
+        let path = temp_path("missing.env");
+
+        assert!(matches!(
+            load_token_from_dotenv_path(&path),
+            Err(LoadTokenError::DotEnvPathNotFound(p)) if p == path
+        ));
+    }
+}
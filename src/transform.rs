@@ -0,0 +1,217 @@
+//! Composable per-url transformations, so library users can add their own cleaning steps
+
+use std::fmt::Debug;
+
+use url::Url;
+
+use crate::remove_si::{
+    clean_url, expand_short_link, rewrite_to_frontend, unwrap_share_link, url_belongs_to_youtube,
+};
+
+/// A step applied to every link
+///
+/// Returning None drops the link, e.g. because it has nothing to clean,
+/// transforms that don't apply to a link should return it unchanged
+pub trait UrlTransform {
+    fn apply(&self, url: Url) -> Option<Url>;
+}
+
+impl<F> UrlTransform for F
+where
+    F: Fn(Url) -> Option<Url>,
+{
+    fn apply(&self, url: Url) -> Option<Url> {
+        self(url)
+    }
+}
+
+/// Removes the tracking parameters with [`clean_url`], dropping links without any
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SiStripper;
+
+impl UrlTransform for SiStripper {
+    fn apply(&self, url: Url) -> Option<Url> {
+        clean_url(url)
+    }
+}
+
+/// Expands short YouTube links with [`expand_short_link`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShortLinkExpander;
+
+impl UrlTransform for ShortLinkExpander {
+    fn apply(&self, url: Url) -> Option<Url> {
+        Some(expand_short_link(url))
+    }
+}
+
+/// Replaces Telegram share links with the YouTube links they carry, see [`unwrap_share_link`]
+///
+/// Goes before the [`SiStripper`], so the embedded link gets cleaned
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShareLinkUnwrapper;
+
+impl UrlTransform for ShareLinkUnwrapper {
+    fn apply(&self, url: Url) -> Option<Url> {
+        Some(unwrap_share_link(&url).unwrap_or(url))
+    }
+}
+
+/// Rewrites YouTube links to a privacy frontend host with [`rewrite_to_frontend`]
+///
+/// Other links, and all links if the host is invalid, are left unchanged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontendRewriter {
+    pub host: String,
+}
+
+impl UrlTransform for FrontendRewriter {
+    fn apply(&self, url: Url) -> Option<Url> {
+        if !url_belongs_to_youtube(&url) {
+            return Some(url);
+        }
+
+        Some(rewrite_to_frontend(url.clone(), &self.host).unwrap_or(url))
+    }
+}
+
+/// Transforms applied in order, each to the result of the previous one
+///
+/// Only has the [`SiStripper`] by default
+pub struct TransformChain<'a> {
+    transforms: Vec<Box<dyn UrlTransform + 'a>>,
+}
+
+impl<'a> TransformChain<'a> {
+    /// A chain without any transforms, which returns every link unchanged
+    pub fn empty() -> Self {
+        Self {
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Add a transform to the end of the chain
+    pub fn then(mut self, transform: impl UrlTransform + 'a) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+}
+
+impl Default for TransformChain<'_> {
+    fn default() -> Self {
+        Self::empty().then(SiStripper)
+    }
+}
+
+impl Debug for TransformChain<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransformChain")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Applies the transforms in order, dropping the link as soon as one of them drops it
+impl UrlTransform for TransformChain<'_> {
+    fn apply(&self, url: Url) -> Option<Url> {
+        self.transforms
+            .iter()
+            .try_fold(url, |url, transform| transform.apply(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_chain_strips_tracking() -> anyhow::Result<()> {
+        let chain = TransformChain::default();
+
+        assert_eq!(
+            chain.apply(Url::parse(
+                "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce"
+            )?),
+            Some(Url::parse("https://youtu.be/0FwBHrVuMJc")?)
+        );
+        assert_eq!(
+            chain.apply(Url::parse("https://youtu.be/0FwBHrVuMJc")?),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stripping_then_rewriting_to_frontend() -> anyhow::Result<()> {
+        let chain = TransformChain::default().then(FrontendRewriter {
+            host: "yewtu.be".to_owned(),
+        });
+
+        assert_eq!(
+            chain.apply(Url::parse(
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=some_fake_si_i_made_up&t=10"
+            )?),
+            Some(Url::parse("https://yewtu.be/watch?v=3foYyPDp0Ho&t=10")?)
+        );
+        // only YouTube links are rewritten
+        assert_eq!(
+            chain.apply(Url::parse(
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123"
+            )?),
+            Some(Url::parse(
+                "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+            )?)
+        );
+        // links dropped by the stripper never reach the rewriter
+        assert_eq!(
+            chain.apply(Url::parse("https://youtu.be/0FwBHrVuMJc")?),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn share_links_are_unwrapped_before_stripping() -> anyhow::Result<()> {
+        let chain = TransformChain::empty()
+            .then(ShareLinkUnwrapper)
+            .then(SiStripper);
+
+        assert_eq!(
+            chain.apply(Url::parse(
+                "https://t.me/share/url?url=https%3A%2F%2Fyoutu.be%2FFiwMTquj-rQ%3Fsi%3Dabc"
+            )?),
+            Some(Url::parse("https://youtu.be/FiwMTquj-rQ")?)
+        );
+        assert_eq!(chain.apply(Url::parse("https://t.me/durov")?), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn closures_are_transforms() -> anyhow::Result<()> {
+        let chain = TransformChain::empty()
+            .then(|mut url: Url| {
+                url.set_fragment(None);
+                Some(url)
+            })
+            .then(|url: Url| (url.host_str() != Some("example.org")).then_some(url));
+
+        assert_eq!(
+            chain.apply(Url::parse("https://youtu.be/0FwBHrVuMJc#comments")?),
+            Some(Url::parse("https://youtu.be/0FwBHrVuMJc")?)
+        );
+        assert_eq!(chain.apply(Url::parse("https://example.org/")?), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+use url::Url;
+
+use crate::remove_si::is_short_link;
+
+/// The kind of content a YouTube URL points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UrlKind {
+    Video,
+    Short,
+    Live,
+    Embed,
+    Playlist,
+    Channel,
+    Other,
+}
+
+impl UrlKind {
+    pub const ALL: &[UrlKind] = &[
+        Self::Video,
+        Self::Short,
+        Self::Live,
+        Self::Embed,
+        Self::Playlist,
+        Self::Channel,
+        Self::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Video => "video",
+            Self::Short => "short",
+            Self::Live => "live",
+            Self::Embed => "embed",
+            Self::Playlist => "playlist",
+            Self::Channel => "channel",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl Display for UrlKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Unknown URL kind: {0}")]
+pub struct ParseUrlKindError(String);
+
+impl FromStr for UrlKind {
+    type Err = ParseUrlKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s.trim().to_ascii_lowercase().as_str() {
+            "video" => Self::Video,
+            "short" | "shorts" => Self::Short,
+            "live" => Self::Live,
+            "embed" => Self::Embed,
+            "playlist" => Self::Playlist,
+            "channel" => Self::Channel,
+            "other" => Self::Other,
+            _ => return Err(ParseUrlKindError(s.to_owned())),
+        };
+
+        Ok(kind)
+    }
+}
+
+/// Classify a YouTube URL by the kind of content it points to
+///
+/// Assumes the URL already belongs to YouTube, only the path and query are inspected
+pub fn youtube_url_kind(url: &Url) -> UrlKind {
+    if is_short_link(url) {
+        return UrlKind::Video;
+    }
+
+    let mut segments = url.path_segments().into_iter().flatten();
+    let first = segments.next().unwrap_or_default();
+    let has_param = |name: &str| url.query_pairs().any(|(key, _)| key == name);
+
+    match first {
+        "watch" if !has_param("v") && has_param("list") => UrlKind::Playlist,
+        "watch" => UrlKind::Video,
+        "shorts" => UrlKind::Short,
+        "live" => UrlKind::Live,
+        // `/v/<id>` is the legacy form of embed links
+        "embed" | "v" => UrlKind::Embed,
+        "playlist" => UrlKind::Playlist,
+        "channel" | "c" | "user" => UrlKind::Channel,
+        handle if handle.starts_with('@') => UrlKind::Channel,
+        _ => UrlKind::Other,
+    }
+}
+
+/// Extract the id of the video a YouTube URL points to
+///
+/// Assumes the URL already belongs to YouTube, returns None for links to anything but a single video
+pub fn youtube_video_id(url: &Url) -> Option<String> {
+    let mut segments = url.path_segments().into_iter().flatten();
+    let first = segments.next().unwrap_or_default();
+
+    let id = match youtube_url_kind(url) {
+        UrlKind::Video if first == "watch" => url
+            .query_pairs()
+            .find_map(|(key, value)| (key == "v").then(|| value.into_owned()))?,
+        UrlKind::Video => first.to_owned(),
+        UrlKind::Short | UrlKind::Live | UrlKind::Embed => segments.next()?.to_owned(),
+        _ => return None,
+    };
+
+    (!id.is_empty()).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_youtube_urls() -> anyhow::Result<()> {
+        let cases = [
+            ("https://youtu.be/0FwBHrVuMJc", UrlKind::Video),
+            (
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho",
+                UrlKind::Video,
+            ),
+            ("https://www.youtube.com/shorts/abc", UrlKind::Short),
+            ("https://www.youtube.com/live/abc", UrlKind::Live),
+            ("https://www.youtube.com/embed/abc", UrlKind::Embed),
+            ("https://www.youtube.com/v/abc", UrlKind::Embed),
+            (
+                "https://www.youtube.com/playlist?list=PL123",
+                UrlKind::Playlist,
+            ),
+            (
+                "https://www.youtube.com/watch?list=PL123",
+                UrlKind::Playlist,
+            ),
+            ("https://www.youtube.com/@SomeChannel", UrlKind::Channel),
+            ("https://www.youtube.com/channel/UC123", UrlKind::Channel),
+            ("https://www.youtube.com/feed/trending", UrlKind::Other),
+        ];
+
+        for (url, kind) in cases {
+            assert_eq!(youtube_url_kind(&Url::parse(url)?), kind, "{url}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn extracts_video_ids() -> anyhow::Result<()> {
+        let cases = [
+            ("https://youtu.be/0FwBHrVuMJc", Some("0FwBHrVuMJc")),
+            ("https://youtu.be/0FwBHrVuMJc?t=173", Some("0FwBHrVuMJc")),
+            (
+                "https://www.youtube.com/watch?v=3foYyPDp0Ho&t=10",
+                Some("3foYyPDp0Ho"),
+            ),
+            ("https://www.youtube.com/shorts/a-B_9c", Some("a-B_9c")),
+            ("https://www.youtube.com/live/abc", Some("abc")),
+            ("https://www.youtube.com/embed/abc", Some("abc")),
+            ("https://www.youtube.com/v/abc", Some("abc")),
+            ("https://youtu.be/", None),
+            ("https://www.youtube.com/shorts/", None),
+            ("https://www.youtube.com/watch?list=PL123", None),
+            ("https://www.youtube.com/@SomeChannel", None),
+        ];
+
+        for (url, id) in cases {
+            assert_eq!(youtube_video_id(&Url::parse(url)?).as_deref(), id, "{url}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn url_kind_round_trips_through_str() {
+        for &kind in UrlKind::ALL {
+            assert_eq!(kind.as_str().parse::<UrlKind>().ok(), Some(kind));
+        }
+
+        assert_eq!("Shorts".parse::<UrlKind>().ok(), Some(UrlKind::Short));
+        assert!("movie".parse::<UrlKind>().is_err());
+    }
+}
@@ -1,16 +1,18 @@
 use std::{any::Any, error::Error, fmt::Display};
 
+/// Displays an error followed by its sources, on one line so that the whole chain
+/// stays in the log event it's part of
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FullErrorDisplay<E>(pub E);
 
 impl<E: Error> Display for FullErrorDisplay<E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", self.0)?;
+        write!(f, "{}", self.0)?;
 
         let mut e: &dyn Error = &self.0;
         while let Some(src) = e.source() {
             e = src;
-            writeln!(f, "Caused by: {src}")?;
+            write!(f, ": {src}")?;
         }
 
         Ok(())
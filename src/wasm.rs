@@ -0,0 +1,29 @@
+//! The url cleaning exported to JavaScript, e.g. for browser extensions
+//!
+//! Build with
+//! `cargo rustc --lib --crate-type cdylib --target wasm32-unknown-unknown --no-default-features --features wasm`,
+//! then generate the bindings with `wasm-bindgen`
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::remove_si::clean_url_str;
+
+/// The link without tracking parameters, or `undefined` if there's nothing to clean
+#[wasm_bindgen]
+pub fn clean_url(input: &str) -> Option<String> {
+    clean_url_str(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exported_function_cleans_links() {
+        assert_eq!(
+            clean_url("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123").as_deref(),
+            Some("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC")
+        );
+        assert_eq!(clean_url("https://example.org/?si=abc123"), None);
+    }
+}
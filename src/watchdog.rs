@@ -0,0 +1,148 @@
+//! systemd readiness and watchdog notifications, see `sd_notify(3)`
+
+use std::{env, io, os::unix::net::UnixDatagram, time::Duration};
+
+use tracing::{debug, error, info, warn};
+
+use crate::{health::Health, utils::FullErrorDisplay};
+
+const NOTIFY_SOCKET_KEY: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC_KEY: &str = "WATCHDOG_USEC";
+
+const READY: &str = "READY=1";
+const KEEPALIVE: &str = "WATCHDOG=1";
+
+/// Sends state notifications to the service manager
+pub trait Notifier {
+    fn notify(&self, state: &str) -> io::Result<()>;
+}
+
+/// Notifies systemd through the datagram socket passed in `NOTIFY_SOCKET`
+#[derive(Debug, Clone)]
+pub struct SystemdNotifier {
+    socket_path: String,
+}
+
+impl SystemdNotifier {
+    /// None if the bot is not running under systemd with notifications enabled
+    pub fn from_env() -> Option<Self> {
+        let socket_path = env::var(NOTIFY_SOCKET_KEY).ok()?;
+        Some(Self { socket_path })
+    }
+}
+
+impl Notifier for SystemdNotifier {
+    fn notify(&self, state: &str) -> io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+
+        match self.socket_path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(abstract_name) => {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+                let addr = SocketAddr::from_abstract_name(abstract_name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            _ => {
+                socket.send_to(state.as_bytes(), &self.socket_path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How often to ping the watchdog, half of the `WATCHDOG_USEC` timeout as systemd recommends
+///
+/// None if the watchdog is not enabled for the service
+pub fn keepalive_interval() -> Option<Duration> {
+    let usec = env::var(WATCHDOG_USEC_KEY).ok()?.parse().ok()?;
+    keepalive_interval_for(usec)
+}
+
+fn keepalive_interval_for(watchdog_usec: u64) -> Option<Duration> {
+    (watchdog_usec > 0).then(|| Duration::from_micros(watchdog_usec) / 2)
+}
+
+/// Tell the service manager the bot has started
+pub fn notify_ready(notifier: &impl Notifier) {
+    info!("notifying systemd that the bot is ready");
+
+    if let Err(e) = notifier.notify(READY) {
+        warn!(error = %FullErrorDisplay(e), "failed to notify systemd");
+    }
+}
+
+/// Ping the watchdog if the dispatcher is healthy, returns whether it was pinged
+fn keepalive(notifier: &impl Notifier, health: &Health) -> bool {
+    if !health.is_healthy() {
+        debug!("dispatcher is not healthy, skipping the watchdog keepalive");
+        return false;
+    }
+
+    if let Err(e) = notifier.notify(KEEPALIVE) {
+        error!(error = %FullErrorDisplay(e), "failed to ping the watchdog");
+        return false;
+    }
+
+    true
+}
+
+/// Ping the watchdog every `interval` while the dispatcher is healthy, never returns
+pub async fn run_keepalive(notifier: impl Notifier, interval: Duration, health: Health) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        keepalive(&notifier, &health);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct RecordingNotifier(Mutex<Vec<String>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, state: &str) -> io::Result<()> {
+            self.0.lock().unwrap().push(state.to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keepalive_interval_is_half_the_timeout() {
+        assert_eq!(
+            keepalive_interval_for(30_000_000),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(keepalive_interval_for(0), None);
+    }
+
+    #[test]
+    fn keepalives_are_only_sent_while_healthy() {
+        let notifier = RecordingNotifier::default();
+        let health = Health::default();
+
+        assert!(keepalive(&notifier, &health));
+
+        health.set(false);
+        assert!(!keepalive(&notifier, &health));
+
+        health.set(true);
+        assert!(keepalive(&notifier, &health));
+
+        assert_eq!(*notifier.0.lock().unwrap(), [KEEPALIVE, KEEPALIVE]);
+    }
+
+    #[test]
+    fn ready_is_sent_on_startup() {
+        let notifier = RecordingNotifier::default();
+        notify_ready(&notifier);
+
+        assert_eq!(*notifier.0.lock().unwrap(), [READY]);
+    }
+}
@@ -0,0 +1,74 @@
+//! The `clean` subcommand of the binary, which works without a bot token
+#![cfg(feature = "bot")]
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_youtube_no_si_redux");
+
+fn clean(args: &[&str], stdin: &str) -> anyhow::Result<Output> {
+    let mut child = Command::new(BIN)
+        .args(args)
+        // the cleaning must not depend on the bot's configuration
+        .env_remove("TELEGRAM_BOT_TOKEN")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(stdin.as_bytes())?;
+
+    Ok(child.wait_with_output()?)
+}
+
+#[test]
+fn cleaning_a_url_argument() -> anyhow::Result<()> {
+    let output = clean(
+        &[
+            "--clean",
+            "https://youtu.be/0FwBHrVuMJc?si=drdl-LZXYJzZPIce",
+        ],
+        "",
+    )?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "https://youtu.be/0FwBHrVuMJc\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn cleaning_urls_from_stdin() -> anyhow::Result<()> {
+    let output = clean(
+        &["clean"],
+        "https://www.youtube.com/watch?v=3foYyPDp0Ho&si=xyz\n\nhttps://youtu.be/FiwMTquj-rQ?t=173\n",
+    )?;
+
+    assert!(output.status.success(), "{output:?}");
+    // links without tracking are printed unchanged
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "https://www.youtube.com/watch?v=3foYyPDp0Ho\nhttps://youtu.be/FiwMTquj-rQ?t=173\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_urls_fail() -> anyhow::Result<()> {
+    let output = clean(&["--clean", "not a url"], "")?;
+
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8(output.stderr)?.contains("invalid url `not a url`"));
+
+    Ok(())
+}